@@ -0,0 +1,4 @@
+DROP INDEX crates_search_vector_idx;
+
+ALTER TABLE crates
+    DROP COLUMN search_vector
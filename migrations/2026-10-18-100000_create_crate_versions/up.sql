@@ -0,0 +1,15 @@
+CREATE TABLE crate_versions
+(
+    id           SERIAL PRIMARY KEY,
+    crate_id     integer                 NOT NULL REFERENCES crates (id) ON DELETE CASCADE,
+    version      varchar(64)             NOT NULL,
+    changelog    text,
+    yanked       boolean DEFAULT FALSE   NOT NULL,
+    published_at TIMESTAMP DEFAULT NOW() NOT NULL,
+    UNIQUE (crate_id, version)
+);
+
+-- Start every existing crate's history with its current version
+INSERT INTO crate_versions (crate_id, version, published_at)
+SELECT id, version, created_at
+FROM crates
@@ -0,0 +1,14 @@
+CREATE TABLE crate_dependencies
+(
+    id            SERIAL PRIMARY KEY,
+    crate_id      integer                 NOT NULL REFERENCES crates (id) ON DELETE CASCADE,
+    dependency_id integer                 NOT NULL REFERENCES crates (id) ON DELETE CASCADE,
+    requirement   varchar(64)             NOT NULL,
+    kind          varchar(16)             NOT NULL DEFAULT 'normal'
+        CHECK (kind IN ('normal', 'dev', 'build')),
+    created_at    TIMESTAMP DEFAULT NOW() NOT NULL,
+    UNIQUE (crate_id, dependency_id, kind),
+    CHECK (crate_id <> dependency_id)
+);
+
+CREATE INDEX crate_dependencies_dependency_id_idx ON crate_dependencies (dependency_id)
@@ -0,0 +1 @@
+DROP INDEX crates_code_key
\ No newline at end of file
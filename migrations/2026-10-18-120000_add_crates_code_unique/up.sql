@@ -0,0 +1,20 @@
+-- Which of two crates whose codes differ only in case keeps the code is for an operator to decide
+DO
+$$
+    DECLARE
+        duplicates text;
+    BEGIN
+        SELECT string_agg(code, ', ' ORDER BY code)
+        INTO duplicates
+        FROM (SELECT lower(code) AS code
+              FROM crates
+              GROUP BY lower(code)
+              HAVING count(*) > 1) AS duplicated;
+
+        IF duplicates IS NOT NULL THEN
+            RAISE EXCEPTION 'Rename crates whose codes differ only in case first: %', duplicates;
+        END IF;
+    END
+$$;
+
+CREATE UNIQUE INDEX crates_code_key ON crates (lower(code))
\ No newline at end of file
@@ -0,0 +1 @@
+DROP INDEX rustaceans_email_key
\ No newline at end of file
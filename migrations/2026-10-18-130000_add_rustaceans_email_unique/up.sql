@@ -0,0 +1,20 @@
+-- Which of two rustaceans whose emails differ only in case keeps the address is for an operator to decide
+DO
+$$
+    DECLARE
+        duplicates text;
+    BEGIN
+        SELECT string_agg(email, ', ' ORDER BY email)
+        INTO duplicates
+        FROM (SELECT lower(email) AS email
+              FROM rustaceans
+              GROUP BY lower(email)
+              HAVING count(*) > 1) AS duplicated;
+
+        IF duplicates IS NOT NULL THEN
+            RAISE EXCEPTION 'Change rustacean emails that differ only in case first: %', duplicates;
+        END IF;
+    END
+$$;
+
+CREATE UNIQUE INDEX rustaceans_email_key ON rustaceans (lower(email))
\ No newline at end of file
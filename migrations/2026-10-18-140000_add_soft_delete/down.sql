@@ -0,0 +1,11 @@
+DROP INDEX crates_code_key;
+CREATE UNIQUE INDEX crates_code_key ON crates (lower(code));
+
+DROP INDEX rustaceans_email_key;
+CREATE UNIQUE INDEX rustaceans_email_key ON rustaceans (lower(email));
+
+ALTER TABLE crates
+    DROP COLUMN deleted_at;
+
+ALTER TABLE rustaceans
+    DROP COLUMN deleted_at
\ No newline at end of file
@@ -0,0 +1,12 @@
+ALTER TABLE rustaceans
+    ADD COLUMN deleted_at TIMESTAMP;
+
+ALTER TABLE crates
+    ADD COLUMN deleted_at TIMESTAMP;
+
+-- Soft-deleted rows should not keep their email or code taken
+DROP INDEX rustaceans_email_key;
+CREATE UNIQUE INDEX rustaceans_email_key ON rustaceans (lower(email)) WHERE deleted_at IS NULL;
+
+DROP INDEX crates_code_key;
+CREATE UNIQUE INDEX crates_code_key ON crates (lower(code)) WHERE deleted_at IS NULL
\ No newline at end of file
@@ -0,0 +1,13 @@
+CREATE TABLE audit_log
+(
+    id          SERIAL PRIMARY KEY,
+    user_id     integer                 REFERENCES users (id) ON DELETE SET NULL,
+    action      varchar(16)             NOT NULL,
+    resource    varchar(64)             NOT NULL,
+    resource_id integer                 NOT NULL,
+    before      jsonb,
+    after       jsonb,
+    created_at  TIMESTAMP DEFAULT NOW() NOT NULL
+);
+
+CREATE INDEX audit_log_resource_idx ON audit_log (resource, resource_id)
\ No newline at end of file
@@ -0,0 +1,8 @@
+DROP TRIGGER set_updated_at ON crates;
+DROP TRIGGER set_updated_at ON rustaceans;
+
+ALTER TABLE crates
+    DROP COLUMN updated_at;
+
+ALTER TABLE rustaceans
+    DROP COLUMN updated_at
\ No newline at end of file
@@ -0,0 +1,2 @@
+ALTER TABLE user_roles
+    DROP CONSTRAINT user_roles_user_id_role_id_key
\ No newline at end of file
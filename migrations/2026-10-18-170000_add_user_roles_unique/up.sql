@@ -0,0 +1,10 @@
+-- Keep the oldest of any duplicated assignments
+DELETE
+FROM user_roles duplicate
+    USING user_roles original
+WHERE duplicate.user_id = original.user_id
+  AND duplicate.role_id = original.role_id
+  AND duplicate.id > original.id;
+
+ALTER TABLE user_roles
+    ADD CONSTRAINT user_roles_user_id_role_id_key UNIQUE (user_id, role_id)
\ No newline at end of file
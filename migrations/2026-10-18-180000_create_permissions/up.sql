@@ -0,0 +1,42 @@
+CREATE TABLE permissions
+(
+    id         SERIAL PRIMARY KEY,
+    code       varchar(64)             NOT NULL UNIQUE,
+    name       varchar(128)            NOT NULL,
+    created_at TIMESTAMP DEFAULT NOW() NOT NULL
+);
+
+CREATE TABLE role_permissions
+(
+    id            SERIAL PRIMARY KEY,
+    role_id       integer NOT NULL REFERENCES roles (id) ON DELETE CASCADE,
+    permission_id integer NOT NULL REFERENCES permissions (id) ON DELETE CASCADE,
+    UNIQUE (role_id, permission_id)
+);
+
+-- Roles used to be created on first use only
+INSERT INTO roles (code, name)
+VALUES ('admin', 'admin'),
+       ('editor', 'editor'),
+       ('viewer', 'viewer')
+ON CONFLICT (code) DO NOTHING;
+
+INSERT INTO permissions (code, name)
+VALUES ('rustaceans:write', 'Create and update rustaceans'),
+       ('rustaceans:delete', 'Delete rustaceans'),
+       ('rustaceans:cascade', 'Delete rustaceans along with their crates'),
+       ('rustaceans:restore', 'Restore deleted rustaceans'),
+       ('crates:write', 'Create and update crates, their versions and dependencies'),
+       ('crates:delete', 'Delete crates'),
+       ('crates:restore', 'Restore deleted crates'),
+       ('audit:read', 'Read the audit log'),
+       ('users:admin', 'Manage users and their roles');
+
+-- Admins may do anything, editors may change the catalogue and viewers only read it
+INSERT INTO role_permissions (role_id, permission_id)
+SELECT roles.id, permissions.id
+FROM roles,
+     permissions
+WHERE roles.code = 'admin'
+   OR (roles.code = 'editor' AND permissions.code IN
+                                 ('rustaceans:write', 'rustaceans:delete', 'crates:write', 'crates:delete'))
\ No newline at end of file
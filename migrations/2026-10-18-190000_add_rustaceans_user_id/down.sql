@@ -0,0 +1,6 @@
+DELETE
+FROM permissions
+WHERE code = 'crates:any';
+
+ALTER TABLE rustaceans
+    DROP COLUMN user_id
\ No newline at end of file
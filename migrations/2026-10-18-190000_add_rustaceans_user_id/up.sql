@@ -0,0 +1,14 @@
+ALTER TABLE rustaceans
+    ADD COLUMN user_id integer REFERENCES users (id) ON DELETE SET NULL;
+
+CREATE INDEX rustaceans_user_id_idx ON rustaceans (user_id);
+
+INSERT INTO permissions (code, name)
+VALUES ('crates:any', 'Change crates of any owner');
+
+INSERT INTO role_permissions (role_id, permission_id)
+SELECT roles.id, permissions.id
+FROM roles,
+     permissions
+WHERE roles.code = 'admin'
+  AND permissions.code = 'crates:any'
\ No newline at end of file
@@ -0,0 +1,14 @@
+CREATE TABLE api_keys
+(
+    id           SERIAL PRIMARY KEY,
+    user_id      integer                 NOT NULL REFERENCES users (id) ON DELETE CASCADE,
+    name         varchar(128)            NOT NULL,
+    prefix       varchar(16)             NOT NULL UNIQUE,
+    key_hash     varchar(128)            NOT NULL,
+    scopes       text[]                  NOT NULL DEFAULT '{}',
+    expires_at   TIMESTAMP,
+    last_used_at TIMESTAMP,
+    created_at   TIMESTAMP DEFAULT NOW() NOT NULL
+);
+
+CREATE INDEX api_keys_user_id_idx ON api_keys (user_id)
\ No newline at end of file
@@ -14,18 +14,57 @@ pub struct Credentials {
     pub password: String,
 }
 
+#[derive(serde::Deserialize)]
+pub struct RefreshRequest {
+    pub refresh_token: String,
+}
+
+#[derive(serde::Deserialize)]
+pub struct PasswordChange {
+    pub current_password: String,
+    pub new_password: String,
+}
+
+#[derive(serde::Deserialize)]
+pub struct PasswordResetRequest {
+    pub email: String,
+}
+
+#[derive(serde::Deserialize)]
+pub struct PasswordReset {
+    pub token: String,
+    pub password: String,
+}
+
 pub fn authorize_user(user: &User, credentials: Credentials) -> Result<String, Error> {
     let argon2 = Argon2::default();
     let db_hash = PasswordHash::new(&user.password)?;
     argon2.verify_password(credentials.password.as_bytes(), &db_hash)?;
 
-    let session_id = rand::thread_rng()
+    Ok(generate_token(128))
+}
+
+/// Makes a new API key, `{prefix}.{secret}`, evaluating to its prefix and the key.
+pub fn generate_api_key() -> (String, String) {
+    let prefix = generate_token(12);
+    let key = format!("{}.{}", prefix, generate_token(48));
+    (prefix, key)
+}
+
+/// Whether a secret, such as an API key, matches the Argon2 hash kept of it.
+pub fn verify_secret(hash: &str, secret: &str) -> bool {
+    PasswordHash::new(hash)
+        .and_then(|hash| Argon2::default().verify_password(secret.as_bytes(), &hash))
+        .is_ok()
+}
+
+/// A random alphanumeric string, for tokens and other identifiers not to be guessed.
+pub fn generate_token(length: usize) -> String {
+    rand::thread_rng()
         .sample_iter(&Alphanumeric)
-        .take(128)
+        .take(length)
         .map(char::from)
-        .collect();
-
-    Ok(session_id)
+        .collect()
 }
 
 pub fn hash_password(password: String) -> Result<String, Error> {
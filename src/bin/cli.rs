@@ -1,7 +1,13 @@
 extern crate backend;
 
-use backend::commands::{create_user, delete_user, list_users};
-use clap::{value_parser, Arg, Command};
+use backend::commands::{
+    check_versions, create_user, delete_user, export_crates, export_rustaceans, grant_role,
+    import_crates, import_rustaceans, list_users, purge, revoke_role, revoke_sessions, unlock_user,
+    DataFormat,
+};
+use clap::{value_parser, Arg, ArgAction, Command};
+use std::path::PathBuf;
+use std::str::FromStr;
 
 #[tokio::main]
 async fn main() {
@@ -14,6 +20,8 @@ fn build_cli() -> Command {
         .about("Cr8s commands")
         .arg_required_else_help(true)
         .subcommand(build_users_command())
+        .subcommand(build_crates_command())
+        .subcommand(build_rustaceans_command())
         .subcommand(
             Command::new("digest-send")
                 .about("Send a digest with latest crates via email")
@@ -24,6 +32,15 @@ fn build_cli() -> Command {
                         .value_parser(value_parser!(i32)),
                 ),
         )
+        .subcommand(
+            Command::new("purge")
+                .about("Permanently remove crates and rustaceans deleted more than N days ago")
+                .arg(
+                    Arg::new("days")
+                        .required(true)
+                        .value_parser(value_parser!(i32).range(0..)),
+                ),
+        )
 }
 
 fn build_users_command() -> Command {
@@ -33,6 +50,16 @@ fn build_users_command() -> Command {
         .subcommand(build_create_user_command())
         .subcommand(build_list_users_command())
         .subcommand(build_delete_user_command())
+        .subcommand(build_user_role_command(
+            "grant",
+            "Give a role to an existing user",
+        ))
+        .subcommand(build_user_role_command(
+            "revoke",
+            "Take a role away from a user",
+        ))
+        .subcommand(build_revoke_sessions_command())
+        .subcommand(build_unlock_user_command())
 }
 
 fn build_create_user_command() -> Command {
@@ -64,9 +91,110 @@ fn build_delete_user_command() -> Command {
         )
 }
 
+fn build_revoke_sessions_command() -> Command {
+    Command::new("revoke-sessions")
+        .about("Log a user out of all their sessions")
+        .arg_required_else_help(true)
+        .arg(
+            Arg::new("id")
+                .required(true)
+                .value_parser(value_parser!(i32)),
+        )
+}
+
+fn build_unlock_user_command() -> Command {
+    Command::new("unlock")
+        .about("Let a user locked out after failed logins try again")
+        .arg_required_else_help(true)
+        .arg(
+            Arg::new("id")
+                .required(true)
+                .value_parser(value_parser!(i32)),
+        )
+}
+
+fn build_user_role_command(name: &'static str, about: &'static str) -> Command {
+    Command::new(name)
+        .about(about)
+        .arg_required_else_help(true)
+        .arg(
+            Arg::new("id")
+                .required(true)
+                .value_parser(value_parser!(i32)),
+        )
+        .arg(
+            Arg::new("role")
+                .required(true)
+                .value_parser(["admin", "editor", "viewer"]),
+        )
+}
+
+fn build_crates_command() -> Command {
+    Command::new("crates")
+        .about("Manage crates")
+        .arg_required_else_help(true)
+        .subcommand(build_check_versions_command())
+        .subcommand(build_export_command("crates"))
+        .subcommand(build_import_command("crates", "code"))
+}
+
+fn build_rustaceans_command() -> Command {
+    Command::new("rustaceans")
+        .about("Manage rustaceans")
+        .arg_required_else_help(true)
+        .subcommand(build_export_command("rustaceans"))
+        .subcommand(build_import_command("rustaceans", "email"))
+}
+
+fn build_format_arg() -> Arg {
+    Arg::new("format")
+        .long("format")
+        .value_parser(["csv", "jsonl"])
+}
+
+fn build_export_command(what: &str) -> Command {
+    Command::new("export")
+        .about(format!("Write all {} to standard output", what))
+        .arg(build_format_arg().default_value("csv"))
+}
+
+fn build_import_command(what: &str, key: &str) -> Command {
+    Command::new("import")
+        .about(format!(
+            "Import {} from a CSV or JSON Lines file, updating the ones whose {} exists",
+            what, key
+        ))
+        .arg_required_else_help(true)
+        .arg(
+            Arg::new("file")
+                .required(true)
+                .value_parser(value_parser!(PathBuf)),
+        )
+        .arg(build_format_arg().help("Format of the file, by default told from its extension"))
+        .arg(
+            Arg::new("dry_run")
+                .long("dry-run")
+                .help("Report what would change without saving anything")
+                .action(ArgAction::SetTrue),
+        )
+}
+
+fn build_check_versions_command() -> Command {
+    Command::new("check-versions")
+        .about("Report crate versions that are not valid semver")
+        .arg(
+            Arg::new("normalize")
+                .long("normalize")
+                .help("Rewrite versions with an obvious semver spelling, e.g. v2 -> 2.0.0")
+                .action(ArgAction::SetTrue),
+        )
+}
+
 async fn handle_commands(matches: clap::ArgMatches) {
     match matches.subcommand() {
         Some(("users", sub_matches)) => handle_users_commands(sub_matches).await,
+        Some(("crates", sub_matches)) => handle_crates_commands(sub_matches).await,
+        Some(("rustaceans", sub_matches)) => handle_rustaceans_commands(sub_matches).await,
         Some(("digest-send", sub_matches)) => {
             backend::commands::digest_send(
                 sub_matches.get_one::<String>("email").unwrap().to_owned(),
@@ -77,6 +205,9 @@ async fn handle_commands(matches: clap::ArgMatches) {
             )
             .await
         }
+        Some(("purge", sub_matches)) => {
+            purge(sub_matches.get_one::<i32>("days").unwrap().to_owned()).await
+        }
         _ => unreachable!(),
     }
 }
@@ -86,10 +217,71 @@ async fn handle_users_commands(sub_matches: &clap::ArgMatches) {
         Some(("create", create_matches)) => handle_create_user(create_matches).await,
         Some(("list", _)) => handle_list_users().await,
         Some(("delete", delete_matches)) => handle_delete_user(delete_matches).await,
+        Some(("grant", role_matches)) => {
+            grant_role(
+                role_matches.get_one::<i32>("id").unwrap().to_owned(),
+                role_matches.get_one::<String>("role").unwrap().to_owned(),
+            )
+            .await
+        }
+        Some(("revoke", role_matches)) => {
+            revoke_role(
+                role_matches.get_one::<i32>("id").unwrap().to_owned(),
+                role_matches.get_one::<String>("role").unwrap().to_owned(),
+            )
+            .await
+        }
+        Some(("revoke-sessions", revoke_matches)) => {
+            revoke_sessions(revoke_matches.get_one::<i32>("id").unwrap().to_owned()).await
+        }
+        Some(("unlock", unlock_matches)) => {
+            unlock_user(unlock_matches.get_one::<i32>("id").unwrap().to_owned()).await
+        }
+        _ => unreachable!(),
+    }
+}
+
+async fn handle_crates_commands(sub_matches: &clap::ArgMatches) {
+    match sub_matches.subcommand() {
+        Some(("check-versions", check_matches)) => handle_check_versions(check_matches).await,
+        Some(("export", export_matches)) => {
+            export_crates(get_format(export_matches).unwrap()).await
+        }
+        Some(("import", import_matches)) => {
+            import_crates(
+                import_matches.get_one::<PathBuf>("file").unwrap(),
+                get_format(import_matches),
+                import_matches.get_flag("dry_run"),
+            )
+            .await
+        }
         _ => unreachable!(),
     }
 }
 
+async fn handle_rustaceans_commands(sub_matches: &clap::ArgMatches) {
+    match sub_matches.subcommand() {
+        Some(("export", export_matches)) => {
+            export_rustaceans(get_format(export_matches).unwrap()).await
+        }
+        Some(("import", import_matches)) => {
+            import_rustaceans(
+                import_matches.get_one::<PathBuf>("file").unwrap(),
+                get_format(import_matches),
+                import_matches.get_flag("dry_run"),
+            )
+            .await
+        }
+        _ => unreachable!(),
+    }
+}
+
+fn get_format(matches: &clap::ArgMatches) -> Option<DataFormat> {
+    matches
+        .get_one::<String>("format")
+        .map(|format| DataFormat::from_str(format).unwrap())
+}
+
 async fn handle_create_user(matches: &clap::ArgMatches) {
     let username = matches
         .get_one::<String>("username")
@@ -113,3 +305,7 @@ async fn handle_list_users() {
 async fn handle_delete_user(matches: &clap::ArgMatches) {
     delete_user(matches.get_one::<i32>("id").unwrap().to_owned()).await;
 }
+
+async fn handle_check_versions(matches: &clap::ArgMatches) {
+    check_versions(matches.get_flag("normalize")).await;
+}
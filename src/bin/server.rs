@@ -1,4 +1,5 @@
 extern crate backend;
+use rocket::fairing::AdHoc;
 use rocket_db_pools::Database;
 
 #[rocket::main]
@@ -6,10 +7,28 @@ async fn main() {
     let _ = rocket::build()
         .mount(
             "/",
-            rocket::routes![backend::rocket_routes::authorization::login],
+            rocket::routes![
+                backend::rocket_routes::authorization::login,
+                backend::rocket_routes::authorization::logout,
+                backend::rocket_routes::authorization::refresh_token,
+                backend::rocket_routes::authorization::request_password_reset,
+                backend::rocket_routes::authorization::reset_password
+            ],
         )
+        .mount("/me", backend::rocket_routes::me::routes())
+        .mount("/audit", backend::rocket_routes::audit::routes())
+        .mount("/users", backend::rocket_routes::users::routes())
         .mount("/rustaceans", backend::rocket_routes::rustaceans::routes())
         .mount("/crates", backend::rocket_routes::crates::routes())
+        .mount("/crates", backend::rocket_routes::crate_versions::routes())
+        .mount(
+            "/crates",
+            backend::rocket_routes::crate_dependencies::routes(),
+        )
+        .attach(AdHoc::config::<backend::sessions::SessionConfig>())
+        .attach(AdHoc::config::<backend::lockout::LockoutConfig>())
+        .attach(AdHoc::config::<backend::password::PasswordConfig>())
+        .attach(backend::jwt::fairing())
         .attach(backend::rocket_routes::CacheConn::init())
         .attach(backend::rocket_routes::DbConn::init())
         .launch()
@@ -0,0 +1,169 @@
+use crate::filtering::QueryError;
+use crate::responses::handle_db_error;
+use rocket::http::Status;
+use rocket::response::status::Custom;
+use rocket::serde::json::{serde_json::json, Value};
+use serde::{Deserialize, Serialize};
+
+pub const MAX_ITEMS: usize = 1000;
+
+/// How a bulk request deals with items that fail.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum BulkMode {
+    /// Any failed item rolls back the whole request.
+    AllOrNothing,
+    /// Items that succeed are committed regardless of the ones that fail.
+    BestEffort,
+}
+
+impl BulkMode {
+    /// Validates the raw `?mode=` query parameter, defaulting to all-or-nothing.
+    pub fn from_query(mode: Option<&str>) -> Result<Self, QueryError> {
+        match mode {
+            None | Some("all-or-nothing") => Ok(BulkMode::AllOrNothing),
+            Some("best-effort") => Ok(BulkMode::BestEffort),
+            Some(other) => Err(QueryError::new(
+                "mode",
+                format!(
+                    "Unknown mode '{}', expected 'all-or-nothing' or 'best-effort'",
+                    other
+                ),
+            )),
+        }
+    }
+
+    /// Turns the outcome of each item into its report, failing the surrounding
+    /// transaction when an item failed in all-or-nothing mode.
+    pub fn finish(
+        self,
+        success: Status,
+        outcomes: Vec<Result<Option<Value>, Custom<Value>>>,
+    ) -> Result<Vec<ItemResult>, BulkError> {
+        let results: Vec<_> = outcomes
+            .into_iter()
+            .enumerate()
+            .map(|(index, outcome)| match outcome {
+                Ok(item) => ItemResult {
+                    index,
+                    status: success.code,
+                    item,
+                    error: None,
+                },
+                Err(Custom(status, error)) => ItemResult {
+                    index,
+                    status: status.code,
+                    item: None,
+                    error: Some(error),
+                },
+            })
+            .collect();
+
+        if self == BulkMode::BestEffort || results.iter().all(ItemResult::succeeded) {
+            return Ok(results);
+        }
+
+        // Items that went through are rolled back along with the rest.
+        let results = results
+            .into_iter()
+            .map(|result| {
+                if result.succeeded() {
+                    ItemResult {
+                        status: Status::FailedDependency.code,
+                        item: None,
+                        error: Some(json!({ "error": "Rolled back" })),
+                        ..result
+                    }
+                } else {
+                    result
+                }
+            })
+            .collect();
+        Err(BulkError::RolledBack(results))
+    }
+}
+
+/// The report on one item of a bulk request, at its position in the request body.
+#[derive(Serialize)]
+pub struct ItemResult {
+    pub index: usize,
+    pub status: u16,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub item: Option<Value>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<Value>,
+}
+
+impl ItemResult {
+    pub fn succeeded(&self) -> bool {
+        (200..300).contains(&self.status)
+    }
+}
+
+/// Error returned from the transaction of a bulk request.
+pub enum BulkError {
+    /// An item failed in all-or-nothing mode.
+    RolledBack(Vec<ItemResult>),
+    Database(diesel::result::Error),
+}
+
+impl From<diesel::result::Error> for BulkError {
+    fn from(e: diesel::result::Error) -> Self {
+        BulkError::Database(e)
+    }
+}
+
+/// One entry of a bulk patch request.
+#[derive(Deserialize)]
+pub struct ItemPatch {
+    pub id: i32,
+    /// A JSON Merge Patch document for the row.
+    pub patch: Value,
+}
+
+/// Rejects bulk requests with more than [`MAX_ITEMS`] items.
+pub fn check_size<T>(items: Vec<T>) -> Result<Vec<T>, Custom<Value>> {
+    if items.len() > MAX_ITEMS {
+        return Err(Custom(
+            Status::PayloadTooLarge,
+            json!({
+                "error": "Too many items",
+                "message": format!("At most {} items are accepted per request", MAX_ITEMS),
+            }),
+        ));
+    }
+    Ok(items)
+}
+
+/// Responds with 200 when every item succeeded and 207 when some failed but the rest
+/// were committed. A rolled back request is answered with 422.
+pub fn respond(
+    outcome: Result<Vec<ItemResult>, BulkError>,
+    context: &str,
+) -> Result<Custom<Value>, Custom<Value>> {
+    match outcome {
+        Ok(results) => {
+            let status = if results.iter().all(ItemResult::succeeded) {
+                Status::Ok
+            } else {
+                Status::MultiStatus
+            };
+            Ok(Custom(
+                status,
+                json!({ "committed": true, "results": results }),
+            ))
+        }
+        Err(BulkError::RolledBack(results)) => Err(Custom(
+            Status::UnprocessableEntity,
+            json!({
+                "error": "Some items failed, nothing was committed",
+                "committed": false,
+                "results": results,
+            }),
+        )),
+        Err(BulkError::Database(e)) => Err(handle_db_error(
+            e,
+            format!("Failed {}", context),
+            context.to_string(),
+        )),
+    }
+}
@@ -1,5 +1,16 @@
+use crate::jwt::{self, JwtConfig};
+use crate::lockout;
 use crate::mail::HtmlMailer;
-use crate::repositories::CrateRepository;
+use crate::models::{
+    AuditAction, NewAuditEntry, NewCrate, NewCrateVersion, NewRustacean, UpdateCrate,
+    UpdateRustacean,
+};
+use crate::password::PasswordConfig;
+use crate::repositories::{
+    AuditLogRepository, CrateRepository, CrateVersionRepository, RustaceanRepository,
+};
+use crate::sessions;
+use crate::validation::{normalize_version, parse_semver, Validate, ValidationErrors};
 use crate::{
     auth,
     models::NewUser,
@@ -7,10 +18,20 @@ use crate::{
     repositories::{RoleRepository, UserRepository},
 };
 use chrono::{Datelike, Utc};
+use diesel::{OptionalExtension, QueryResult};
+use diesel_async::scoped_futures::ScopedFutureExt;
 use diesel_async::{AsyncConnection, AsyncPgConnection};
+use rocket_db_pools::deadpool_redis;
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, BufWriter, Stdout, Write};
+use std::path::Path;
 use std::str::FromStr;
 use tera::{Context, Tera};
 
+const EXPORT_BATCH_SIZE: i64 = 500;
+
 async fn load_db_connection() -> AsyncPgConnection {
     let database_url = std::env::var("DATABASE_URL").expect("Cannot load DB url from environment");
     AsyncPgConnection::establish(&database_url)
@@ -18,10 +39,28 @@ async fn load_db_connection() -> AsyncPgConnection {
         .expect("Cannot connect to Postgres")
 }
 
+async fn load_cache_connection() -> deadpool_redis::Connection {
+    let redis_url = std::env::var("REDIS_URL").expect("Cannot load Redis url from environment");
+    deadpool_redis::Config::from_url(redis_url)
+        .create_pool(Some(deadpool_redis::Runtime::Tokio1))
+        .expect("Cannot create Redis pool")
+        .get()
+        .await
+        .expect("Cannot connect to Redis")
+}
+
+/// Creates a user, as long as their password meets the password policy.
 pub async fn create_user(username: String, password: String, role_codes: Vec<String>) {
+    let password_config: PasswordConfig = rocket::Config::figment()
+        .extract()
+        .expect("Cannot load password configuration");
+    if let Err(e) = password_config.check(&username, &password) {
+        eprintln!("Password refused: {}", e);
+        std::process::exit(1);
+    }
     let mut c = load_db_connection().await;
 
-    let password_hash = auth::hash_password(password).unwrap();
+    let password_hash = auth::hash_password(password).expect("Cannot hash password");
     let new_user = NewUser {
         username,
         password: password_hash,
@@ -52,7 +91,494 @@ pub async fn list_users() {
 
 pub async fn delete_user(id: i32) {
     let mut c = load_db_connection().await;
-    UserRepository::delete(&mut c, id).await.unwrap();
+    match UserRepository::delete(&mut c, id).await {
+        Ok(_) => {
+            sign_out_everywhere(id).await;
+            println!("Deleted user {}", id)
+        }
+        Err(diesel::result::Error::NotFound) => {
+            eprintln!("User {} not found", id);
+            std::process::exit(1);
+        }
+        Err(e) => panic!("Cannot delete user {}: {}", id, e),
+    }
+}
+
+pub async fn grant_role(id: i32, role_code: String) {
+    let mut c = load_db_connection().await;
+    let role_code = RoleCode::from_str(role_code.as_str()).expect("Unknown role");
+
+    if UserRepository::grant_role(&mut c, id, role_code.clone())
+        .await
+        .unwrap()
+    {
+        println!("Granted role {} to user {}", role_code, id);
+    } else {
+        println!("User {} already has role {}", id, role_code);
+    }
+}
+
+pub async fn revoke_role(id: i32, role_code: String) {
+    let mut c = load_db_connection().await;
+    let role_code = RoleCode::from_str(role_code.as_str()).expect("Unknown role");
+
+    if UserRepository::revoke_role(&mut c, id, role_code.clone())
+        .await
+        .unwrap()
+    {
+        sign_out_everywhere(id).await;
+        println!("Revoked role {} from user {}", role_code, id);
+    } else {
+        println!("User {} does not have role {}", id, role_code);
+    }
+}
+
+/// Ends every session of a user and denies the tokens issued to them in JWT mode,
+/// evaluating to how many sessions there were.
+async fn sign_out_everywhere(id: i32) -> usize {
+    let mut cache = load_cache_connection().await;
+    let revoked = sessions::revoke_all(&mut cache, id).await.unwrap();
+    let jwt_config: JwtConfig = rocket::Config::figment()
+        .extract()
+        .expect("Cannot load JWT configuration");
+    jwt::deny_all(&mut cache, id, jwt_config.jwt_ttl, None)
+        .await
+        .unwrap();
+    revoked
+}
+
+/// Logs a user out everywhere, e.g. after their account was compromised.
+/// Tokens issued in JWT mode are denied as well.
+pub async fn revoke_sessions(id: i32) {
+    let revoked = sign_out_everywhere(id).await;
+    println!("Revoked {} session(s) of user {}", revoked, id);
+}
+
+/// Lifts a lockout after failed logins, letting the user try again at once.
+pub async fn unlock_user(id: i32) {
+    let mut c = load_db_connection().await;
+    let mut cache = load_cache_connection().await;
+
+    let user = match UserRepository::find(&mut c, id).await {
+        Ok(user) => user,
+        Err(diesel::result::Error::NotFound) => {
+            eprintln!("User {} not found", id);
+            std::process::exit(1);
+        }
+        Err(e) => panic!("Cannot find user {}: {}", id, e),
+    };
+    if !lockout::clear(&mut cache, &user.username).await.unwrap() {
+        println!("User {} has no failed logins", id);
+        return;
+    }
+    let entry = NewAuditEntry {
+        user_id: None,
+        action: AuditAction::Unlock,
+        resource: "users".to_string(),
+        resource_id: id,
+        before: None,
+        after: None,
+    };
+    AuditLogRepository::create(&mut c, entry).await.unwrap();
+    println!("Unlocked user {}", id);
+}
+
+/// Reports crate versions that are not valid semver and, when `normalize` is set,
+/// rewrites the ones with an obvious semver spelling (`v2` -> `2.0.0`).
+pub async fn check_versions(normalize: bool) {
+    let mut c = load_db_connection().await;
+    let mut invalid = 0;
+
+    let versions = CrateVersionRepository::find_all(&mut c).await.unwrap();
+    for version in versions
+        .iter()
+        .filter(|v| parse_semver(&v.version).is_err())
+    {
+        invalid += 1;
+        let label = format!("Crate {} version '{}'", version.crate_id, version.version);
+        match normalize_version(&version.version) {
+            Some(normalized) if normalize => {
+                match CrateVersionRepository::rename(&mut c, version.id, normalized.clone()).await {
+                    Ok(_) => println!("{}: normalized to '{}'", label, normalized),
+                    // Most likely the normalized version is already in the history
+                    Err(e) => println!("{}: cannot normalize to '{}': {}", label, normalized, e),
+                }
+            }
+            _ => report_invalid_version(&label, &version.version),
+        }
+    }
+
+    let crates = CrateRepository::find_all(&mut c).await.unwrap();
+    for krate in crates.iter().filter(|k| parse_semver(&k.version).is_err()) {
+        invalid += 1;
+        let label = format!(
+            "Crate {} ({}) current version '{}'",
+            krate.id, krate.code, krate.version
+        );
+        if !normalize {
+            report_invalid_version(&label, &krate.version);
+            continue;
+        }
+
+        // The normalized history usually yields a valid latest version already
+        let mut updated = CrateVersionRepository::refresh_latest(&mut c, krate.id)
+            .await
+            .unwrap();
+        if let (Err(_), Some(normalized)) = (
+            parse_semver(&updated.version),
+            normalize_version(&krate.version),
+        ) {
+            let patch = UpdateCrate {
+                version: Some(normalized),
+                ..Default::default()
+            };
+            updated = CrateRepository::update(&mut c, krate.id, patch)
+                .await
+                .unwrap();
+        }
+        match parse_semver(&updated.version) {
+            Ok(_) => println!("{}: now '{}'", label, updated.version),
+            Err(_) => println!("{}: cannot be normalized", label),
+        }
+    }
+
+    println!("Found {} invalid version(s)", invalid);
+}
+
+fn report_invalid_version(label: &str, version: &str) {
+    match normalize_version(version) {
+        Some(normalized) => println!("{}: not semver, suggested '{}'", label, normalized),
+        None => println!("{}: not semver, no suggestion", label),
+    }
+}
+
+/// Hard-deletes crates and rustaceans soft-deleted more than `days` days ago.
+/// Crates go first so that their owners can follow.
+pub async fn purge(days: i32) {
+    let mut c = load_db_connection().await;
+
+    let crates = CrateRepository::purge(&mut c, days).await.unwrap();
+    let rustaceans = RustaceanRepository::purge(&mut c, days).await.unwrap();
+    println!(
+        "Purged {} crate(s) and {} rustacean(s) deleted more than {} day(s) ago",
+        crates, rustaceans, days
+    );
+}
+
+/// File formats read and written by the import and export commands.
+#[derive(Clone, Copy)]
+pub enum DataFormat {
+    Csv,
+    /// JSON Lines, one JSON object per line.
+    Jsonl,
+}
+
+impl FromStr for DataFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "csv" => Ok(DataFormat::Csv),
+            "jsonl" => Ok(DataFormat::Jsonl),
+            _ => Err(format!("Unknown format '{}'", s)),
+        }
+    }
+}
+
+impl DataFormat {
+    /// Tells the format of a file from its extension.
+    fn from_path(path: &Path) -> Option<Self> {
+        match path.extension()?.to_str()? {
+            "csv" => Some(DataFormat::Csv),
+            "jsonl" | "ndjson" => Some(DataFormat::Jsonl),
+            _ => None,
+        }
+    }
+}
+
+/// Writes exported rows to standard output as they come.
+enum RowWriter {
+    Csv(Box<csv::Writer<Stdout>>),
+    Jsonl(BufWriter<Stdout>),
+}
+
+impl RowWriter {
+    fn new(format: DataFormat) -> Self {
+        match format {
+            DataFormat::Csv => RowWriter::Csv(Box::new(csv::Writer::from_writer(io::stdout()))),
+            DataFormat::Jsonl => RowWriter::Jsonl(BufWriter::new(io::stdout())),
+        }
+    }
+
+    fn write<T: Serialize>(&mut self, row: &T) {
+        match self {
+            RowWriter::Csv(writer) => writer.serialize(row).unwrap(),
+            RowWriter::Jsonl(writer) => {
+                serde_json::to_writer(&mut *writer, row).unwrap();
+                writeln!(writer).unwrap();
+            }
+        }
+    }
+
+    fn flush(&mut self) {
+        match self {
+            RowWriter::Csv(writer) => writer.flush().unwrap(),
+            RowWriter::Jsonl(writer) => writer.flush().unwrap(),
+        }
+    }
+}
+
+/// A crate as exported and imported. The owner goes by email rather than by id,
+/// so that the crate lands with the same owner in another database.
+#[derive(Serialize, Deserialize)]
+struct CrateRecord {
+    owner_email: String,
+    code: String,
+    name: String,
+    version: String,
+    description: Option<String>,
+}
+
+impl Validate for CrateRecord {
+    fn validate(&self) -> Result<(), ValidationErrors> {
+        let mut errors = ValidationErrors::default();
+        if let Err(e) = parse_semver(&self.version) {
+            errors.add("version", e);
+        }
+        errors.into_result()
+    }
+}
+
+/// Writes every crate to standard output.
+pub async fn export_crates(format: DataFormat) {
+    let mut c = load_db_connection().await;
+    let mut writer = RowWriter::new(format);
+
+    let mut after = 0;
+    loop {
+        let batch = CrateRepository::find_batch_with_owner_emails(&mut c, after, EXPORT_BATCH_SIZE)
+            .await
+            .unwrap();
+        let Some((last, _)) = batch.last() else { break };
+        after = last.id;
+        for (krate, owner_email) in batch {
+            writer.write(&CrateRecord {
+                owner_email,
+                code: krate.code,
+                name: krate.name,
+                version: krate.version,
+                description: krate.description,
+            });
+        }
+    }
+    writer.flush();
+}
+
+/// Writes every rustacean to standard output.
+pub async fn export_rustaceans(format: DataFormat) {
+    let mut c = load_db_connection().await;
+    let mut writer = RowWriter::new(format);
+
+    let mut after = 0;
+    loop {
+        let batch = RustaceanRepository::find_batch(&mut c, after, EXPORT_BATCH_SIZE)
+            .await
+            .unwrap();
+        let Some(last) = batch.last() else { break };
+        after = last.id;
+        batch.iter().for_each(|rustacean| writer.write(rustacean));
+    }
+    writer.flush();
+}
+
+/// What importing a row did to the table.
+enum RowOutcome {
+    Inserted,
+    Updated,
+    Unchanged,
+    /// Left out, for the given reason.
+    Rejected(String),
+}
+
+/// Reads the rows of an import file lazily, each parsed on its own
+/// so that a malformed row can be skipped without giving up on the rest.
+fn read_rows<T: DeserializeOwned + 'static>(
+    path: &Path,
+    format: DataFormat,
+) -> Box<dyn Iterator<Item = Result<T, String>>> {
+    match format {
+        DataFormat::Csv => {
+            let reader = csv::Reader::from_path(path).expect("Cannot open import file");
+            Box::new(
+                reader
+                    .into_deserialize()
+                    .map(|row| row.map_err(|e| e.to_string())),
+            )
+        }
+        DataFormat::Jsonl => {
+            let file = File::open(path).expect("Cannot open import file");
+            Box::new(
+                BufReader::new(file)
+                    .lines()
+                    .filter(|line| !matches!(line, Ok(line) if line.trim().is_empty()))
+                    .map(|line| {
+                        line.map_err(|e| e.to_string())
+                            .and_then(|line| serde_json::from_str(&line).map_err(|e| e.to_string()))
+                    }),
+            )
+        }
+    }
+}
+
+/// Upserts every row of an import file with `upsert` and prints a summary.
+/// Rows that cannot be parsed, fail validation or are rejected by the database are
+/// skipped. A dry run does all the work in a transaction that is never committed.
+async fn import_rows<T: DeserializeOwned + Validate + 'static>(
+    path: &Path,
+    format: Option<DataFormat>,
+    dry_run: bool,
+    mut upsert: impl AsyncFnMut(&mut AsyncPgConnection, T) -> QueryResult<RowOutcome>,
+) {
+    let format = format
+        .or_else(|| DataFormat::from_path(path))
+        .expect("Cannot tell the format from the file extension, pass --format");
+    let mut c = load_db_connection().await;
+    if dry_run {
+        c.begin_test_transaction().await.unwrap();
+    }
+
+    let (mut inserted, mut updated, mut skipped) = (0, 0, 0);
+    for (index, row) in read_rows::<T>(path, format).enumerate() {
+        let outcome = match row {
+            Ok(row) => match row.validate() {
+                Ok(()) => upsert(&mut c, row).await.map_err(|e| e.to_string()),
+                Err(errors) => Err(errors.to_string()),
+            },
+            Err(e) => Err(e),
+        };
+        match outcome {
+            Ok(RowOutcome::Inserted) => inserted += 1,
+            Ok(RowOutcome::Updated) => updated += 1,
+            Ok(RowOutcome::Unchanged) => skipped += 1,
+            Ok(RowOutcome::Rejected(e)) | Err(e) => {
+                println!("Row {}: skipped, {}", index + 1, e);
+                skipped += 1;
+            }
+        }
+    }
+
+    println!(
+        "{}Inserted {}, updated {} and skipped {} row(s)",
+        if dry_run {
+            "Dry run, nothing saved. "
+        } else {
+            ""
+        },
+        inserted,
+        updated,
+        skipped
+    );
+}
+
+/// Imports crates, updating the ones whose code is already taken.
+/// The owner is looked up by email, and a version the crate does not have yet
+/// is added to its version history.
+pub async fn import_crates(path: &Path, format: Option<DataFormat>, dry_run: bool) {
+    import_rows(path, format, dry_run, upsert_crate).await
+}
+
+async fn upsert_crate(c: &mut AsyncPgConnection, row: CrateRecord) -> QueryResult<RowOutcome> {
+    c.transaction(|conn| {
+        async move {
+            let Some(owner) = RustaceanRepository::find_by_email(conn, &row.owner_email)
+                .await
+                .optional()?
+            else {
+                return Ok(RowOutcome::Rejected(format!(
+                    "no rustacean with email '{}'",
+                    row.owner_email
+                )));
+            };
+            let Some(existing) = CrateRepository::find_by_code(conn, &row.code)
+                .await
+                .optional()?
+            else {
+                CrateRepository::create(
+                    conn,
+                    NewCrate {
+                        rustacean_id: owner.id,
+                        code: row.code,
+                        name: row.name,
+                        version: row.version,
+                        description: row.description,
+                    },
+                )
+                .await?;
+                return Ok(RowOutcome::Inserted);
+            };
+
+            // The crate points at its latest version, which need not be the one in the row.
+            let versions = CrateVersionRepository::find_by_crate(conn, existing.id).await?;
+            let known_version = versions.iter().any(|v| v.version == row.version);
+            let unchanged = known_version
+                && existing.rustacean_id == owner.id
+                && existing.code == row.code
+                && existing.name == row.name
+                && existing.description == row.description;
+            if unchanged {
+                return Ok(RowOutcome::Unchanged);
+            }
+            if !known_version {
+                CrateVersionRepository::create(
+                    conn,
+                    NewCrateVersion {
+                        crate_id: existing.id,
+                        version: row.version,
+                        changelog: None,
+                    },
+                )
+                .await?;
+            }
+            let patch = UpdateCrate {
+                rustacean_id: Some(owner.id),
+                code: Some(row.code),
+                name: Some(row.name),
+                version: None,
+                description: Some(row.description),
+            };
+            CrateRepository::update(conn, existing.id, patch).await?;
+            Ok(RowOutcome::Updated)
+        }
+        .scope_boxed()
+    })
+    .await
+}
+
+/// Imports rustaceans, updating the ones whose email is already taken.
+pub async fn import_rustaceans(path: &Path, format: Option<DataFormat>, dry_run: bool) {
+    import_rows(path, format, dry_run, upsert_rustacean).await
+}
+
+async fn upsert_rustacean(c: &mut AsyncPgConnection, row: NewRustacean) -> QueryResult<RowOutcome> {
+    c.transaction(|conn| {
+        async move {
+            let Some(existing) = RustaceanRepository::find_by_email(conn, &row.email)
+                .await
+                .optional()?
+            else {
+                RustaceanRepository::create(conn, row).await?;
+                return Ok(RowOutcome::Inserted);
+            };
+
+            if existing.name == row.name && existing.email == row.email {
+                return Ok(RowOutcome::Unchanged);
+            }
+            RustaceanRepository::update(conn, existing.id, UpdateRustacean::from(row)).await?;
+            Ok(RowOutcome::Updated)
+        }
+        .scope_boxed()
+    })
+    .await
 }
 
 fn load_template_engine() -> Tera {
@@ -66,7 +592,7 @@ pub async fn digest_send(email: String, hours_since: i32) {
         .await
         .unwrap();
 
-    if crates.len() > 0 {
+    if !crates.is_empty() {
         println!("Sending digest for {} crates", crates.len());
         let year = Utc::now().year();
         let mut context = Context::new();
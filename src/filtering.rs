@@ -0,0 +1,160 @@
+use crate::models::AuditAction;
+use chrono::{NaiveDate, NaiveDateTime};
+use std::collections::HashMap;
+use std::fmt;
+
+/// A rejected list query parameter, reported back to the client as a 400.
+#[derive(Debug)]
+pub struct QueryError {
+    pub field: String,
+    pub message: String,
+}
+
+impl QueryError {
+    pub fn new(field: &str, message: impl Into<String>) -> Self {
+        Self {
+            field: field.to_string(),
+            message: message.into(),
+        }
+    }
+
+    pub fn unknown_filter(field: &str) -> Self {
+        Self::new(field, "Unknown filter")
+    }
+
+    pub fn invalid_value(field: &str, value: &str) -> Self {
+        Self::new(field, format!("Invalid value '{}'", value))
+    }
+
+    pub fn unknown_sort_field(field: &str) -> Self {
+        Self::new("sort", format!("Cannot sort by '{}'", field))
+    }
+}
+
+impl fmt::Display for QueryError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: {}", self.field, self.message)
+    }
+}
+
+/// Error returned by the generated `find_multiple`.
+#[derive(Debug)]
+pub enum ListError {
+    Query(QueryError),
+    Database(diesel::result::Error),
+}
+
+impl From<QueryError> for ListError {
+    fn from(e: QueryError) -> Self {
+        ListError::Query(e)
+    }
+}
+
+impl From<diesel::result::Error> for ListError {
+    fn from(e: diesel::result::Error) -> Self {
+        ListError::Database(e)
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+pub enum SortDirection {
+    Asc,
+    Desc,
+}
+
+#[derive(Debug)]
+pub struct SortField {
+    pub field: String,
+    pub direction: SortDirection,
+}
+
+/// Filters and sort order requested for a list endpoint.
+/// Field names are only checked against a repository's declared columns in `find_multiple`.
+#[derive(Debug, Default)]
+pub struct ListParams {
+    pub filters: Vec<(String, String)>,
+    pub sort: Vec<SortField>,
+}
+
+impl ListParams {
+    /// Parses the raw query string parameters,
+    /// e.g. `?code=SERDE&sort=-created_at,name`.
+    pub fn from_query(
+        filters: HashMap<String, String>,
+        sort: Option<&str>,
+    ) -> Result<Self, QueryError> {
+        let sort = match sort {
+            Some(sort) => sort
+                .split(',')
+                .map(|field| {
+                    let field = field.trim();
+                    let (field, direction) = match field.strip_prefix('-') {
+                        Some(field) => (field, SortDirection::Desc),
+                        None => (field.strip_prefix('+').unwrap_or(field), SortDirection::Asc),
+                    };
+                    if field.is_empty() {
+                        return Err(QueryError::new("sort", "Empty sort field"));
+                    }
+                    Ok(SortField {
+                        field: field.to_string(),
+                        direction,
+                    })
+                })
+                .collect::<Result<_, _>>()?,
+            None => Vec::new(),
+        };
+
+        Ok(Self {
+            filters: filters.into_iter().collect(),
+            sort,
+        })
+    }
+}
+
+/// A type a raw filter value can be parsed into.
+pub trait FilterValue: Sized {
+    fn parse_filter(value: &str) -> Option<Self>;
+}
+
+impl FilterValue for i32 {
+    fn parse_filter(value: &str) -> Option<Self> {
+        value.parse().ok()
+    }
+}
+
+impl FilterValue for String {
+    fn parse_filter(value: &str) -> Option<Self> {
+        Some(value.to_string())
+    }
+}
+
+impl FilterValue for AuditAction {
+    fn parse_filter(value: &str) -> Option<Self> {
+        value.parse().ok()
+    }
+}
+
+impl FilterValue for NaiveDateTime {
+    /// Accepts either a full timestamp (`2025-06-08T17:14:13`) or a plain date (`2025-06-08`).
+    fn parse_filter(value: &str) -> Option<Self> {
+        value.parse().ok().or_else(|| {
+            value
+                .parse::<NaiveDate>()
+                .ok()
+                .and_then(|date| date.and_hms_opt(0, 0, 0))
+        })
+    }
+}
+
+pub fn parse_filter_value<T: FilterValue>(field: &str, value: &str) -> Result<T, QueryError> {
+    T::parse_filter(value).ok_or_else(|| QueryError::invalid_value(field, value))
+}
+
+/// Builds an `ILIKE` pattern matching `value` anywhere, with wildcards in it escaped.
+pub fn contains_pattern(value: &str) -> String {
+    let escaped = value
+        .replace('\\', "\\\\")
+        .replace('%', "\\%")
+        .replace('_', "\\_");
+    format!("%{}%", escaped)
+}
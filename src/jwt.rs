@@ -0,0 +1,429 @@
+use crate::auth::generate_token;
+use crate::models::{RoleCode, User};
+use crate::repositories::RoleRepository;
+use chrono::{NaiveDateTime, Utc};
+use diesel::QueryResult;
+use diesel_async::AsyncPgConnection;
+use jsonwebtoken::{Algorithm, DecodingKey, EncodingKey, Header, Validation};
+use rocket::fairing::AdHoc;
+use rocket_db_pools::deadpool_redis::redis::aio::ConnectionLike;
+use rocket_db_pools::deadpool_redis::redis::{AsyncCommands, RedisResult};
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+use tokio::sync::OnceCell;
+
+/// How requests authenticate, chosen at startup with `ROCKET_AUTH_MODE`.
+#[derive(Clone, Copy, Default, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum AuthMode {
+    /// Opaque tokens looked up in Redis on every request.
+    #[default]
+    Session,
+    /// Signed tokens verified without a lookup, save for the deny list.
+    Jwt,
+}
+
+#[derive(Clone, Copy, Default, Deserialize)]
+pub enum JwtAlgorithm {
+    #[default]
+    HS256,
+    EdDSA,
+}
+
+/// JWT settings, read from the Rocket configuration, e.g. `ROCKET_JWT_SECRET=...`.
+#[derive(Deserialize)]
+#[serde(default)]
+pub struct JwtConfig {
+    pub auth_mode: AuthMode,
+    pub jwt_algorithm: JwtAlgorithm,
+    /// The shared secret for HS256.
+    pub jwt_secret: Option<String>,
+    /// The PEM encoded Ed25519 key pair for EdDSA.
+    pub jwt_private_key: Option<String>,
+    pub jwt_public_key: Option<String>,
+    /// How many seconds a token lasts.
+    pub jwt_ttl: u64,
+}
+
+impl Default for JwtConfig {
+    fn default() -> Self {
+        Self {
+            auth_mode: AuthMode::default(),
+            jwt_algorithm: JwtAlgorithm::default(),
+            jwt_secret: None,
+            jwt_private_key: None,
+            jwt_public_key: None,
+            jwt_ttl: 15 * 60,
+        }
+    }
+}
+
+/// What a token says about its user. The password hash is never part of it.
+/// Permissions are resolved from the role codes without looking the user up, so
+/// changing someone's roles revokes their tokens.
+#[derive(Serialize, Deserialize)]
+pub struct Claims {
+    /// The user id.
+    pub sub: String,
+    pub name: String,
+    pub roles: Vec<RoleCode>,
+    /// When the user was created.
+    pub created_at: NaiveDateTime,
+    /// Identifies the token on the deny list.
+    pub jti: String,
+    pub iat: i64,
+    /// When the token was issued, in milliseconds, to tell it apart from revoking all
+    /// of the user's tokens within the same second.
+    #[serde(default)]
+    pub iat_ms: i64,
+    pub exp: i64,
+}
+
+impl Claims {
+    pub fn user(&self) -> Option<User> {
+        Some(User {
+            id: self.sub.parse().ok()?,
+            username: self.name.clone(),
+            password: String::new(),
+            created_at: self.created_at,
+        })
+    }
+}
+
+/// The authentication mode picked at startup, managed by Rocket.
+pub enum Authentication {
+    Session,
+    Jwt(Box<JwtKeys>),
+}
+
+impl Authentication {
+    /// The keys to sign and verify tokens with, in JWT mode.
+    pub fn jwt_keys(&self) -> Option<&JwtKeys> {
+        match self {
+            Authentication::Session => None,
+            Authentication::Jwt(keys) => Some(keys),
+        }
+    }
+}
+
+/// The permissions each role grants, managed by Rocket in JWT mode. They only change
+/// with migrations, so they are loaded once, on first use.
+#[derive(Default)]
+pub struct RolePermissions(OnceCell<HashMap<RoleCode, HashSet<String>>>);
+
+impl RolePermissions {
+    /// Whether any of the roles grants the permission with the given code.
+    pub async fn grant(
+        &self,
+        c: &mut AsyncPgConnection,
+        roles: &[RoleCode],
+        permission_code: &str,
+    ) -> QueryResult<bool> {
+        let permissions = self
+            .0
+            .get_or_try_init(|| async {
+                let mut permissions: HashMap<RoleCode, HashSet<String>> = HashMap::new();
+                for (role, code) in RoleRepository::find_permission_codes(c).await? {
+                    permissions.entry(role).or_default().insert(code);
+                }
+                Ok::<_, diesel::result::Error>(permissions)
+            })
+            .await?;
+        Ok(roles.iter().any(|role| {
+            permissions
+                .get(role)
+                .is_some_and(|codes| codes.contains(permission_code))
+        }))
+    }
+}
+
+/// The keys for signing and verifying tokens.
+pub struct JwtKeys {
+    header: Header,
+    encoding: EncodingKey,
+    decoding: DecodingKey,
+    validation: Validation,
+    ttl: u64,
+}
+
+impl JwtKeys {
+    pub fn from_config(config: &JwtConfig) -> Result<Self, String> {
+        let (algorithm, encoding, decoding) = match config.jwt_algorithm {
+            JwtAlgorithm::HS256 => {
+                let secret = config
+                    .jwt_secret
+                    .as_deref()
+                    .filter(|secret| !secret.is_empty())
+                    .ok_or("jwt_secret is required for HS256")?;
+                (
+                    Algorithm::HS256,
+                    EncodingKey::from_secret(secret.as_bytes()),
+                    DecodingKey::from_secret(secret.as_bytes()),
+                )
+            }
+            JwtAlgorithm::EdDSA => {
+                let (Some(private_key), Some(public_key)) =
+                    (&config.jwt_private_key, &config.jwt_public_key)
+                else {
+                    return Err("jwt_private_key and jwt_public_key are required for EdDSA".into());
+                };
+                (
+                    Algorithm::EdDSA,
+                    EncodingKey::from_ed_pem(private_key.as_bytes())
+                        .map_err(|e| format!("Invalid jwt_private_key: {}", e))?,
+                    DecodingKey::from_ed_pem(public_key.as_bytes())
+                        .map_err(|e| format!("Invalid jwt_public_key: {}", e))?,
+                )
+            }
+        };
+
+        Ok(Self {
+            header: Header::new(algorithm),
+            encoding,
+            decoding,
+            validation: Validation::new(algorithm),
+            ttl: config.jwt_ttl,
+        })
+    }
+
+    /// Signs a token for a user holding the given roles.
+    pub fn issue(&self, user: &User, roles: Vec<RoleCode>) -> jsonwebtoken::errors::Result<String> {
+        let now = Utc::now();
+        let claims = Claims {
+            sub: user.id.to_string(),
+            name: user.username.clone(),
+            roles,
+            created_at: user.created_at,
+            jti: generate_token(32),
+            iat: now.timestamp(),
+            iat_ms: now.timestamp_millis(),
+            exp: now.timestamp() + self.ttl as i64,
+        };
+        jsonwebtoken::encode(&self.header, &claims, &self.encoding)
+    }
+
+    /// How many seconds a token lasts.
+    pub fn ttl(&self) -> u64 {
+        self.ttl
+    }
+
+    /// The claims of a token that is genuine and has not expired.
+    pub fn verify(&self, token: &str) -> Option<Claims> {
+        jsonwebtoken::decode::<Claims>(token, &self.decoding, &self.validation)
+            .ok()
+            .map(|data| data.claims)
+    }
+}
+
+/// Picks the authentication mode at startup, failing the launch on a bad JWT setup.
+pub fn fairing() -> AdHoc {
+    AdHoc::try_on_ignite("Authentication mode", |rocket| async {
+        let config: JwtConfig = match rocket.figment().extract() {
+            Ok(config) => config,
+            Err(e) => {
+                rocket::error!("Invalid JWT configuration: {}", e);
+                return Err(rocket);
+            }
+        };
+        if config.auth_mode == AuthMode::Session {
+            return Ok(rocket.manage(Authentication::Session));
+        }
+        match JwtKeys::from_config(&config) {
+            Ok(keys) => Ok(rocket
+                .manage(Authentication::Jwt(Box::new(keys)))
+                .manage(RolePermissions::default())),
+            Err(e) => {
+                rocket::error!("Invalid JWT configuration: {}", e);
+                Err(rocket)
+            }
+        }
+    })
+}
+
+fn denied_key(jti: &str) -> String {
+    format!("revoked_tokens/{}", jti)
+}
+
+fn cutoff_key(user_id: i32) -> String {
+    format!("users/{}/tokens_revoked_at", user_id)
+}
+
+fn kept_key(user_id: i32) -> String {
+    format!("users/{}/token_kept", user_id)
+}
+
+/// Puts a token on the deny list until it would have expired anyway.
+pub async fn deny<C: ConnectionLike + Send>(cache: &mut C, claims: &Claims) -> RedisResult<()> {
+    let remaining = (claims.exp - Utc::now().timestamp()).max(1) as u64;
+    cache.set_ex(denied_key(&claims.jti), 1, remaining).await
+}
+
+/// Revokes every token issued to a user so far, for as long as any of them could last,
+/// given how many seconds tokens last. Tokens issued afterwards, even within the same
+/// second, stay valid, as does the token with the `keep` id if given.
+pub async fn deny_all<C: ConnectionLike + Send>(
+    cache: &mut C,
+    user_id: i32,
+    ttl: u64,
+    keep: Option<&str>,
+) -> RedisResult<()> {
+    match keep {
+        Some(jti) => {
+            cache
+                .set_ex::<_, _, ()>(kept_key(user_id), jti, ttl)
+                .await?
+        }
+        None => cache.del::<_, ()>(kept_key(user_id)).await?,
+    }
+    cache
+        .set_ex(cutoff_key(user_id), Utc::now().timestamp_millis(), ttl)
+        .await
+}
+
+/// Whether a token was denied on its own or along with all of its user's tokens.
+pub async fn is_denied<C: ConnectionLike + Send>(
+    cache: &mut C,
+    claims: &Claims,
+) -> RedisResult<bool> {
+    let Ok(user_id) = claims.sub.parse::<i32>() else {
+        return Ok(true);
+    };
+    let (denied, cutoff, kept): (Option<i32>, Option<i64>, Option<String>) = cache
+        .mget(&[
+            denied_key(&claims.jti),
+            cutoff_key(user_id),
+            kept_key(user_id),
+        ])
+        .await?;
+    Ok(denied_by(claims, denied.is_some(), cutoff, kept.as_deref()))
+}
+
+/// Whether a token is denied, given whether it was denied on its own, when all of its
+/// user's tokens were last revoked (in milliseconds) and which token that spared.
+fn denied_by(claims: &Claims, denied: bool, cutoff: Option<i64>, kept: Option<&str>) -> bool {
+    denied
+        || (cutoff.is_some_and(|cutoff| claims.iat_ms < cutoff)
+            && kept != Some(claims.jti.as_str()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+    use base64::Engine;
+
+    fn keys() -> JwtKeys {
+        JwtKeys::from_config(&JwtConfig {
+            auth_mode: AuthMode::Jwt,
+            jwt_secret: Some("secret".to_string()),
+            ..JwtConfig::default()
+        })
+        .unwrap()
+    }
+
+    fn user() -> User {
+        User {
+            id: 7,
+            username: "ferris".to_string(),
+            password: "hash".to_string(),
+            created_at: Utc::now().naive_utc(),
+        }
+    }
+
+    fn claims(iat_ms: i64, exp: i64) -> Claims {
+        Claims {
+            sub: "7".to_string(),
+            name: "ferris".to_string(),
+            roles: vec![RoleCode::Editor],
+            created_at: Utc::now().naive_utc(),
+            jti: "token".to_string(),
+            iat: iat_ms / 1000,
+            iat_ms,
+            exp,
+        }
+    }
+
+    #[test]
+    fn verifies_issued_tokens() {
+        let keys = keys();
+        let token = keys.issue(&user(), vec![RoleCode::Editor]).unwrap();
+        let claims = keys.verify(&token).unwrap();
+        assert_eq!(claims.user().unwrap().id, 7);
+        assert_eq!(claims.name, "ferris");
+        assert_eq!(claims.roles, vec![RoleCode::Editor]);
+        assert_eq!(claims.exp - claims.iat, keys.ttl() as i64);
+    }
+
+    #[test]
+    fn rejects_tampered_tokens() {
+        let keys = keys();
+        let token = keys.issue(&user(), vec![RoleCode::Viewer]).unwrap();
+        let parts: Vec<&str> = token.split('.').collect();
+        let payload = String::from_utf8(URL_SAFE_NO_PAD.decode(parts[1]).unwrap()).unwrap();
+        let payload = payload.replace("\"viewer\"", "\"admin\"");
+        let tampered = format!(
+            "{}.{}.{}",
+            parts[0],
+            URL_SAFE_NO_PAD.encode(payload),
+            parts[2]
+        );
+        assert!(keys.verify(&tampered).is_none());
+
+        let other_keys = JwtKeys::from_config(&JwtConfig {
+            jwt_secret: Some("another secret".to_string()),
+            ..JwtConfig::default()
+        })
+        .unwrap();
+        let forged = other_keys.issue(&user(), vec![RoleCode::Admin]).unwrap();
+        assert!(keys.verify(&forged).is_none());
+    }
+
+    #[test]
+    fn rejects_expired_tokens() {
+        let keys = keys();
+        let now = Utc::now();
+        // Past the leeway the validation allows for clock skew.
+        let expired = claims(now.timestamp_millis() - 600_000, now.timestamp() - 300);
+        let token = jsonwebtoken::encode(&keys.header, &expired, &keys.encoding).unwrap();
+        assert!(keys.verify(&token).is_none());
+    }
+
+    #[test]
+    fn rejects_other_algorithms() {
+        let keys = keys();
+        let now = Utc::now();
+        let claims = claims(now.timestamp_millis(), now.timestamp() + 60);
+        let token = jsonwebtoken::encode(
+            &Header::new(Algorithm::HS384),
+            &claims,
+            &EncodingKey::from_secret(b"secret"),
+        )
+        .unwrap();
+        assert!(keys.verify(&token).is_none());
+    }
+
+    #[test]
+    fn denies_tokens_on_their_own() {
+        let claims = claims(1_000_500, 2_000);
+        assert!(denied_by(&claims, true, None, None));
+        // Keeping a token through revoking all others does not undo denying it.
+        assert!(denied_by(&claims, true, Some(1_000_000), Some("token")));
+        assert!(!denied_by(&claims, false, None, None));
+    }
+
+    #[test]
+    fn denies_tokens_issued_before_the_cutoff() {
+        let claims = claims(1_000_500, 2_000);
+        // Within the same second, only tokens issued before the millisecond are denied.
+        assert!(denied_by(&claims, false, Some(1_000_501), None));
+        assert!(!denied_by(&claims, false, Some(1_000_500), None));
+        assert!(!denied_by(&claims, false, Some(1_000_000), None));
+    }
+
+    #[test]
+    fn spares_the_kept_token() {
+        let claims = claims(1_000_500, 2_000);
+        assert!(!denied_by(&claims, false, Some(1_000_501), Some("token")));
+        assert!(denied_by(&claims, false, Some(1_000_501), Some("other")));
+    }
+}
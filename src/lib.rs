@@ -1,9 +1,19 @@
 mod auth;
+mod bulk;
 pub mod commands;
+mod filtering;
+pub mod jwt;
+pub mod lockout;
 mod macros;
 mod mail;
+mod merge_patch;
 mod models;
+mod pagination;
+pub mod password;
+mod permissions;
 mod repositories;
 mod responses;
 pub mod rocket_routes;
 mod schema;
+pub mod sessions;
+mod validation;
@@ -0,0 +1,176 @@
+use rocket_db_pools::deadpool_redis::redis::aio::ConnectionLike;
+use rocket_db_pools::deadpool_redis::redis::{AsyncCommands, RedisResult};
+use serde::Deserialize;
+
+/// Limits on failed logins, read from the Rocket configuration, e.g.
+/// `ROCKET_LOGIN_LOCKOUT_AFTER=5`.
+///
+/// Failures are counted per username and per client IP, and password reset requests
+/// per email address and, apart from failed logins, per client IP. Both thresholds count the failure that
+/// reaches them: from the backoff threshold on, every failure makes the next attempt
+/// wait twice as long as the one before; once the lockout threshold is reached, no
+/// attempt is allowed until the lockout ends.
+#[derive(Clone, Deserialize)]
+#[serde(default)]
+pub struct LockoutConfig {
+    /// Failures for a username before attempts have to wait.
+    pub login_backoff_after: u32,
+    /// Failures for a username before it is locked out.
+    pub login_lockout_after: u32,
+    /// Failures from an IP address, for any usernames, before attempts have to wait.
+    pub login_ip_backoff_after: u32,
+    /// Failures from an IP address before it is locked out.
+    pub login_ip_lockout_after: u32,
+    /// Seconds to wait after the failure reaching a backoff threshold.
+    pub login_backoff_base: u64,
+    /// Seconds a lockout lasts, and failures are remembered for after the last one.
+    pub login_lockout_duration: u64,
+}
+
+impl Default for LockoutConfig {
+    fn default() -> Self {
+        Self {
+            login_backoff_after: 3,
+            login_lockout_after: 10,
+            login_ip_backoff_after: 50,
+            login_ip_lockout_after: 250,
+            login_backoff_base: 1,
+            login_lockout_duration: 15 * 60,
+        }
+    }
+}
+
+/// The kinds of attempts that are limited, each counted apart from the other.
+#[derive(Clone, Copy)]
+pub enum Attempt {
+    Login,
+    PasswordReset,
+}
+
+/// What attempts are counted against.
+#[derive(Clone, Copy)]
+pub enum Subject<'a> {
+    /// Failed logins for a username.
+    Username(&'a str),
+    /// Password reset requests for an email address, limited like a username.
+    Email(&'a str),
+    /// Attempts of one kind from a client IP.
+    Ip(Attempt, &'a str),
+}
+
+impl Subject<'_> {
+    fn attempt(&self) -> Attempt {
+        match self {
+            Subject::Username(_) => Attempt::Login,
+            Subject::Email(_) => Attempt::PasswordReset,
+            Subject::Ip(attempt, _) => *attempt,
+        }
+    }
+
+    fn key_suffix(&self) -> String {
+        match self {
+            Subject::Username(username) => format!("users/{}", username),
+            Subject::Email(email) => format!("emails/{}", email),
+            Subject::Ip(_, ip) => format!("ips/{}", ip),
+        }
+    }
+
+    fn failures_key(&self) -> String {
+        let prefix = match self.attempt() {
+            Attempt::Login => "login_failures",
+            Attempt::PasswordReset => "password_reset_requests",
+        };
+        format!("{}/{}", prefix, self.key_suffix())
+    }
+
+    /// Present for as long as attempts have to wait, expiring when they may resume.
+    fn blocked_key(&self) -> String {
+        let prefix = match self.attempt() {
+            Attempt::Login => "login_blocked",
+            Attempt::PasswordReset => "password_reset_blocked",
+        };
+        format!("{}/{}", prefix, self.key_suffix())
+    }
+
+    /// How many failures lead to backoff and to a lockout.
+    fn limits(&self, config: &LockoutConfig) -> (u32, u32) {
+        match self {
+            Subject::Username(_) | Subject::Email(_) => {
+                (config.login_backoff_after, config.login_lockout_after)
+            }
+            Subject::Ip(..) => (config.login_ip_backoff_after, config.login_ip_lockout_after),
+        }
+    }
+}
+
+/// The subjects of an attempt: its account, by username or email address, and, when
+/// known, its client IP for the same kind of attempt.
+fn subjects<'a>(account: Subject<'a>, ip: Option<&'a str>) -> Vec<Subject<'a>> {
+    let mut subjects = vec![account];
+    subjects.extend(ip.map(|ip| Subject::Ip(account.attempt(), ip)));
+    subjects
+}
+
+/// Seconds until an attempt on an account may be made, if it has to wait at all.
+pub async fn retry_after<C: ConnectionLike + Send>(
+    cache: &mut C,
+    account: Subject<'_>,
+    ip: Option<&str>,
+) -> RedisResult<Option<u64>> {
+    let mut wait = None;
+    for subject in subjects(account, ip) {
+        let ttl: i64 = cache.ttl(subject.blocked_key()).await?;
+        // A key without an expiry (-1) should not exist; it blocks for a second.
+        let seconds = match ttl {
+            -2 => continue,
+            ttl => ttl.max(1) as u64,
+        };
+        wait = wait.max(Some(seconds));
+    }
+    Ok(wait)
+}
+
+/// Counts a failed attempt on an account, making further attempts wait or locking them
+/// out as the limits are reached. Evaluates to the subjects this failure locked out.
+pub async fn record_failure<'a, C: ConnectionLike + Send>(
+    cache: &mut C,
+    config: &LockoutConfig,
+    account: Subject<'a>,
+    ip: Option<&'a str>,
+) -> RedisResult<Vec<Subject<'a>>> {
+    let mut locked = Vec::new();
+    for subject in subjects(account, ip) {
+        let failures: u32 = cache.incr(subject.failures_key(), 1).await?;
+        cache
+            .expire::<_, ()>(subject.failures_key(), config.login_lockout_duration as i64)
+            .await?;
+
+        let (backoff_after, lockout_after) = subject.limits(config);
+        let wait = if failures >= lockout_after {
+            locked.push(subject);
+            config.login_lockout_duration
+        } else if failures >= backoff_after {
+            let doublings = (failures - backoff_after).min(63);
+            config
+                .login_backoff_base
+                .saturating_mul(1 << doublings)
+                .min(config.login_lockout_duration)
+        } else {
+            continue;
+        };
+        cache
+            .set_ex::<_, _, ()>(subject.blocked_key(), 1, wait.max(1))
+            .await?;
+    }
+    Ok(locked)
+}
+
+/// Forgets the failures of a username, as after logging in or being unlocked.
+/// Evaluates to `false` when there were none.
+pub async fn clear<C: ConnectionLike + Send>(cache: &mut C, username: &str) -> RedisResult<bool> {
+    let subject = Subject::Username(username);
+    let removed: usize = cache
+        .del(&[subject.failures_key(), subject.blocked_key()])
+        .await?;
+    Ok(removed > 0)
+}
@@ -1,3 +1,15 @@
+/// Generates the list, view, create, update (`PUT`, full replacement), patch
+/// (`PATCH` with a JSON Merge Patch), restore and delete handlers for a resource,
+/// along with bulk create, patch and delete handlers under `/bulk`.
+/// Leave out the two delete handlers' names, and the delete permission, to write those by hand.
+///
+/// Reading only takes an authenticated user; the other handlers require the
+/// permissions named in `permissions(write: .., restore: .., delete: .., owner: .., replace: ..)`.
+/// The optional `owner` check, an `async fn(conn, &User, &T) -> QueryResult<bool>` taking
+/// both the model and the new model, further limits who may create, update or delete a
+/// given row; others get a 403. Updates check the row as it is and as it would become.
+/// The optional `replace` check, an `async fn(conn, &User, &T, &NewT) -> QueryResult<Result<(), Custom<Value>>>`,
+/// vets an update against the row it replaces and answers with its own error.
 #[macro_export]
 macro_rules! crud_handlers {
     (
@@ -6,11 +18,150 @@ macro_rules! crud_handlers {
         $repo:ty,
         $new_model:ty,
         $update_model:ty,
+        permissions(
+            write: $write_permission:ty,
+            restore: $restore_permission:ty,
+            delete: $delete_permission:ty
+            $(, owner: $owner_check:path)?
+            $(, replace: $replace_check:path)?
+        ),
         $get_all_fn:ident,
         $view_fn:ident,
         $create_fn:ident,
         $update_fn:ident,
-        $delete_fn:ident
+        $patch_fn:ident,
+        $restore_fn:ident,
+        $bulk_create_fn:ident,
+        $bulk_patch_fn:ident,
+        $delete_fn:ident,
+        $bulk_delete_fn:ident
+    ) => {
+        $crate::crud_handlers!(
+            $single_str,
+            $plural_str,
+            $repo,
+            $new_model,
+            $update_model,
+            permissions(
+                write: $write_permission,
+                restore: $restore_permission
+                $(, owner: $owner_check)?
+                $(, replace: $replace_check)?
+            ),
+            $get_all_fn,
+            $view_fn,
+            $create_fn,
+            $update_fn,
+            $patch_fn,
+            $restore_fn,
+            $bulk_create_fn,
+            $bulk_patch_fn
+        );
+
+        /// Deletes a row, honouring `If-Match` and the owner check. A missing row counts
+        /// as deleted. The inner error rejects the request without touching the row.
+        async fn delete_in(
+            conn: &mut diesel_async::AsyncPgConnection,
+            id: i32,
+            if_match: &$crate::rocket_routes::IfMatch,
+            user: &$crate::models::User,
+        ) -> diesel::QueryResult<HandlerResult<()>> {
+            let before = diesel::OptionalExtension::optional(<$repo>::find_for_update(conn, id).await)?;
+            let etag = before.as_ref().map(|item| $crate::responses::etag(item.id, item.updated_at));
+            if !if_match.matches(etag.as_deref()) {
+                return Ok(Err($crate::responses::precondition_failed()));
+            }
+            if let Some(before) = before {
+                $(
+                    if !$owner_check(conn, user, &before).await? {
+                        return Ok(Err(Custom(
+                            Status::Forbidden,
+                            json!({ "error": format!("Not allowed to change this {}", $single_str) }),
+                        )));
+                    }
+                )?
+                <$repo>::delete(conn, id).await?;
+                record_audit(conn, user, $crate::models::AuditAction::Delete, id, Some(json!(before)), None).await?;
+            }
+            Ok(Ok(()))
+        }
+
+        fn delete_error(id: i32, e: diesel::result::Error) -> Custom<Value> {
+            $crate::responses::handle_db_error(
+                e,
+                format!("Failed to delete {} with id {}", $single_str, id),
+                format!("deleting {}", $single_str),
+            )
+        }
+
+        #[rocket::delete("/<id>")]
+        pub async fn $delete_fn(
+            mut db: Db,
+            id: i32,
+            if_match: $crate::rocket_routes::IfMatch,
+            user: $crate::rocket_routes::Require<$delete_permission>,
+        ) -> HandlerResult<NoContent> {
+            let user = user.0;
+            db.transaction(|conn| delete_in(conn, id, &if_match, &user).scope_boxed())
+                .await
+                .map_err(|e| delete_error(id, e))?
+                .map(|_| NoContent)
+        }
+        #[rocket::post("/bulk/delete?<mode>", format = "json", data = "<ids>")]
+        pub async fn $bulk_delete_fn(
+            mut db: Db,
+            mode: Option<String>,
+            ids: Json<Vec<i32>>,
+            user: $crate::rocket_routes::Require<$delete_permission>,
+        ) -> HandlerResult<Custom<Value>> {
+            let mode = $crate::bulk::BulkMode::from_query(mode.as_deref())
+                .map_err($crate::responses::invalid_query_error)?;
+            let ids = $crate::bulk::check_size(ids.into_inner())?;
+
+            let user = user.0;
+            let outcome = db.transaction(|conn| {
+                async move {
+                    let no_precondition = $crate::rocket_routes::IfMatch::default();
+                    let mut outcomes = Vec::with_capacity(ids.len());
+                    for id in ids {
+                        // Each item gets a savepoint, so a failed one leaves the rest intact.
+                        let outcome = conn
+                            .transaction(|c| delete_in(c, id, &no_precondition, &user).scope_boxed())
+                            .await
+                            .map_err(|e| delete_error(id, e))
+                            .and_then(|deleted| deleted)
+                            .map(|_| None);
+                        outcomes.push(outcome);
+                    }
+                    mode.finish(Status::NoContent, outcomes)
+                }
+                .scope_boxed()
+            })
+                .await;
+
+            $crate::bulk::respond(outcome, &format!("deleting {}", $plural_str))
+        }
+    };
+    (
+        $single_str:literal,
+        $plural_str:literal,
+        $repo:ty,
+        $new_model:ty,
+        $update_model:ty,
+        permissions(
+            write: $write_permission:ty,
+            restore: $restore_permission:ty
+            $(, owner: $owner_check:path)?
+            $(, replace: $replace_check:path)?
+        ),
+        $get_all_fn:ident,
+        $view_fn:ident,
+        $create_fn:ident,
+        $update_fn:ident,
+        $patch_fn:ident,
+        $restore_fn:ident,
+        $bulk_create_fn:ident,
+        $bulk_patch_fn:ident
     ) => {
         use rocket::{
             http::Status,
@@ -18,95 +169,360 @@ macro_rules! crud_handlers {
             serde::json::{Json, Value, json},
         };
         use rocket_db_pools::Connection;
+        use diesel_async::{AsyncConnection, scoped_futures::ScopedFutureExt};
 
         type HandlerResult<T> = Result<T, Custom<Value>>;
-        type Db = Connection<crate::rocket_routes::DbConn>;
+        type Db = Connection<$crate::rocket_routes::DbConn>;
+
+        /// Appends an entry about a change to one of this resource's rows to the audit log.
+        async fn record_audit(
+            c: &mut diesel_async::AsyncPgConnection,
+            user: &$crate::models::User,
+            action: $crate::models::AuditAction,
+            resource_id: i32,
+            before: Option<Value>,
+            after: Option<Value>,
+        ) -> diesel::QueryResult<()> {
+            let entry = $crate::models::NewAuditEntry {
+                user_id: Some(user.id),
+                action,
+                resource: $plural_str.to_string(),
+                resource_id,
+                before,
+                after,
+            };
+            $crate::repositories::AuditLogRepository::create(c, entry).await.map(|_| ())
+        }
 
         fn map_foreign_key_error(e: diesel::result::Error, default: impl FnOnce(diesel::result::Error) -> Custom<Value>) -> Custom<Value> {
             match e {
                 diesel::result::Error::DatabaseError(
                     diesel::result::DatabaseErrorKind::ForeignKeyViolation,
                     _,
-                ) => Custom(Status::NotFound, json!({ "error": "Not Found" })),
+                ) => $crate::responses::not_found(),
+                e => default(e),
+            }
+        }
+
+        fn map_unique_error(e: diesel::result::Error, default: impl FnOnce(diesel::result::Error) -> Custom<Value>) -> Custom<Value> {
+            match e {
+                diesel::result::Error::DatabaseError(
+                    diesel::result::DatabaseErrorKind::UniqueViolation,
+                    info,
+                ) => $crate::responses::unique_violation_error(info.as_ref()),
                 e => default(e),
             }
         }
 
-        #[rocket::get("/", rank = 1)]
-        pub async fn $get_all_fn(mut db: Db, _user: crate::models::User) -> HandlerResult<Value> {
-            <$repo>::find_multiple(&mut db, 100)
+        #[rocket::get("/?<limit>&<offset>&<cursor>&<sort>&<filters..>", rank = 1)]
+        pub async fn $get_all_fn(
+            mut db: Db,
+            limit: Option<String>,
+            offset: Option<String>,
+            cursor: Option<String>,
+            sort: Option<String>,
+            filters: std::collections::HashMap<String, String>,
+            _user: $crate::models::User,
+        ) -> HandlerResult<Value> {
+            let pagination =
+                $crate::pagination::Pagination::from_query(limit.as_deref(), offset.as_deref(), cursor.as_deref())
+                    .map_err($crate::responses::invalid_query_error)?;
+            let params = $crate::filtering::ListParams::from_query(filters, sort.as_deref())
+                .map_err($crate::responses::invalid_query_error)?;
+
+            <$repo>::find_multiple(&mut db, pagination, &params)
                 .await
-                .map(|items| json!(items))
-                .map_err(|e| {
-                    crate::responses::handle_db_error(
-                        e,
-                        format!("Failed to fetch {}", $plural_str),
-                        format!("fetching {}", $plural_str),
-                    )
+                .map(|page| json!(page))
+                .map_err(|e| match e {
+                    $crate::filtering::ListError::Query(e) => {
+                        $crate::responses::invalid_query_error(e)
+                    }
+                    $crate::filtering::ListError::Database(e) => {
+                        $crate::responses::handle_db_error(
+                            e,
+                            format!("Failed to fetch {}", $plural_str),
+                            format!("fetching {}", $plural_str),
+                        )
+                    }
                 })
         }
         #[rocket::get("/<id>")]
-        pub async fn $view_fn(mut db: Db, id: i32, _user: crate::models::User) -> HandlerResult<Value> {
+        pub async fn $view_fn(
+            mut db: Db,
+            id: i32,
+            _user: $crate::models::User,
+        ) -> HandlerResult<$crate::responses::WithETag<Value>> {
             <$repo>::find(&mut db, id)
                 .await
-                .map(|item| json!(item))
+                .map(|item| $crate::responses::WithETag {
+                    etag: $crate::responses::etag(item.id, item.updated_at),
+                    inner: json!(item),
+                })
                 .map_err(|e| match e {
                     diesel::result::Error::NotFound => {
-                        Custom(Status::NotFound, json!({ "error": "Not Found" }))
+                        $crate::responses::not_found()
                     }
-                    _ => crate::responses::handle_db_error(
+                    _ => $crate::responses::handle_db_error(
                         e,
                         format!("Failed to fetch {} with id {}", $single_str, id),
                         format!("fetching {}", $single_str),
                     ),
                 })
         }
+        async fn insert_item(
+            conn: &mut diesel_async::AsyncPgConnection,
+            user: &$crate::models::User,
+            data: $new_model,
+        ) -> diesel::QueryResult<HandlerResult<Value>> {
+            $(
+                if !$owner_check(conn, user, &data).await? {
+                    return Ok(Err(Custom(
+                        Status::Forbidden,
+                        json!({ "error": format!("Not allowed to create this {}", $single_str) }),
+                    )));
+                }
+            )?
+            let item = <$repo>::create(conn, data).await?;
+            record_audit(conn, user, $crate::models::AuditAction::Create, item.id, None, Some(json!(item))).await?;
+            Ok(Ok(json!(item)))
+        }
+
+        fn create_error(e: diesel::result::Error) -> Custom<Value> {
+            map_foreign_key_error(e, |e| map_unique_error(e, |e| {
+                $crate::responses::handle_db_error(
+                    e,
+                    format!("Failed to create {}", $single_str),
+                    format!("creating {}", $single_str),
+                )
+            }))
+        }
+
         #[rocket::post("/", format = "json", data = "<data>")]
         pub async fn $create_fn(
             mut db: Db,
             data: Json<$new_model>,
-            _user: crate::rocket_routes::EditorUser,
+            user: $crate::rocket_routes::Require<$write_permission>,
         ) -> HandlerResult<Custom<Value>> {
-            <$repo>::create(&mut db, data.into_inner())
+            let data = data.into_inner();
+            $crate::validation::Validate::validate(&data)
+                .map_err($crate::responses::validation_error)?;
+
+            let user = user.0;
+            db.transaction(|conn| insert_item(conn, &user, data).scope_boxed())
                 .await
-                .map(|item| Custom(Status::Created, json!(item)))
-                .map_err(|e| map_foreign_key_error(e, |e| {
-                    crate::responses::handle_db_error(
+                .map_err(create_error)?
+                .map(|item| Custom(Status::Created, item))
+        }
+        /// Replaces a row with the model `replacement` builds from the row's current JSON,
+        /// honouring `If-Match` and recording the change in the audit log.
+        /// The inner error rejects the request without touching the row.
+        async fn replace_in(
+            conn: &mut diesel_async::AsyncPgConnection,
+            id: i32,
+            if_match: &$crate::rocket_routes::IfMatch,
+            user: &$crate::models::User,
+            replacement: impl FnOnce(Value) -> Result<$new_model, Custom<Value>> + Send,
+        ) -> diesel::QueryResult<HandlerResult<$crate::responses::WithETag<Value>>> {
+            let before = <$repo>::find_for_update(conn, id).await?;
+            if !if_match.matches(Some(&$crate::responses::etag(before.id, before.updated_at))) {
+                return Ok(Err($crate::responses::precondition_failed()));
+            }
+            $(
+                if !$owner_check(conn, user, &before).await? {
+                    return Ok(Err(Custom(
+                        Status::Forbidden,
+                        json!({ "error": format!("Not allowed to change this {}", $single_str) }),
+                    )));
+                }
+            )?
+            let data = match replacement(json!(before)) {
+                Ok(data) => data,
+                Err(e) => return Ok(Err(e)),
+            };
+            $(
+                if !$owner_check(conn, user, &data).await? {
+                    return Ok(Err(Custom(
+                        Status::Forbidden,
+                        json!({ "error": format!("Not allowed to change this {}", $single_str) }),
+                    )));
+                }
+            )?
+            $(
+                if let Err(e) = $replace_check(conn, user, &before, &data).await? {
+                    return Ok(Err(e));
+                }
+            )?
+            let item = <$repo>::update(conn, id, <$update_model>::from(data)).await?;
+            record_audit(conn, user, $crate::models::AuditAction::Update, id, Some(json!(before)), Some(json!(item))).await?;
+            Ok(Ok($crate::responses::WithETag {
+                etag: $crate::responses::etag(item.id, item.updated_at),
+                inner: json!(item),
+            }))
+        }
+
+        fn update_error(id: i32, e: diesel::result::Error) -> Custom<Value> {
+            match e {
+                diesel::result::Error::NotFound => {
+                    $crate::responses::not_found()
+                }
+                e => map_foreign_key_error(e, |e| map_unique_error(e, |e| {
+                    $crate::responses::handle_db_error(
                         e,
-                        format!("Failed to create {}", $single_str),
-                        format!("creating {}", $single_str),
+                        format!("Failed to update {} with id {}", $single_str, id),
+                        format!("updating {}", $single_str),
                     )
-                }))
+                })),
+            }
         }
+
+        /// Applies a JSON Merge Patch to a row's current JSON and validates the result.
+        fn apply_patch(mut current: Value, patch: &Value) -> Result<$new_model, Custom<Value>> {
+            $crate::merge_patch::apply(&mut current, patch);
+            let data: $new_model = rocket::serde::json::serde_json::from_value(current)
+                .map_err(|e| Custom(
+                    Status::UnprocessableEntity,
+                    json!({ "error": "Invalid patch", "message": e.to_string() }),
+                ))?;
+            $crate::validation::Validate::validate(&data)
+                .map_err($crate::responses::validation_error)?;
+            Ok(data)
+        }
+
         #[rocket::put("/<id>", format = "json", data = "<data>")]
         pub async fn $update_fn(
             mut db: Db,
             id: i32,
-            data: Json<$update_model>,
-            _user: crate::rocket_routes::EditorUser,
-        ) -> HandlerResult<Value> {
-            <$repo>::update(&mut db, id, data.into_inner())
+            data: Json<$new_model>,
+            if_match: $crate::rocket_routes::IfMatch,
+            user: $crate::rocket_routes::Require<$write_permission>,
+        ) -> HandlerResult<$crate::responses::WithETag<Value>> {
+            let data = data.into_inner();
+            $crate::validation::Validate::validate(&data)
+                .map_err($crate::responses::validation_error)?;
+
+            let user = user.0;
+            db.transaction(|conn| replace_in(conn, id, &if_match, &user, |_| Ok(data)).scope_boxed())
                 .await
-                .map(|item| json!(item))
-                .map_err(|e| map_foreign_key_error(e, |e| {
-                    crate::responses::handle_db_error(
-                        e,
-                        format!("Failed to update {} with id {}", $single_str, id),
-                        format!("updating {}", $single_str),
-                    )
-                }))
+                .map_err(|e| update_error(id, e))?
         }
-        #[rocket::delete("/<id>")]
-        pub async fn $delete_fn(mut db: Db, id: i32, _user: crate::rocket_routes::EditorUser) -> HandlerResult<NoContent> {
-            <$repo>::delete(&mut db, id)
+        #[rocket::patch("/<id>", format = "application/merge-patch+json", data = "<patch>")]
+        pub async fn $patch_fn(
+            mut db: Db,
+            id: i32,
+            patch: Json<Value>,
+            if_match: $crate::rocket_routes::IfMatch,
+            user: $crate::rocket_routes::Require<$write_permission>,
+        ) -> HandlerResult<$crate::responses::WithETag<Value>> {
+            let patch = patch.into_inner();
+
+            let user = user.0;
+            db.transaction(|conn| {
+                replace_in(conn, id, &if_match, &user, |current| apply_patch(current, &patch)).scope_boxed()
+            })
                 .await
-                .map(|_| NoContent)
-                .map_err(|e| {
-                    crate::responses::handle_db_error(
-                        e,
-                        format!("Failed to delete {} with id {}", $single_str, id),
-                        format!("deleting {}", $single_str),
-                    )
+                .map_err(|e| update_error(id, e))?
+        }
+        #[rocket::post("/bulk?<mode>", format = "json", data = "<items>")]
+        pub async fn $bulk_create_fn(
+            mut db: Db,
+            mode: Option<String>,
+            items: Json<Vec<$new_model>>,
+            user: $crate::rocket_routes::Require<$write_permission>,
+        ) -> HandlerResult<Custom<Value>> {
+            let mode = $crate::bulk::BulkMode::from_query(mode.as_deref())
+                .map_err($crate::responses::invalid_query_error)?;
+            let items = $crate::bulk::check_size(items.into_inner())?;
+
+            let user = user.0;
+            let outcome = db.transaction(|conn| {
+                async move {
+                    let mut outcomes = Vec::with_capacity(items.len());
+                    for data in items {
+                        let outcome = match $crate::validation::Validate::validate(&data) {
+                            Err(errors) => Err($crate::responses::validation_error(errors)),
+                            // Each item gets a savepoint, so a failed one leaves the rest intact.
+                            Ok(()) => conn
+                                .transaction(|c| insert_item(c, &user, data).scope_boxed())
+                                .await
+                                .map_err(create_error)
+                                .and_then(|created| created)
+                                .map(Some),
+                        };
+                        outcomes.push(outcome);
+                    }
+                    mode.finish(Status::Created, outcomes)
+                }
+                .scope_boxed()
+            })
+                .await;
+
+            $crate::bulk::respond(outcome, &format!("creating {}", $plural_str))
+        }
+        #[rocket::patch("/bulk?<mode>", format = "json", data = "<patches>")]
+        pub async fn $bulk_patch_fn(
+            mut db: Db,
+            mode: Option<String>,
+            patches: Json<Vec<$crate::bulk::ItemPatch>>,
+            user: $crate::rocket_routes::Require<$write_permission>,
+        ) -> HandlerResult<Custom<Value>> {
+            let mode = $crate::bulk::BulkMode::from_query(mode.as_deref())
+                .map_err($crate::responses::invalid_query_error)?;
+            let patches = $crate::bulk::check_size(patches.into_inner())?;
+
+            let user = user.0;
+            let outcome = db.transaction(|conn| {
+                async move {
+                    let no_precondition = $crate::rocket_routes::IfMatch::default();
+                    let mut outcomes = Vec::with_capacity(patches.len());
+                    for $crate::bulk::ItemPatch { id, patch } in patches {
+                        // Each item gets a savepoint, so a failed one leaves the rest intact.
+                        let outcome = conn
+                            .transaction(|c| {
+                                replace_in(c, id, &no_precondition, &user, |current| apply_patch(current, &patch))
+                                    .scope_boxed()
+                            })
+                            .await
+                            .map_err(|e| update_error(id, e))
+                            .and_then(|updated| updated)
+                            .map(|updated| Some(updated.inner));
+                        outcomes.push(outcome);
+                    }
+                    mode.finish(Status::Ok, outcomes)
+                }
+                .scope_boxed()
+            })
+                .await;
+
+            $crate::bulk::respond(outcome, &format!("updating {}", $plural_str))
+        }
+        #[rocket::post("/<id>/restore")]
+        pub async fn $restore_fn(
+            mut db: Db,
+            id: i32,
+            user: $crate::rocket_routes::Require<$restore_permission>,
+        ) -> HandlerResult<Value> {
+            let user = user.0;
+            db.transaction(|conn| {
+                async move {
+                    let item = <$repo>::restore(conn, id).await?;
+                    record_audit(conn, &user, $crate::models::AuditAction::Restore, id, None, Some(json!(item))).await?;
+                    Ok(item)
+                }
+                .scope_boxed()
+            })
+                .await
+                .map(|item| json!(item))
+                .map_err(|e| match e {
+                    diesel::result::Error::NotFound => {
+                        $crate::responses::not_found()
+                    }
+                    e => map_unique_error(e, |e| {
+                        $crate::responses::handle_db_error(
+                            e,
+                            format!("Failed to restore {} with id {}", $single_str, id),
+                            format!("restoring {}", $single_str),
+                        )
+                    }),
                 })
         }
     };
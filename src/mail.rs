@@ -3,6 +3,7 @@ use lettre::message::MessageBuilder;
 use lettre::transport::smtp::authentication::Credentials;
 use lettre::transport::smtp::response::Response;
 use lettre::{SmtpTransport, Transport};
+use std::env::{self, VarError};
 use std::error::Error;
 use tera::{Context, Tera};
 
@@ -81,6 +82,7 @@ impl Default for HtmlMailerBuilder {
 }
 
 impl HtmlMailerBuilder {
+    #[allow(dead_code)]
     pub fn default_subject(mut self, subject: String) -> Self {
         self.default_subject = subject;
         self
@@ -114,4 +116,12 @@ impl HtmlMailerBuilder {
         self.smtp_password = Some(smtp_password);
         self
     }
+
+    /// Takes the SMTP settings from `SMTP_HOST`, `SMTP_USERNAME` and `SMTP_PASSWORD`.
+    pub fn smtp_from_env(self) -> Result<Self, VarError> {
+        Ok(self
+            .smtp_host(env::var("SMTP_HOST")?)
+            .smtp_username(env::var("SMTP_USERNAME")?)
+            .smtp_password(env::var("SMTP_PASSWORD")?))
+    }
 }
@@ -0,0 +1,24 @@
+use rocket::serde::json::Value;
+
+/// Applies a JSON Merge Patch (RFC 7396) to `target` in place:
+/// objects are merged recursively, `null` removes a member and anything else replaces it.
+pub fn apply(target: &mut Value, patch: &Value) {
+    let Value::Object(patch) = patch else {
+        *target = patch.clone();
+        return;
+    };
+    if !target.is_object() {
+        *target = Value::Object(Default::default());
+    }
+    let Value::Object(target) = target else {
+        unreachable!()
+    };
+
+    for (key, value) in patch {
+        if value.is_null() {
+            target.remove(key);
+        } else {
+            apply(target.entry(key.as_str()).or_insert(Value::Null), value);
+        }
+    }
+}
@@ -4,19 +4,25 @@ use diesel::deserialize::FromSql;
 use diesel::expression::AsExpression;
 use diesel::pg::{Pg, PgValue};
 use diesel::serialize::ToSql;
-use diesel::sql_types::Text;
+use diesel::sql_types::{Float4, Integer, Text};
 use diesel::{deserialize, deserialize::FromSqlRow, prelude::*};
 use serde::{Deserialize, Serialize};
+use std::fmt;
 use std::io::Write;
 use std::str::FromStr;
 
-#[derive(Queryable, Serialize)]
+#[derive(Queryable, Selectable, Identifiable, Serialize)]
 #[diesel(table_name = rustaceans)]
 pub struct Rustacean {
     pub id: i32,
     pub name: String,
     pub email: String,
     pub created_at: NaiveDateTime,
+    #[serde(skip_serializing)]
+    pub deleted_at: Option<NaiveDateTime>,
+    pub updated_at: NaiveDateTime,
+    /// The user account of the rustacean, who may then edit their crates.
+    pub user_id: Option<i32>,
 }
 
 #[derive(Insertable, Deserialize)]
@@ -26,7 +32,7 @@ pub struct NewRustacean {
     pub email: String,
 }
 
-#[derive(Queryable, Serialize)]
+#[derive(Queryable, QueryableByName, Selectable, Identifiable, Serialize)]
 #[diesel(table_name = crates)]
 pub struct Crate {
     pub id: i32,
@@ -36,6 +42,21 @@ pub struct Crate {
     pub version: String,
     pub description: Option<String>,
     pub created_at: NaiveDateTime,
+    #[serde(skip_serializing)]
+    pub deleted_at: Option<NaiveDateTime>,
+    pub updated_at: NaiveDateTime,
+}
+
+/// A crate matched by full-text search, with its relevance and a highlighted excerpt.
+#[derive(QueryableByName, Serialize)]
+pub struct CrateSearchHit {
+    #[diesel(embed)]
+    #[serde(flatten)]
+    pub krate: Crate,
+    #[diesel(sql_type = Float4)]
+    pub rank: f32,
+    #[diesel(sql_type = Text)]
+    pub snippet: String,
 }
 
 #[derive(Insertable, Deserialize)]
@@ -48,14 +69,120 @@ pub struct NewCrate {
     pub description: Option<String>,
 }
 
+/// What belongs to a rustacean, and so to the user linked to them.
+pub trait RustaceanOwned {
+    fn rustacean_id(&self) -> i32;
+}
+
+impl RustaceanOwned for Crate {
+    fn rustacean_id(&self) -> i32 {
+        self.rustacean_id
+    }
+}
+
+impl RustaceanOwned for NewCrate {
+    fn rustacean_id(&self) -> i32 {
+        self.rustacean_id
+    }
+}
+
+#[derive(Queryable, Selectable, Identifiable, Associations, Serialize)]
+#[diesel(belongs_to(Crate))]
+#[diesel(table_name = crate_versions)]
+pub struct CrateVersion {
+    pub id: i32,
+    pub crate_id: i32,
+    pub version: String,
+    pub changelog: Option<String>,
+    pub yanked: bool,
+    pub published_at: NaiveDateTime,
+}
+
+#[derive(Insertable, Deserialize)]
+#[diesel(table_name = crate_versions)]
+pub struct NewCrateVersion {
+    /// Taken from the request path rather than the body.
+    #[serde(skip_deserializing)]
+    pub crate_id: i32,
+    pub version: String,
+    pub changelog: Option<String>,
+}
+
 #[derive(AsChangeset, Debug, Deserialize)]
+#[diesel(table_name = crate_versions)]
+pub struct UpdateCrateVersion {
+    pub changelog: Option<Option<String>>,
+    pub yanked: Option<bool>,
+}
+
+#[derive(Queryable, Selectable, Identifiable, Serialize)]
+#[diesel(table_name = crate_dependencies)]
+pub struct CrateDependency {
+    pub id: i32,
+    pub crate_id: i32,
+    pub dependency_id: i32,
+    pub requirement: String,
+    pub kind: DependencyKind,
+    pub created_at: NaiveDateTime,
+}
+
+#[derive(Insertable, Deserialize)]
+#[diesel(table_name = crate_dependencies)]
+pub struct NewCrateDependency {
+    /// Taken from the request path rather than the body.
+    #[serde(skip_deserializing)]
+    pub crate_id: i32,
+    pub dependency_id: i32,
+    pub requirement: String,
+    #[serde(default)]
+    pub kind: DependencyKind,
+}
+
+/// One edge of a crate's transitive dependencies, `depth` levels below the root crate.
+#[derive(QueryableByName, Serialize)]
+pub struct DependencyTreeEdge {
+    #[diesel(sql_type = Integer)]
+    pub crate_id: i32,
+    #[diesel(sql_type = Integer)]
+    pub dependency_id: i32,
+    #[diesel(sql_type = Text)]
+    pub requirement: String,
+    #[diesel(sql_type = Text)]
+    pub kind: DependencyKind,
+    #[diesel(sql_type = Integer)]
+    pub depth: i32,
+    #[diesel(sql_type = Text)]
+    pub code: String,
+    #[diesel(sql_type = Text)]
+    pub name: String,
+    #[diesel(sql_type = Text)]
+    pub version: String,
+}
+
+#[derive(AsChangeset, Debug)]
 #[diesel(table_name = rustaceans)]
 pub struct UpdateRustacean {
     pub name: Option<String>,
     pub email: Option<String>,
 }
 
-#[derive(AsChangeset, Debug, Default, Deserialize)]
+/// Replaces every field, as `PUT` and `PATCH` send the complete new state.
+impl From<NewRustacean> for UpdateRustacean {
+    fn from(rustacean: NewRustacean) -> Self {
+        Self {
+            name: Some(rustacean.name),
+            email: Some(rustacean.email),
+        }
+    }
+}
+
+/// The user account to link a rustacean to, or `null` to unlink it.
+#[derive(Deserialize)]
+pub struct RustaceanUser {
+    pub user_id: Option<i32>,
+}
+
+#[derive(AsChangeset, Debug, Default)]
 #[diesel(table_name = crates)]
 pub struct UpdateCrate {
     pub rustacean_id: Option<i32>,
@@ -65,14 +192,51 @@ pub struct UpdateCrate {
     pub description: Option<Option<String>>,
 }
 
-#[derive(Queryable, Debug, Identifiable, Serialize)]
+/// Replaces every field, as `PUT` and `PATCH` send the complete new state.
+impl From<NewCrate> for UpdateCrate {
+    fn from(krate: NewCrate) -> Self {
+        Self {
+            rustacean_id: Some(krate.rustacean_id),
+            name: Some(krate.name),
+            code: Some(krate.code),
+            version: Some(krate.version),
+            description: Some(krate.description),
+        }
+    }
+}
+
+#[derive(Queryable, Selectable, Debug, Identifiable, Serialize)]
 pub struct User {
     pub id: i32,
     pub username: String,
+    /// The Argon2 hash, never sent to clients.
+    #[serde(skip_serializing)]
     pub password: String,
     pub created_at: NaiveDateTime,
 }
 
+/// A user together with the codes of the roles they hold.
+#[derive(Serialize)]
+pub struct UserWithRoles {
+    #[serde(flatten)]
+    pub user: User,
+    pub roles: Vec<RoleCode>,
+}
+
+/// A user created through the API, with the password in plain text.
+#[derive(Deserialize)]
+pub struct NewUserWithRoles {
+    pub username: String,
+    pub password: String,
+    pub roles: Vec<RoleCode>,
+}
+
+/// The full set of roles to give a user.
+#[derive(Deserialize)]
+pub struct UserRoles {
+    pub roles: Vec<RoleCode>,
+}
+
 #[derive(Insertable)]
 #[diesel(table_name=users)]
 pub struct NewUser {
@@ -80,7 +244,7 @@ pub struct NewUser {
     pub password: String,
 }
 
-#[derive(Queryable, Debug, Identifiable)]
+#[derive(Queryable, Selectable, Debug, Identifiable)]
 pub struct Role {
     pub id: i32,
     pub code: RoleCode,
@@ -95,7 +259,7 @@ pub struct NewRole {
     pub name: String,
 }
 
-#[derive(Queryable, Associations, Identifiable, Debug)]
+#[derive(Queryable, Selectable, Associations, Identifiable, Debug)]
 #[diesel(belongs_to(User))]
 #[diesel(belongs_to(Role))]
 #[diesel(table_name = user_roles)]
@@ -111,20 +275,65 @@ pub struct NewUserRole {
     pub role_id: i32,
 }
 
-#[derive(AsExpression, Debug, FromSqlRow, PartialEq, Eq, Hash, Clone)]
+/// A key a user made for machine clients. Only its Argon2 hash is kept; the key
+/// itself, `{prefix}.{secret}`, is shown once on creation.
+#[derive(Queryable, Selectable, Associations, Identifiable, Serialize, Debug)]
+#[diesel(belongs_to(User))]
+#[diesel(table_name = api_keys)]
+pub struct ApiKey {
+    pub id: i32,
+    #[serde(skip_serializing)]
+    pub user_id: i32,
+    pub name: String,
+    /// The public part of the key, to find it by.
+    pub prefix: String,
+    #[serde(skip_serializing)]
+    pub key_hash: String,
+    /// The permission codes the key may use, of those its user holds.
+    pub scopes: Vec<String>,
+    pub expires_at: Option<NaiveDateTime>,
+    pub last_used_at: Option<NaiveDateTime>,
+    pub created_at: NaiveDateTime,
+}
+
+#[derive(Insertable)]
+#[diesel(table_name = api_keys)]
+pub struct NewApiKey {
+    pub user_id: i32,
+    pub name: String,
+    pub prefix: String,
+    pub key_hash: String,
+    pub scopes: Vec<String>,
+    pub expires_at: Option<NaiveDateTime>,
+}
+
+/// The settings of an API key, as sent to create or update one.
+#[derive(Deserialize, AsChangeset)]
+#[diesel(table_name = api_keys)]
+#[diesel(treat_none_as_null = true)]
+pub struct ApiKeySettings {
+    pub name: String,
+    #[serde(default)]
+    pub scopes: Vec<String>,
+    #[serde(default)]
+    pub expires_at: Option<NaiveDateTime>,
+}
+
+#[derive(AsExpression, Debug, FromSqlRow, PartialEq, Eq, Hash, Clone, Serialize, Deserialize)]
 #[diesel(sql_type=Text)]
+#[serde(rename_all = "lowercase")]
 pub enum RoleCode {
     Admin,
     Editor,
     Viewer,
 }
 
-impl ToString for RoleCode {
-    fn to_string(&self) -> String {
+impl fmt::Display for RoleCode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
-            RoleCode::Admin => String::from("admin"),
-            RoleCode::Editor => String::from("editor"),
-            RoleCode::Viewer => String::from("viewer"),
+            RoleCode::Admin => f.write_str("admin"),
+            RoleCode::Editor => f.write_str("editor"),
+            RoleCode::Viewer => f.write_str("viewer"),
         }
     }
 }
@@ -166,3 +375,147 @@ impl ToSql<Text, Pg> for RoleCode {
         Ok(diesel::serialize::IsNull::No)
     }
 }
+
+#[derive(
+    AsExpression, Debug, Default, FromSqlRow, PartialEq, Eq, Clone, Copy, Serialize, Deserialize,
+)]
+#[diesel(sql_type=Text)]
+#[serde(rename_all = "lowercase")]
+pub enum DependencyKind {
+    #[default]
+    Normal,
+    Dev,
+    Build,
+}
+
+impl fmt::Display for DependencyKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DependencyKind::Normal => f.write_str("normal"),
+            DependencyKind::Dev => f.write_str("dev"),
+            DependencyKind::Build => f.write_str("build"),
+        }
+    }
+}
+
+impl FromStr for DependencyKind {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "normal" => Ok(DependencyKind::Normal),
+            "dev" => Ok(DependencyKind::Dev),
+            "build" => Ok(DependencyKind::Build),
+            _ => Err(()),
+        }
+    }
+}
+
+impl FromSql<Text, Pg> for DependencyKind {
+    fn from_sql(value: PgValue<'_>) -> deserialize::Result<Self> {
+        match value.as_bytes() {
+            b"normal" => Ok(DependencyKind::Normal),
+            b"dev" => Ok(DependencyKind::Dev),
+            b"build" => Ok(DependencyKind::Build),
+            _ => Err("Unrecognized enum variant from database".into()),
+        }
+    }
+}
+
+impl ToSql<Text, Pg> for DependencyKind {
+    fn to_sql<'b>(
+        &'b self,
+        out: &mut diesel::serialize::Output<'b, '_, Pg>,
+    ) -> diesel::serialize::Result {
+        match self {
+            DependencyKind::Normal => out.write_all(b"normal")?,
+            DependencyKind::Dev => out.write_all(b"dev")?,
+            DependencyKind::Build => out.write_all(b"build")?,
+        };
+        Ok(diesel::serialize::IsNull::No)
+    }
+}
+
+#[derive(Queryable, Selectable, Identifiable, Serialize)]
+#[diesel(table_name = audit_log)]
+pub struct AuditEntry {
+    pub id: i32,
+    pub user_id: Option<i32>,
+    pub action: AuditAction,
+    pub resource: String,
+    pub resource_id: i32,
+    pub before: Option<serde_json::Value>,
+    pub after: Option<serde_json::Value>,
+    pub created_at: NaiveDateTime,
+}
+
+#[derive(Insertable)]
+#[diesel(table_name = audit_log)]
+pub struct NewAuditEntry {
+    pub user_id: Option<i32>,
+    pub action: AuditAction,
+    pub resource: String,
+    pub resource_id: i32,
+    pub before: Option<serde_json::Value>,
+    pub after: Option<serde_json::Value>,
+}
+
+#[derive(AsExpression, Debug, FromSqlRow, PartialEq, Eq, Clone, Copy, Serialize)]
+#[diesel(sql_type=Text)]
+#[serde(rename_all = "lowercase")]
+pub enum AuditAction {
+    Create,
+    Update,
+    Delete,
+    Restore,
+    /// A user was locked out after too many failed logins.
+    Lock,
+    Unlock,
+}
+
+impl FromStr for AuditAction {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "create" => Ok(AuditAction::Create),
+            "update" => Ok(AuditAction::Update),
+            "delete" => Ok(AuditAction::Delete),
+            "restore" => Ok(AuditAction::Restore),
+            "lock" => Ok(AuditAction::Lock),
+            "unlock" => Ok(AuditAction::Unlock),
+            _ => Err(()),
+        }
+    }
+}
+
+impl FromSql<Text, Pg> for AuditAction {
+    fn from_sql(value: PgValue<'_>) -> deserialize::Result<Self> {
+        match value.as_bytes() {
+            b"create" => Ok(AuditAction::Create),
+            b"update" => Ok(AuditAction::Update),
+            b"delete" => Ok(AuditAction::Delete),
+            b"restore" => Ok(AuditAction::Restore),
+            b"lock" => Ok(AuditAction::Lock),
+            b"unlock" => Ok(AuditAction::Unlock),
+            _ => Err("Unrecognized enum variant from database".into()),
+        }
+    }
+}
+
+impl ToSql<Text, Pg> for AuditAction {
+    fn to_sql<'b>(
+        &'b self,
+        out: &mut diesel::serialize::Output<'b, '_, Pg>,
+    ) -> diesel::serialize::Result {
+        match self {
+            AuditAction::Create => out.write_all(b"create")?,
+            AuditAction::Update => out.write_all(b"update")?,
+            AuditAction::Delete => out.write_all(b"delete")?,
+            AuditAction::Restore => out.write_all(b"restore")?,
+            AuditAction::Lock => out.write_all(b"lock")?,
+            AuditAction::Unlock => out.write_all(b"unlock")?,
+        };
+        Ok(diesel::serialize::IsNull::No)
+    }
+}
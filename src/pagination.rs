@@ -0,0 +1,127 @@
+use crate::filtering::QueryError;
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use base64::Engine;
+use serde::Serialize;
+
+pub const DEFAULT_LIMIT: i64 = 100;
+pub const MAX_LIMIT: i64 = 100;
+
+const ID_CURSOR_PREFIX: &str = "id:";
+const OFFSET_CURSOR_PREFIX: &str = "offset:";
+
+/// Where a requested page begins.
+pub enum PageStart {
+    /// Skip the given number of rows.
+    Offset(i64),
+    /// Continue right after the row with the given id.
+    After(i32),
+}
+
+/// Pagination parameters accepted by the generated `find_multiple`.
+pub struct Pagination {
+    pub limit: i64,
+    pub start: PageStart,
+}
+
+impl Pagination {
+    /// Validates the raw `?limit=&offset=&cursor=` query parameters.
+    pub fn from_query(
+        limit: Option<&str>,
+        offset: Option<&str>,
+        cursor: Option<&str>,
+    ) -> Result<Self, QueryError> {
+        let limit = parse_number("limit", limit)?.unwrap_or(DEFAULT_LIMIT);
+        let offset = parse_number("offset", offset)?;
+        if !(1..=MAX_LIMIT).contains(&limit) {
+            return Err(QueryError::new(
+                "limit",
+                format!("Must be between 1 and {}", MAX_LIMIT),
+            ));
+        }
+
+        let start = match (offset, cursor) {
+            (Some(_), Some(_)) => {
+                return Err(QueryError::new(
+                    "cursor",
+                    "Use either offset or cursor, not both",
+                ));
+            }
+            (Some(offset), None) if offset < 0 => {
+                return Err(QueryError::new("offset", "Must not be negative"));
+            }
+            (Some(offset), None) => PageStart::Offset(offset),
+            (None, Some(cursor)) => {
+                decode_cursor(cursor).ok_or_else(|| QueryError::new("cursor", "Invalid cursor"))?
+            }
+            (None, None) => PageStart::Offset(0),
+        };
+
+        Ok(Self { limit, start })
+    }
+}
+
+/// Parses a whole number query parameter, refusing anything else rather than
+/// falling back to its default.
+fn parse_number(field: &str, value: Option<&str>) -> Result<Option<i64>, QueryError> {
+    value
+        .map(|value| {
+            value
+                .parse()
+                .map_err(|_| QueryError::invalid_value(field, value))
+        })
+        .transpose()
+}
+
+/// A single page of results together with what is needed to fetch the next one.
+#[derive(Serialize)]
+pub struct Page<T> {
+    pub items: Vec<T>,
+    pub total: i64,
+    pub next_cursor: Option<String>,
+}
+
+impl<T> Page<T> {
+    /// Builds a page from up to `limit + 1` loaded rows.
+    /// The extra row is dropped and only signals that another page exists;
+    /// `next_start` tells where that page begins given the last row kept.
+    pub fn from_rows(
+        mut items: Vec<T>,
+        limit: i64,
+        total: i64,
+        next_start: impl Fn(&T) -> PageStart,
+    ) -> Self {
+        let next_cursor = if items.len() as i64 > limit {
+            items.truncate(limit as usize);
+            items.last().map(|item| encode_cursor(&next_start(item)))
+        } else {
+            None
+        };
+
+        Self {
+            items,
+            total,
+            next_cursor,
+        }
+    }
+}
+
+pub fn encode_cursor(start: &PageStart) -> String {
+    let raw = match start {
+        PageStart::Offset(offset) => format!("{}{}", OFFSET_CURSOR_PREFIX, offset),
+        PageStart::After(id) => format!("{}{}", ID_CURSOR_PREFIX, id),
+    };
+    URL_SAFE_NO_PAD.encode(raw)
+}
+
+pub fn decode_cursor(cursor: &str) -> Option<PageStart> {
+    let decoded = String::from_utf8(URL_SAFE_NO_PAD.decode(cursor).ok()?).ok()?;
+    if let Some(id) = decoded.strip_prefix(ID_CURSOR_PREFIX) {
+        return id.parse().ok().map(PageStart::After);
+    }
+    decoded
+        .strip_prefix(OFFSET_CURSOR_PREFIX)?
+        .parse()
+        .ok()
+        .filter(|offset| *offset >= 0)
+        .map(PageStart::Offset)
+}
@@ -0,0 +1,126 @@
+use crate::auth::generate_token;
+use rocket_db_pools::deadpool_redis::redis::aio::ConnectionLike;
+use rocket_db_pools::deadpool_redis::redis::{AsyncCommands, RedisResult};
+use serde::Deserialize;
+
+/// The password policy and reset settings, read from the Rocket configuration, e.g.
+/// `ROCKET_PASSWORD_MIN_LENGTH=12` or `ROCKET_PASSWORD_BANNED=[hunter22, letmein1]`.
+#[derive(Clone, Deserialize)]
+#[serde(default)]
+pub struct PasswordConfig {
+    pub password_min_length: usize,
+    pub password_max_length: usize,
+    /// How many of lowercase letters, uppercase letters, digits and other characters
+    /// a password has to mix.
+    pub password_min_character_classes: usize,
+    /// Passwords refused outright, compared ignoring case.
+    pub password_banned: Vec<String>,
+    /// Seconds a password reset link stays valid.
+    pub password_reset_timeout: u64,
+    /// Where reset links point, with the token appended as `?token=...`.
+    pub password_reset_url: String,
+}
+
+impl Default for PasswordConfig {
+    fn default() -> Self {
+        Self {
+            password_min_length: 8,
+            password_max_length: 128,
+            password_min_character_classes: 2,
+            password_banned: [
+                "password",
+                "password1",
+                "password123",
+                "12345678",
+                "123456789",
+                "1234567890",
+                "qwerty123",
+                "qwertyuiop",
+                "iloveyou",
+                "letmein1",
+                "welcome1",
+                "admin123",
+            ]
+            .map(String::from)
+            .to_vec(),
+            password_reset_timeout: 60 * 60,
+            password_reset_url: "http://localhost:8000/password/reset".to_string(),
+        }
+    }
+}
+
+impl PasswordConfig {
+    /// Checks a new password for a user against the policy, evaluating to what is
+    /// wrong with it.
+    pub fn check(&self, username: &str, password: &str) -> Result<(), String> {
+        let length = password.chars().count();
+        if length < self.password_min_length || length > self.password_max_length {
+            return Err(format!(
+                "Must be between {} and {} characters long",
+                self.password_min_length, self.password_max_length
+            ));
+        }
+
+        let classes = [
+            password.chars().any(|c| c.is_lowercase()),
+            password.chars().any(|c| c.is_uppercase()),
+            password.chars().any(|c| c.is_numeric()),
+            password.chars().any(|c| !c.is_alphanumeric()),
+        ];
+        if classes.iter().filter(|&&class| class).count() < self.password_min_character_classes {
+            return Err(format!(
+                "Must mix at least {} of lowercase letters, uppercase letters, digits and other characters",
+                self.password_min_character_classes
+            ));
+        }
+
+        if password.eq_ignore_ascii_case(username)
+            || self
+                .password_banned
+                .iter()
+                .any(|banned| password.eq_ignore_ascii_case(banned))
+        {
+            return Err("Is too easy to guess".to_string());
+        }
+        Ok(())
+    }
+
+    /// The link for resetting a password with a token.
+    pub fn reset_link(&self, token: &str) -> String {
+        format!("{}?token={}", self.password_reset_url, token)
+    }
+}
+
+fn reset_key(token: &str) -> String {
+    format!("password_resets/{}", token)
+}
+
+/// Issues a token for resetting a user's password, valid for the reset timeout.
+pub async fn issue_reset<C: ConnectionLike + Send>(
+    cache: &mut C,
+    config: &PasswordConfig,
+    user_id: i32,
+) -> RedisResult<String> {
+    let token = generate_token(64);
+    cache
+        .set_ex::<_, _, ()>(reset_key(&token), user_id, config.password_reset_timeout)
+        .await?;
+    Ok(token)
+}
+
+/// The user a reset token was issued to, leaving the token in place.
+pub async fn find_reset<C: ConnectionLike + Send>(
+    cache: &mut C,
+    token: &str,
+) -> RedisResult<Option<i32>> {
+    cache.get(reset_key(token)).await
+}
+
+/// Uses up a reset token, evaluating to the user it was issued to. Each token works
+/// once: a second use, even a concurrent one, finds nothing.
+pub async fn take_reset<C: ConnectionLike + Send>(
+    cache: &mut C,
+    token: &str,
+) -> RedisResult<Option<i32>> {
+    cache.get_del(reset_key(token)).await
+}
@@ -0,0 +1,38 @@
+/// A permission a route may require, granted to users through their roles.
+/// `CODE` names the matching row of the `permissions` table.
+pub trait Permission: Send + Sync + 'static {
+    const CODE: &'static str;
+}
+
+/// Declares a marker type for each permission, to be used as `Require<Marker>`.
+macro_rules! permissions {
+    ($($(#[$meta:meta])* $name:ident => $code:literal),* $(,)?) => {
+        $(
+            $(#[$meta])*
+            pub struct $name;
+
+            impl Permission for $name {
+                const CODE: &'static str = $code;
+            }
+        )*
+    };
+}
+
+permissions! {
+    /// Create and update rustaceans.
+    RustaceansWrite => "rustaceans:write",
+    /// Delete rustaceans who own no crates, or hand their crates over first.
+    RustaceansDelete => "rustaceans:delete",
+    /// Delete rustaceans along with their crates.
+    RustaceansCascade => "rustaceans:cascade",
+    RustaceansRestore => "rustaceans:restore",
+    /// Create and update crates, their versions and dependencies.
+    CratesWrite => "crates:write",
+    CratesDelete => "crates:delete",
+    CratesRestore => "crates:restore",
+    /// Change crates whatever rustacean they belong to, not just one's own.
+    CratesAny => "crates:any",
+    AuditRead => "audit:read",
+    /// Manage users and their roles.
+    UsersAdmin => "users:admin",
+}
@@ -1,14 +1,39 @@
+use crate::filtering::{
+    contains_pattern, parse_filter_value, ListError, ListParams, QueryError, SortDirection,
+};
 use crate::models::*;
+use crate::pagination::{Page, PageStart, Pagination};
 #[allow(unused_imports)]
 use crate::schema::*;
+use chrono::NaiveDateTime;
 use diesel::dsl::{now, IntervalDsl};
+use diesel::pg::Pg;
 use diesel::prelude::*;
+use diesel::sql_types::{BigInt, Bool, Integer, Text};
 use diesel_async::scoped_futures::ScopedFutureExt;
 use diesel_async::{AsyncConnection, AsyncPgConnection, RunQueryDsl};
 use std::collections::HashSet;
 
+sql_function!(fn lower(x: diesel::sql_types::Text) -> diesel::sql_types::Text);
+
 /// A macro to generate a repository implementation for a given data model.
 /// This abstracts away the boilerplate CRUD logic.
+///
+/// `find_multiple` accepts an optional listing spec declaring which query parameters
+/// may filter the list and which columns it may be sorted by:
+///
+/// ```text
+/// find_multiple(
+///     filter { code: String => eq(crates::code), name: String => contains(crates::name) },
+///     sort { name: crates::name, created_at: crates::created_at }
+/// )
+/// ```
+///
+/// Filter operators are `eq`, `gt`, `ge`, `lt`, `le` and `contains` (case-insensitive substring).
+///
+/// With `soft_delete(table::deleted_at)` ahead of the method list, rows are hidden from
+/// `find`, `find_multiple` and `update` once that column is set, `delete` sets it,
+/// `restore` clears it and `purge` removes rows deleted more than a number of days ago.
 macro_rules! implement_repository {
     // With an explicit list of methods to generate
     (
@@ -16,24 +41,34 @@ macro_rules! implement_repository {
         $table:path,
         $model:ty,
         $new_model:ty,
-        { $($method:ident $(($($arg:ty),*))?),* }
+        { $($methods:tt)* }
     ) => {
-        pub struct $struct_name;
+        implement_repository!(@impl $struct_name, $table, $model, $new_model, [], { $($methods)* });
+    };
 
-        impl $struct_name {
-            $(
-                implement_repository!(@method $method, $table, $model, $new_model, $($($arg),*)?);
-            )*
-        }
+    // The same for a table whose rows are soft-deleted by setting the given column
+    (
+        $struct_name:ident,
+        $table:path,
+        $model:ty,
+        $new_model:ty,
+        soft_delete($deleted_at:path),
+        { $($methods:tt)* }
+    ) => {
+        implement_repository!(
+            @impl $struct_name, $table, $model, $new_model, [$deleted_at], { $($methods)* }
+        );
     };
 
-    // With no methods specified, generate all (including update)
+    // With no methods specified, generate all (including update),
+    // optionally followed by the listing spec for `find_multiple`
     (
         $struct_name:ident,
         $table:path,
         $model:ty,
         $new_model:ty,
         $update_model:ty
+        $(, $($listing:tt)+)?
     ) => {
         implement_repository!(
             $struct_name,
@@ -42,7 +77,7 @@ macro_rules! implement_repository {
             $new_model,
             {
                 find,
-                find_multiple,
+                find_multiple $(($($listing)+))?,
                 create,
                 update($update_model),
                 delete
@@ -72,75 +107,942 @@ macro_rules! implement_repository {
     };
 
     // Internal helpers to generate method implementations
-    (@method find, $table:path, $model:ty, $_new_model:ty, ) => {
+    (
+        @impl $struct_name:ident, $table:path, $model:ty, $new_model:ty, $soft_delete:tt,
+        { $($method:ident $(($($arg:tt)*))?),* }
+    ) => {
+        pub struct $struct_name;
+
+        impl $struct_name {
+            $(
+                implement_repository!(
+                    @method $method, $table, $model, $new_model, $soft_delete, $($($arg)*)?
+                );
+            )*
+        }
+    };
+
+    (@method find, $table:path, $model:ty, $_new_model:ty, [$($deleted_at:path)?], ) => {
         pub async fn find(c: &mut AsyncPgConnection, id: i32) -> QueryResult<$model> {
-            $table.find(id).get_result(c).await
+            $table
+                .find(id)
+                $(.filter($deleted_at.is_null()))?
+                .select(<$model>::as_select())
+                .get_result(c)
+                .await
+        }
+    };
+
+    (@method find_for_update, $table:path, $model:ty, $_new_model:ty, [$($deleted_at:path)?], ) => {
+        /// Like `find`, but locks the row until the end of the transaction.
+        pub async fn find_for_update(c: &mut AsyncPgConnection, id: i32) -> QueryResult<$model> {
+            $table
+                .find(id)
+                $(.filter($deleted_at.is_null()))?
+                .select(<$model>::as_select())
+                .for_update()
+                .get_result(c)
+                .await
         }
     };
 
-    (@method find_multiple, $table:path, $model:ty, $_new_model:ty, ) => {
+    (@method find_multiple, $table:path, $model:ty, $new_model:ty, [$($deleted_at:path)?], ) => {
+        implement_repository!(
+            @method find_multiple, $table, $model, $new_model, [$($deleted_at)?], filter {}, sort {}
+        );
+    };
+
+    (
+        @method find_multiple, $table:path, $model:ty, $_new_model:ty, [$($deleted_at:path)?],
+        filter { $($filter:ident: $filter_ty:ty => $op:ident($filter_column:path)),* $(,)? },
+        sort { $($sort:ident: $sort_column:path),* $(,)? }
+    ) => {
+        fn filtered(
+            params: &ListParams,
+        ) -> Result<diesel::helper_types::IntoBoxed<'static, $table, Pg>, QueryError> {
+            params
+                .filters
+                .iter()
+                .try_fold($table $(.filter($deleted_at.is_null()))? .into_boxed(), |query, (field, value)| {
+                    Ok(match field.as_str() {
+                        $(
+                            stringify!($filter) => query.filter(implement_repository!(
+                                @filter $op,
+                                $filter_column,
+                                parse_filter_value::<$filter_ty>(field, value)?
+                            )),
+                        )*
+                        _ => return Err(QueryError::unknown_filter(field)),
+                    })
+                })
+        }
+
         pub async fn find_multiple(
             c: &mut AsyncPgConnection,
+            pagination: Pagination,
+            params: &ListParams,
+        ) -> Result<Page<$model>, ListError> {
+            // Keyset cursors only make sense for the default order.
+            let sorted = !params.sort.is_empty();
+            if sorted && matches!(pagination.start, PageStart::After(_)) {
+                return Err(QueryError::new("cursor", "Cursor does not match the requested sort").into());
+            }
+
+            let query = params.sort.iter().try_fold(Self::filtered(params)?, |query, sort| {
+                Ok(match (sort.field.as_str(), sort.direction) {
+                    $(
+                        (stringify!($sort), SortDirection::Asc) => query.then_order_by($sort_column.asc()),
+                        (stringify!($sort), SortDirection::Desc) => query.then_order_by($sort_column.desc()),
+                    )*
+                    _ => return Err(QueryError::unknown_sort_field(&sort.field)),
+                })
+            })?;
+            let total = Self::filtered(params)?.count().get_result(c).await?;
+
+            // Newest rows first unless sorted otherwise, with the primary key breaking ties.
+            // One extra row is loaded to tell whether a next page exists.
+            let query = query
+                .select(<$model>::as_select())
+                .then_order_by($table.primary_key().desc())
+                .limit(pagination.limit + 1);
+            let (query, offset) = match pagination.start {
+                PageStart::Offset(offset) => (query.offset(offset), offset),
+                PageStart::After(id) => (query.filter($table.primary_key().lt(id)), 0),
+            };
+            let items = query.load(c).await?;
+
+            Ok(Page::from_rows(items, pagination.limit, total, |item: &$model| {
+                if sorted {
+                    PageStart::Offset(offset + pagination.limit)
+                } else {
+                    PageStart::After(*item.id())
+                }
+            }))
+        }
+    };
+
+    (@filter contains, $column:path, $value:expr) => {
+        $column.ilike(contains_pattern(&$value))
+    };
+
+    (@filter $op:ident, $column:path, $value:expr) => {
+        $column.$op($value)
+    };
+
+    (@method find_batch, $table:path, $model:ty, $_new_model:ty, [$($deleted_at:path)?], ) => {
+        /// Loads up to `limit` rows with ids above `after` in id order,
+        /// for walking a whole table without holding all of it in memory.
+        pub async fn find_batch(
+            c: &mut AsyncPgConnection,
+            after: i32,
             limit: i64,
         ) -> QueryResult<Vec<$model>> {
-            $table.limit(limit).load(c).await
+            $table
+                .filter($table.primary_key().gt(after))
+                $(.filter($deleted_at.is_null()))?
+                .order($table.primary_key())
+                .select(<$model>::as_select())
+                .limit(limit)
+                .load(c)
+                .await
         }
     };
 
-    (@method create, $table:path, $model:ty, $new_model:ty, ) => {
+    (@method create, $table:path, $model:ty, $new_model:ty, [$($_deleted_at:path)?], ) => {
         pub async fn create(
             c: &mut AsyncPgConnection,
             new_item: $new_model,
         ) -> QueryResult<$model> {
             diesel::insert_into($table)
                 .values(new_item)
+                .returning(<$model>::as_returning())
                 .get_result(c)
                 .await
         }
     };
 
-    (@method update, $table:path, $model:ty, $_new_model:ty, $update_model:ty) => {
+    (
+        @method update, $table:path, $model:ty, $_new_model:ty, [$($deleted_at:path)?],
+        $update_model:ty
+    ) => {
         pub async fn update(
             c: &mut AsyncPgConnection,
             id: i32,
             patch: $update_model,
         ) -> QueryResult<$model> {
-            diesel::update($table.find(id))
+            diesel::update($table.find(id) $(.filter($deleted_at.is_null()))?)
                 .set(&patch)
+                .returning(<$model>::as_returning())
                 .get_result(c)
                 .await
         }
     };
 
-    (@method delete, $table:path, $model:ty, $_new_model:ty, ) => {
+    (@method delete, $table:path, $model:ty, $_new_model:ty, [], ) => {
         pub async fn delete(c: &mut AsyncPgConnection, id: i32) -> QueryResult<usize> {
             diesel::delete($table.find(id)).execute(c).await
         }
     };
+
+    (@method delete, $table:path, $model:ty, $_new_model:ty, [$deleted_at:path], ) => {
+        /// Soft-deletes the row; it is hard-deleted later by `purge`.
+        pub async fn delete(c: &mut AsyncPgConnection, id: i32) -> QueryResult<usize> {
+            diesel::update($table.find(id).filter($deleted_at.is_null()))
+                .set($deleted_at.eq(now))
+                .execute(c)
+                .await
+        }
+    };
+
+    (@method restore, $table:path, $model:ty, $_new_model:ty, [$deleted_at:path], ) => {
+        /// Brings back a soft-deleted row, failing with `NotFound` if there is none.
+        pub async fn restore(c: &mut AsyncPgConnection, id: i32) -> QueryResult<$model> {
+            diesel::update($table.find(id).filter($deleted_at.is_not_null()))
+                .set($deleted_at.eq(None::<NaiveDateTime>))
+                .returning(<$model>::as_returning())
+                .get_result(c)
+                .await
+        }
+    };
+
+    (@method purge, $table:path, $model:ty, $_new_model:ty, [$deleted_at:path], ) => {
+        /// Hard-deletes rows that were soft-deleted more than `days` days ago.
+        pub async fn purge(c: &mut AsyncPgConnection, days: i32) -> QueryResult<usize> {
+            diesel::delete($table.filter($deleted_at.lt((now - days.days()).nullable())))
+                .execute(c)
+                .await
+        }
+    };
 }
 
 // Use the macro to generate the implementation for RustaceanRepository.
+// Delete has to deal with owned crates and purge must leave owners of crates alone,
+// so both are written by hand below.
 implement_repository!(
     RustaceanRepository,
     rustaceans::table,
     Rustacean,
     NewRustacean,
-    UpdateRustacean
+    soft_delete(rustaceans::deleted_at),
+    {
+        find,
+        find_for_update,
+        find_multiple(
+            filter {
+                name: String => contains(rustaceans::name),
+                email: String => contains(rustaceans::email),
+                created_after: NaiveDateTime => gt(rustaceans::created_at),
+                created_before: NaiveDateTime => lt(rustaceans::created_at),
+            },
+            sort {
+                id: rustaceans::id,
+                name: rustaceans::name,
+                email: rustaceans::email,
+                created_at: rustaceans::created_at,
+            }
+        ),
+        find_batch,
+        create,
+        update(UpdateRustacean),
+        restore
+    }
 );
 
+/// What happens to the crates of a rustacean being deleted.
+#[derive(Clone, Copy, Debug)]
+pub enum OwnedCratesPolicy {
+    /// Refuse to delete a rustacean who still owns crates.
+    Restrict,
+    /// Delete the owned crates along with the rustacean.
+    Cascade,
+    /// Hand the owned crates over to another rustacean first.
+    ReassignTo(i32),
+}
+
+pub enum DeleteRustaceanError {
+    /// The rustacean still owns these crates.
+    OwnsCrates(Vec<Crate>),
+    /// The rustacean to reassign the crates to does not exist.
+    UnknownAssignee(i32),
+    Database(diesel::result::Error),
+}
+
+impl From<diesel::result::Error> for DeleteRustaceanError {
+    fn from(e: diesel::result::Error) -> Self {
+        DeleteRustaceanError::Database(e)
+    }
+}
+
+impl RustaceanRepository {
+    /// Finds a rustacean by email, ignoring case like the unique index on it does.
+    pub async fn find_by_email(c: &mut AsyncPgConnection, email: &str) -> QueryResult<Rustacean> {
+        rustaceans::table
+            .filter(lower(rustaceans::email).eq(email.to_lowercase()))
+            .filter(rustaceans::deleted_at.is_null())
+            .select(Rustacean::as_select())
+            .get_result(c)
+            .await
+    }
+
+    /// Links a rustacean to a user account, or unlinks it with `None`.
+    pub async fn set_user(
+        c: &mut AsyncPgConnection,
+        id: i32,
+        user_id: Option<i32>,
+    ) -> QueryResult<Rustacean> {
+        diesel::update(rustaceans::table.find(id))
+            .filter(rustaceans::deleted_at.is_null())
+            .set(rustaceans::user_id.eq(user_id))
+            .returning(Rustacean::as_returning())
+            .get_result(c)
+            .await
+    }
+
+    /// Whether a rustacean is linked to the given user account.
+    pub async fn is_linked_to(
+        c: &mut AsyncPgConnection,
+        id: i32,
+        user_id: i32,
+    ) -> QueryResult<bool> {
+        diesel::select(diesel::dsl::exists(
+            rustaceans::table
+                .find(id)
+                .filter(rustaceans::user_id.eq(user_id)),
+        ))
+        .get_result(c)
+        .await
+    }
+
+    /// Soft-deletes a rustacean, dealing with the crates they own according to `policy`.
+    /// Evaluates to the crates this changed, as they were before and, unless they were
+    /// deleted along with the rustacean, as they are now.
+    pub async fn delete(
+        c: &mut AsyncPgConnection,
+        id: i32,
+        policy: OwnedCratesPolicy,
+    ) -> Result<Vec<(Crate, Option<Crate>)>, DeleteRustaceanError> {
+        c.transaction(|conn| {
+            async move {
+                // Keeps new crates from being assigned to the rustacean meanwhile.
+                let Some(_) = rustaceans::table
+                    .find(id)
+                    .filter(rustaceans::deleted_at.is_null())
+                    .select(rustaceans::id)
+                    .for_update()
+                    .get_result::<i32>(conn)
+                    .await
+                    .optional()?
+                else {
+                    return Ok(Vec::new());
+                };
+
+                let owned_crates = crates::rustacean_id.eq(id);
+                let affected = match policy {
+                    OwnedCratesPolicy::Restrict => {
+                        let owned = crates::table
+                            .filter(owned_crates)
+                            .filter(crates::deleted_at.is_null())
+                            .select(Crate::as_select())
+                            .order(crates::id)
+                            .load(conn)
+                            .await?;
+                        if !owned.is_empty() {
+                            return Err(DeleteRustaceanError::OwnsCrates(owned));
+                        }
+                        Vec::new()
+                    }
+                    OwnedCratesPolicy::Cascade => {
+                        let before = crates::table
+                            .filter(owned_crates)
+                            .filter(crates::deleted_at.is_null())
+                            .select(Crate::as_select())
+                            .order(crates::id)
+                            .for_update()
+                            .load(conn)
+                            .await?;
+                        diesel::update(crates::table.filter(owned_crates))
+                            .filter(crates::deleted_at.is_null())
+                            .set(crates::deleted_at.eq(now))
+                            .execute(conn)
+                            .await?;
+                        before.into_iter().map(|krate| (krate, None)).collect()
+                    }
+                    OwnedCratesPolicy::ReassignTo(assignee_id) => {
+                        let assignee = rustaceans::table
+                            .find(assignee_id)
+                            .filter(rustaceans::deleted_at.is_null())
+                            .select(rustaceans::id)
+                            .for_share()
+                            .get_result::<i32>(conn)
+                            .await
+                            .optional()?;
+                        if assignee.is_none() {
+                            return Err(DeleteRustaceanError::UnknownAssignee(assignee_id));
+                        }
+
+                        // Soft-deleted crates move along, so they come back to the new owner
+                        // if restored.
+                        let before = crates::table
+                            .filter(owned_crates)
+                            .select(Crate::as_select())
+                            .order(crates::id)
+                            .for_update()
+                            .load(conn)
+                            .await?;
+                        let mut after = diesel::update(crates::table.filter(owned_crates))
+                            .set(crates::rustacean_id.eq(assignee_id))
+                            .returning(Crate::as_returning())
+                            .get_results(conn)
+                            .await?;
+                        after.sort_by_key(|krate| krate.id);
+                        before
+                            .into_iter()
+                            .zip(after.into_iter().map(Some))
+                            .collect()
+                    }
+                };
+
+                diesel::update(rustaceans::table.find(id))
+                    .set(rustaceans::deleted_at.eq(now))
+                    .execute(conn)
+                    .await?;
+                Ok(affected)
+            }
+            .scope_boxed()
+        })
+        .await
+    }
+
+    /// Hard-deletes rustaceans soft-deleted more than `days` days ago,
+    /// except those still owning crates, soft-deleted or not.
+    pub async fn purge(c: &mut AsyncPgConnection, days: i32) -> QueryResult<usize> {
+        diesel::delete(
+            rustaceans::table
+                .filter(rustaceans::deleted_at.lt((now - days.days()).nullable()))
+                .filter(diesel::dsl::not(diesel::dsl::exists(
+                    crates::table.filter(crates::rustacean_id.eq(rustaceans::id)),
+                ))),
+        )
+        .execute(c)
+        .await
+    }
+}
+
 // Use the macro to generate the implementation for CrateRepository.
-implement_repository!(CrateRepository, crates::table, Crate, NewCrate, UpdateCrate);
+// Create and update also maintain the version history, so they are written by hand below.
+implement_repository!(
+    CrateRepository,
+    crates::table,
+    Crate,
+    NewCrate,
+    soft_delete(crates::deleted_at),
+    {
+        find,
+        find_for_update,
+        find_multiple(
+            filter {
+                rustacean_id: i32 => eq(crates::rustacean_id),
+                code: String => eq(crates::code),
+                name: String => contains(crates::name),
+                version: String => eq(crates::version),
+                created_after: NaiveDateTime => gt(crates::created_at),
+                created_before: NaiveDateTime => lt(crates::created_at),
+            },
+            sort {
+                id: crates::id,
+                code: crates::code,
+                name: crates::name,
+                version: crates::version,
+                created_at: crates::created_at,
+            }
+        ),
+        delete,
+        restore,
+        purge
+    }
+);
+
+#[derive(QueryableByName)]
+struct Count {
+    #[diesel(sql_type = BigInt)]
+    count: i64,
+}
 
 impl CrateRepository {
+    /// Creates a crate together with the initial entry of its version history.
+    pub async fn create(c: &mut AsyncPgConnection, new_crate: NewCrate) -> QueryResult<Crate> {
+        c.transaction(|conn| {
+            async move {
+                let krate = diesel::insert_into(crates::table)
+                    .values(new_crate)
+                    .returning(Crate::as_returning())
+                    .get_result(conn)
+                    .await?;
+
+                diesel::insert_into(crate_versions::table)
+                    .values(NewCrateVersion {
+                        crate_id: krate.id,
+                        version: krate.version.clone(),
+                        changelog: None,
+                    })
+                    .execute(conn)
+                    .await?;
+
+                Ok(krate)
+            }
+            .scope_boxed()
+        })
+        .await
+    }
+
+    /// Updates a crate. A changed version is recorded in the version history
+    /// and the crate then points at the latest one of them.
+    pub async fn update(
+        c: &mut AsyncPgConnection,
+        id: i32,
+        patch: UpdateCrate,
+    ) -> QueryResult<Crate> {
+        c.transaction(|conn| {
+            async move {
+                let krate =
+                    diesel::update(crates::table.find(id).filter(crates::deleted_at.is_null()))
+                        .set(&patch)
+                        .returning(Crate::as_returning())
+                        .get_result(conn)
+                        .await?;
+
+                if let Some(version) = patch.version {
+                    diesel::insert_into(crate_versions::table)
+                        .values(NewCrateVersion {
+                            crate_id: id,
+                            version,
+                            changelog: None,
+                        })
+                        .on_conflict_do_nothing()
+                        .execute(conn)
+                        .await?;
+                    return CrateVersionRepository::refresh_latest(conn, id).await;
+                }
+
+                Ok(krate)
+            }
+            .scope_boxed()
+        })
+        .await
+    }
+
+    /// Ranks crates against a web-search style query (`"exact phrase" -excluded or`)
+    /// over their name, code and description.
+    pub async fn search(
+        c: &mut AsyncPgConnection,
+        query: &str,
+        pagination: Pagination,
+    ) -> Result<Page<CrateSearchHit>, ListError> {
+        let offset = match pagination.start {
+            PageStart::Offset(offset) => offset,
+            PageStart::After(_) => {
+                return Err(QueryError::new("cursor", "Cursor does not match the search").into());
+            }
+        };
+
+        let total = diesel::sql_query(
+            "SELECT count(*) AS count \
+             FROM crates, websearch_to_tsquery('english', $1) query \
+             WHERE search_vector @@ query AND deleted_at IS NULL",
+        )
+        .bind::<Text, _>(query)
+        .get_result::<Count>(c)
+        .await?
+        .count;
+
+        let hits = diesel::sql_query(
+            "SELECT crates.*, \
+                    ts_rank(search_vector, query) AS rank, \
+                    ts_headline('english', coalesce(description, name), query, \
+                                'StartSel=<mark>, StopSel=</mark>') AS snippet \
+             FROM crates, websearch_to_tsquery('english', $1) query \
+             WHERE search_vector @@ query AND deleted_at IS NULL \
+             ORDER BY rank DESC, id DESC \
+             LIMIT $2 OFFSET $3",
+        )
+        .bind::<Text, _>(query)
+        .bind::<BigInt, _>(pagination.limit + 1)
+        .bind::<BigInt, _>(offset)
+        .load::<CrateSearchHit>(c)
+        .await?;
+
+        Ok(Page::from_rows(hits, pagination.limit, total, |_| {
+            PageStart::Offset(offset + pagination.limit)
+        }))
+    }
+
+    /// Finds a crate by its code, ignoring case like the unique index on it does.
+    pub async fn find_by_code(c: &mut AsyncPgConnection, code: &str) -> QueryResult<Crate> {
+        crates::table
+            .filter(lower(crates::code).eq(code.to_lowercase()))
+            .filter(crates::deleted_at.is_null())
+            .select(Crate::as_select())
+            .get_result(c)
+            .await
+    }
+
+    /// Loads up to `limit` crates with ids above `after` in id order, along with the
+    /// email of each one's owner, for walking all crates without holding them in memory.
+    pub async fn find_batch_with_owner_emails(
+        c: &mut AsyncPgConnection,
+        after: i32,
+        limit: i64,
+    ) -> QueryResult<Vec<(Crate, String)>> {
+        crates::table
+            .inner_join(rustaceans::table)
+            .filter(crates::id.gt(after))
+            .filter(crates::deleted_at.is_null())
+            .order(crates::id)
+            .select((Crate::as_select(), rustaceans::email))
+            .limit(limit)
+            .load(c)
+            .await
+    }
+
+    pub async fn find_all(c: &mut AsyncPgConnection) -> QueryResult<Vec<Crate>> {
+        crates::table
+            .filter(crates::deleted_at.is_null())
+            .order(crates::id)
+            .select(Crate::as_select())
+            .load(c)
+            .await
+    }
+
     pub async fn find_since(
         c: &mut AsyncPgConnection,
         hours_since: i32,
     ) -> QueryResult<Vec<Crate>> {
         crates::table
             .filter(crates::created_at.ge(now - hours_since.hours()))
+            .filter(crates::deleted_at.is_null())
+            .select(Crate::as_select())
+            .load(c)
+            .await
+    }
+}
+
+implement_repository!(
+    CrateVersionRepository,
+    crate_versions::table,
+    CrateVersion,
+    NewCrateVersion,
+    {}
+);
+
+impl CrateVersionRepository {
+    /// All versions of a crate, newest semver first.
+    /// Versions that are not valid semver are listed last.
+    pub async fn find_by_crate(
+        c: &mut AsyncPgConnection,
+        crate_id: i32,
+    ) -> QueryResult<Vec<CrateVersion>> {
+        let mut versions = crate_versions::table
+            .filter(crate_versions::crate_id.eq(crate_id))
+            .order(crate_versions::published_at.desc())
+            .select(CrateVersion::as_select())
+            .load::<CrateVersion>(c)
+            .await?;
+        versions.sort_by_cached_key(|v| std::cmp::Reverse(semver::Version::parse(&v.version).ok()));
+        Ok(versions)
+    }
+
+    pub async fn find_all(c: &mut AsyncPgConnection) -> QueryResult<Vec<CrateVersion>> {
+        crate_versions::table
+            .order(crate_versions::id)
+            .select(CrateVersion::as_select())
+            .load(c)
+            .await
+    }
+
+    pub async fn find_by_version(
+        c: &mut AsyncPgConnection,
+        crate_id: i32,
+        version: &str,
+    ) -> QueryResult<CrateVersion> {
+        crate_versions::table
+            .filter(crate_versions::crate_id.eq(crate_id))
+            .filter(crate_versions::version.eq(version))
+            .select(CrateVersion::as_select())
+            .get_result(c)
+            .await
+    }
+
+    /// Picks the latest version by semver ordering, skipping yanked versions
+    /// and preferring stable releases over pre-releases.
+    pub fn latest(versions: &[CrateVersion]) -> Option<&CrateVersion> {
+        versions
+            .iter()
+            .filter(|v| !v.yanked)
+            .filter_map(|v| {
+                semver::Version::parse(&v.version)
+                    .ok()
+                    .map(|parsed| (parsed, v))
+            })
+            .max_by(|(a, _), (b, _)| {
+                a.pre
+                    .is_empty()
+                    .cmp(&b.pre.is_empty())
+                    .then_with(|| a.cmp(b))
+            })
+            .map(|(_, v)| v)
+    }
+
+    /// Points the crate's `version` at the latest entry of its history.
+    pub async fn refresh_latest(c: &mut AsyncPgConnection, crate_id: i32) -> QueryResult<Crate> {
+        let versions = Self::find_by_crate(c, crate_id).await?;
+        match Self::latest(&versions) {
+            Some(latest) => {
+                diesel::update(crates::table.find(crate_id))
+                    .set(crates::version.eq(&latest.version))
+                    .returning(Crate::as_returning())
+                    .get_result(c)
+                    .await
+            }
+            None => {
+                crates::table
+                    .find(crate_id)
+                    .select(Crate::as_select())
+                    .get_result(c)
+                    .await
+            }
+        }
+    }
+
+    pub async fn create(
+        c: &mut AsyncPgConnection,
+        new_version: NewCrateVersion,
+    ) -> QueryResult<CrateVersion> {
+        c.transaction(|conn| {
+            async move {
+                let version = diesel::insert_into(crate_versions::table)
+                    .values(new_version)
+                    .returning(CrateVersion::as_returning())
+                    .get_result(conn)
+                    .await?;
+                Self::refresh_latest(conn, version.crate_id).await?;
+                Ok(version)
+            }
+            .scope_boxed()
+        })
+        .await
+    }
+
+    pub async fn update(
+        c: &mut AsyncPgConnection,
+        crate_id: i32,
+        version: String,
+        patch: UpdateCrateVersion,
+    ) -> QueryResult<CrateVersion> {
+        c.transaction(|conn| {
+            async move {
+                let version = diesel::update(
+                    crate_versions::table
+                        .filter(crate_versions::crate_id.eq(crate_id))
+                        .filter(crate_versions::version.eq(version)),
+                )
+                .set(&patch)
+                .returning(CrateVersion::as_returning())
+                .get_result(conn)
+                .await?;
+                Self::refresh_latest(conn, crate_id).await?;
+                Ok(version)
+            }
+            .scope_boxed()
+        })
+        .await
+    }
+
+    /// Renames a version in place, e.g. to normalize it.
+    pub async fn rename(
+        c: &mut AsyncPgConnection,
+        id: i32,
+        version: String,
+    ) -> QueryResult<CrateVersion> {
+        diesel::update(crate_versions::table.find(id))
+            .set(crate_versions::version.eq(version))
+            .returning(CrateVersion::as_returning())
+            .get_result(c)
+            .await
+    }
+
+    pub async fn delete(
+        c: &mut AsyncPgConnection,
+        crate_id: i32,
+        version: String,
+    ) -> QueryResult<usize> {
+        c.transaction(|conn| {
+            async move {
+                let deleted = diesel::delete(
+                    crate_versions::table
+                        .filter(crate_versions::crate_id.eq(crate_id))
+                        .filter(crate_versions::version.eq(version)),
+                )
+                .execute(conn)
+                .await?;
+                Self::refresh_latest(conn, crate_id).await?;
+                Ok(deleted)
+            }
+            .scope_boxed()
+        })
+        .await
+    }
+}
+
+#[derive(Debug)]
+pub enum DependencyError {
+    /// The new edge would make a crate depend on itself, directly or transitively.
+    Cycle,
+    Database(diesel::result::Error),
+}
+
+impl From<diesel::result::Error> for DependencyError {
+    fn from(e: diesel::result::Error) -> Self {
+        DependencyError::Database(e)
+    }
+}
+
+#[derive(QueryableByName)]
+struct Exists {
+    #[diesel(sql_type = Bool)]
+    exists: bool,
+}
+
+implement_repository!(
+    CrateDependencyRepository,
+    crate_dependencies::table,
+    CrateDependency,
+    NewCrateDependency,
+    {}
+);
+
+impl CrateDependencyRepository {
+    /// Crates the given crate depends on, along with the edges to them.
+    pub async fn find_dependencies(
+        c: &mut AsyncPgConnection,
+        crate_id: i32,
+    ) -> QueryResult<Vec<(CrateDependency, Crate)>> {
+        crate_dependencies::table
+            .inner_join(crates::table.on(crates::id.eq(crate_dependencies::dependency_id)))
+            .filter(crate_dependencies::crate_id.eq(crate_id))
+            .filter(crates::deleted_at.is_null())
+            .order(crates::name)
+            .select((CrateDependency::as_select(), Crate::as_select()))
+            .load(c)
+            .await
+    }
+
+    /// Crates depending on the given crate, along with the edges from them.
+    pub async fn find_dependents(
+        c: &mut AsyncPgConnection,
+        crate_id: i32,
+    ) -> QueryResult<Vec<(CrateDependency, Crate)>> {
+        crate_dependencies::table
+            .inner_join(crates::table.on(crates::id.eq(crate_dependencies::crate_id)))
+            .filter(crate_dependencies::dependency_id.eq(crate_id))
+            .filter(crates::deleted_at.is_null())
+            .order(crates::name)
+            .select((CrateDependency::as_select(), Crate::as_select()))
             .load(c)
             .await
     }
+
+    /// Transitive dependencies of a crate up to `max_depth` levels deep.
+    /// Each edge is listed once, at the shallowest depth it is reached.
+    pub async fn find_tree(
+        c: &mut AsyncPgConnection,
+        crate_id: i32,
+        max_depth: i32,
+    ) -> QueryResult<Vec<DependencyTreeEdge>> {
+        diesel::sql_query(
+            "WITH RECURSIVE tree (crate_id, dependency_id, requirement, kind, depth) AS ( \
+                 SELECT crate_id, dependency_id, requirement, kind, 1 \
+                 FROM crate_dependencies \
+                 WHERE crate_id = $1 \
+                 UNION \
+                 SELECT d.crate_id, d.dependency_id, d.requirement, d.kind, tree.depth + 1 \
+                 FROM crate_dependencies d \
+                 JOIN tree ON d.crate_id = tree.dependency_id \
+                 WHERE tree.depth < $2 \
+             ) \
+             SELECT tree.crate_id, tree.dependency_id, tree.requirement, tree.kind, \
+                    min(tree.depth) AS depth, crates.code, crates.name, crates.version \
+             FROM tree \
+             JOIN crates ON crates.id = tree.dependency_id \
+             WHERE crates.deleted_at IS NULL \
+             GROUP BY tree.crate_id, tree.dependency_id, tree.requirement, tree.kind, \
+                      crates.code, crates.name, crates.version \
+             ORDER BY depth, tree.crate_id, crates.name",
+        )
+        .bind::<Integer, _>(crate_id)
+        .bind::<Integer, _>(max_depth)
+        .load(c)
+        .await
+    }
+
+    /// Adds a dependency edge unless the dependency already (transitively) depends on the crate.
+    pub async fn create(
+        c: &mut AsyncPgConnection,
+        new_dependency: NewCrateDependency,
+    ) -> Result<CrateDependency, DependencyError> {
+        c.transaction(|conn| {
+            async move {
+                // Serialize concurrent writers so two edges cannot close a cycle together
+                diesel::sql_query("LOCK TABLE crate_dependencies IN SHARE ROW EXCLUSIVE MODE")
+                    .execute(conn)
+                    .await?;
+
+                let creates_cycle = diesel::sql_query(
+                    "WITH RECURSIVE reachable (id) AS ( \
+                         SELECT $1 \
+                         UNION \
+                         SELECT d.dependency_id \
+                         FROM crate_dependencies d \
+                         JOIN reachable ON d.crate_id = reachable.id \
+                     ) \
+                     SELECT EXISTS (SELECT 1 FROM reachable WHERE id = $2) AS exists",
+                )
+                .bind::<Integer, _>(new_dependency.dependency_id)
+                .bind::<Integer, _>(new_dependency.crate_id)
+                .get_result::<Exists>(conn)
+                .await?
+                .exists;
+                if creates_cycle {
+                    return Err(DependencyError::Cycle);
+                }
+
+                diesel::insert_into(crate_dependencies::table)
+                    .values(new_dependency)
+                    .returning(CrateDependency::as_returning())
+                    .get_result(conn)
+                    .await
+                    .map_err(DependencyError::from)
+            }
+            .scope_boxed()
+        })
+        .await
+    }
+
+    /// Removes the edges to a dependency, optionally only those of one kind.
+    pub async fn delete(
+        c: &mut AsyncPgConnection,
+        crate_id: i32,
+        dependency_id: i32,
+        kind: Option<DependencyKind>,
+    ) -> QueryResult<usize> {
+        let mut query = diesel::delete(crate_dependencies::table)
+            .filter(crate_dependencies::crate_id.eq(crate_id))
+            .filter(crate_dependencies::dependency_id.eq(dependency_id))
+            .into_boxed();
+        if let Some(kind) = kind {
+            query = query.filter(crate_dependencies::kind.eq(kind));
+        }
+        query.execute(c).await
+    }
 }
 
 implement_repository!(UserRepository, users::table, User, NewUser, { find });
@@ -169,6 +1071,7 @@ impl UserRepository {
         Ok(users.into_iter().zip(result).collect())
     }
 
+    /// Deletes a user with their roles, failing with `NotFound` when there is no such user.
     pub async fn delete(c: &mut AsyncPgConnection, id: i32) -> QueryResult<usize> {
         c.transaction(|conn| {
             Box::pin(async move {
@@ -178,12 +1081,143 @@ impl UserRepository {
                     .await?;
 
                 // Then, delete the user
-                diesel::delete(users::table.find(id)).execute(conn).await
+                match diesel::delete(users::table.find(id)).execute(conn).await? {
+                    0 => Err(diesel::result::Error::NotFound),
+                    deleted => Ok(deleted),
+                }
             })
         })
         .await
     }
 
+    /// Whether any of the user's roles grants the permission with the given code.
+    pub async fn has_permission(
+        c: &mut AsyncPgConnection,
+        user_id: i32,
+        permission_code: &str,
+    ) -> QueryResult<bool> {
+        let role_ids = user_roles::table
+            .filter(user_roles::user_id.eq(user_id))
+            .select(user_roles::role_id);
+        diesel::select(diesel::dsl::exists(
+            role_permissions::table
+                .inner_join(permissions::table)
+                .filter(role_permissions::role_id.eq_any(role_ids))
+                .filter(permissions::code.eq(permission_code)),
+        ))
+        .get_result(c)
+        .await
+    }
+
+    /// Replaces the password hash of a user.
+    pub async fn set_password(
+        c: &mut AsyncPgConnection,
+        id: i32,
+        password_hash: String,
+    ) -> QueryResult<usize> {
+        diesel::update(users::table.find(id))
+            .set(users::password.eq(password_hash))
+            .execute(c)
+            .await
+    }
+
+    /// The codes of the permissions a user holds through any of their roles.
+    pub async fn permission_codes(
+        c: &mut AsyncPgConnection,
+        user_id: i32,
+    ) -> QueryResult<Vec<String>> {
+        let role_ids = user_roles::table
+            .filter(user_roles::user_id.eq(user_id))
+            .select(user_roles::role_id);
+        role_permissions::table
+            .inner_join(permissions::table)
+            .filter(role_permissions::role_id.eq_any(role_ids))
+            .select(permissions::code)
+            .distinct()
+            .order(permissions::code)
+            .load(c)
+            .await
+    }
+
+    /// Gives a user a role, creating the role if needed. Evaluates to `false`
+    /// when the user already held it, and fails with `NotFound` for an unknown user.
+    pub async fn grant_role(
+        c: &mut AsyncPgConnection,
+        user_id: i32,
+        role_code: RoleCode,
+    ) -> QueryResult<bool> {
+        c.transaction(|conn| {
+            async move {
+                Self::find(conn, user_id).await?;
+                let roles = RoleRepository::find_or_create(conn, vec![role_code]).await?;
+
+                let granted = diesel::insert_into(user_roles::table)
+                    .values(NewUserRole {
+                        user_id,
+                        role_id: roles[0].id,
+                    })
+                    .on_conflict((user_roles::user_id, user_roles::role_id))
+                    .do_nothing()
+                    .execute(conn)
+                    .await?;
+                Ok(granted > 0)
+            }
+            .scope_boxed()
+        })
+        .await
+    }
+
+    /// Takes a role away from a user. Evaluates to `false` when the user did not hold it.
+    pub async fn revoke_role(
+        c: &mut AsyncPgConnection,
+        user_id: i32,
+        role_code: RoleCode,
+    ) -> QueryResult<bool> {
+        let role_ids = roles::table
+            .filter(roles::code.eq(role_code))
+            .select(roles::id);
+        let revoked = diesel::delete(user_roles::table)
+            .filter(user_roles::user_id.eq(user_id))
+            .filter(user_roles::role_id.eq_any(role_ids))
+            .execute(c)
+            .await?;
+        Ok(revoked > 0)
+    }
+
+    /// Replaces the roles of a user with the given ones.
+    pub async fn set_roles(
+        c: &mut AsyncPgConnection,
+        user_id: i32,
+        role_codes: Vec<RoleCode>,
+    ) -> QueryResult<Vec<Role>> {
+        c.transaction(|conn| {
+            async move {
+                // Fails with `NotFound` for an unknown user
+                let user = Self::find(conn, user_id).await?;
+                let roles = RoleRepository::find_or_create(conn, role_codes).await?;
+
+                diesel::delete(UserRole::belonging_to(&user))
+                    .execute(conn)
+                    .await?;
+                let new_user_roles: Vec<_> = roles
+                    .iter()
+                    .map(|role| NewUserRole {
+                        user_id,
+                        role_id: role.id,
+                    })
+                    .collect();
+                diesel::insert_into(user_roles::table)
+                    .values(&new_user_roles)
+                    .execute(conn)
+                    .await?;
+
+                Ok(roles)
+            }
+            .scope_boxed()
+        })
+        .await
+    }
+
     pub async fn create_with_roles(
         c: &mut AsyncPgConnection,
         new_user: NewUser,
@@ -201,46 +1235,15 @@ impl UserRepository {
                     return Ok(user);
                 }
 
-                // 2. Find which roles already exist in one query
-                let existing_roles = roles::table
-                    .filter(roles::code.eq_any(&role_codes))
-                    .load::<Role>(conn)
-                    .await?;
-
-                // 3. Determine which roles are new
-                let existing_role_codes: HashSet<_> =
-                    existing_roles.iter().map(|r| r.code.clone()).collect();
-                let roles_to_create: Vec<_> = role_codes
-                    .into_iter()
-                    .filter(|rc| !existing_role_codes.contains(rc))
-                    .map(|rc| NewRole {
-                        name: rc.to_string(),
-                        code: rc,
-                    })
-                    .collect();
-
-                // 4. Create all new roles in a single batch insert
-                let created_roles = if !roles_to_create.is_empty() {
-                    diesel::insert_into(roles::table)
-                        .values(&roles_to_create)
-                        .get_results::<Role>(conn)
-                        .await?
-                } else {
-                    Vec::new()
-                };
+                // 2. Find or create the roles
+                let roles = RoleRepository::find_or_create(conn, role_codes).await?;
 
-                // 5. Combine existing and new roles and create the associations in a single batch
-                let all_role_ids: Vec<_> = existing_roles
+                // 3. Create the associations in a single batch
+                let new_user_roles: Vec<_> = roles
                     .iter()
-                    .map(|r| r.id)
-                    .chain(created_roles.iter().map(|r| r.id))
-                    .collect();
-
-                let new_user_roles: Vec<_> = all_role_ids
-                    .into_iter()
-                    .map(|role_id_val| NewUserRole {
+                    .map(|role| NewUserRole {
                         user_id: user.id,
-                        role_id: role_id_val,
+                        role_id: role.id,
                     })
                     .collect();
 
@@ -262,12 +1265,59 @@ implement_repository!(RoleRepository, roles::table, Role, NewRole, {});
 
 // Add custom methods to RoleRepository
 impl RoleRepository {
+    /// Loads the roles with the given codes, creating the ones that do not exist yet.
+    pub async fn find_or_create(
+        c: &mut AsyncPgConnection,
+        role_codes: Vec<RoleCode>,
+    ) -> QueryResult<Vec<Role>> {
+        // Find which roles already exist in one query
+        let mut roles = roles::table
+            .filter(roles::code.eq_any(&role_codes))
+            .load::<Role>(c)
+            .await?;
+
+        // Create the missing ones in a single batch insert
+        let existing_role_codes: HashSet<_> = roles.iter().map(|r| r.code.clone()).collect();
+        let roles_to_create: Vec<_> = role_codes
+            .into_iter()
+            .filter(|rc| !existing_role_codes.contains(rc))
+            .collect::<HashSet<_>>()
+            .into_iter()
+            .map(|rc| NewRole {
+                name: rc.to_string(),
+                code: rc,
+            })
+            .collect();
+        if !roles_to_create.is_empty() {
+            roles.extend(
+                diesel::insert_into(roles::table)
+                    .values(&roles_to_create)
+                    .get_results::<Role>(c)
+                    .await?,
+            );
+        }
+
+        Ok(roles)
+    }
+
     pub async fn find_by_ids(c: &mut AsyncPgConnection, ids: Vec<i32>) -> QueryResult<Vec<Role>> {
         roles::table.filter(roles::id.eq_any(ids)).load(c).await
     }
     // pub async fn find_by_code(c: &mut AsyncPgConnection, code: &RoleCode) -> QueryResult<Role> {
     //     roles::table.filter(roles::code.eq(code)).first(c).await
     // }
+    /// The code of every permission granted, along with the role granting it.
+    pub async fn find_permission_codes(
+        c: &mut AsyncPgConnection,
+    ) -> QueryResult<Vec<(RoleCode, String)>> {
+        role_permissions::table
+            .inner_join(roles::table)
+            .inner_join(permissions::table)
+            .select((roles::code, permissions::code))
+            .load(c)
+            .await
+    }
+
     pub async fn find_by_user(c: &mut AsyncPgConnection, user: &User) -> QueryResult<Vec<Role>> {
         let user_roles = UserRole::belonging_to(&user)
             .get_results::<UserRole>(c)
@@ -276,3 +1326,97 @@ impl RoleRepository {
         Self::find_by_ids(c, role_ids).await
     }
 }
+
+implement_repository!(ApiKeyRepository, api_keys::table, ApiKey, NewApiKey, {
+    create
+});
+
+// API keys are only ever reached through the user they belong to, save when authenticating.
+impl ApiKeyRepository {
+    pub async fn find_by_user(c: &mut AsyncPgConnection, user_id: i32) -> QueryResult<Vec<ApiKey>> {
+        api_keys::table
+            .filter(api_keys::user_id.eq(user_id))
+            .order(api_keys::id)
+            .select(ApiKey::as_select())
+            .load(c)
+            .await
+    }
+
+    pub async fn find_for_user(
+        c: &mut AsyncPgConnection,
+        user_id: i32,
+        id: i32,
+    ) -> QueryResult<ApiKey> {
+        api_keys::table
+            .find(id)
+            .filter(api_keys::user_id.eq(user_id))
+            .select(ApiKey::as_select())
+            .get_result(c)
+            .await
+    }
+
+    pub async fn find_by_prefix(c: &mut AsyncPgConnection, prefix: &str) -> QueryResult<ApiKey> {
+        api_keys::table
+            .filter(api_keys::prefix.eq(prefix))
+            .select(ApiKey::as_select())
+            .get_result(c)
+            .await
+    }
+
+    pub async fn update_for_user(
+        c: &mut AsyncPgConnection,
+        user_id: i32,
+        id: i32,
+        settings: ApiKeySettings,
+    ) -> QueryResult<ApiKey> {
+        diesel::update(api_keys::table.find(id))
+            .filter(api_keys::user_id.eq(user_id))
+            .set(settings)
+            .returning(ApiKey::as_returning())
+            .get_result(c)
+            .await
+    }
+
+    pub async fn delete_for_user(
+        c: &mut AsyncPgConnection,
+        user_id: i32,
+        id: i32,
+    ) -> QueryResult<usize> {
+        diesel::delete(api_keys::table.find(id))
+            .filter(api_keys::user_id.eq(user_id))
+            .execute(c)
+            .await
+    }
+
+    pub async fn mark_used(c: &mut AsyncPgConnection, id: i32) -> QueryResult<usize> {
+        diesel::update(api_keys::table.find(id))
+            .set(api_keys::last_used_at.eq(now))
+            .execute(c)
+            .await
+    }
+}
+
+// Entries are only ever appended, by the handlers changing the audited resources.
+implement_repository!(
+    AuditLogRepository,
+    audit_log::table,
+    AuditEntry,
+    NewAuditEntry,
+    {
+        find_multiple(
+            filter {
+                resource: String => eq(audit_log::resource),
+                id: i32 => eq(audit_log::resource_id),
+                user_id: i32 => eq(audit_log::user_id),
+                action: AuditAction => eq(audit_log::action),
+                created_after: NaiveDateTime => gt(audit_log::created_at),
+                created_before: NaiveDateTime => lt(audit_log::created_at),
+            },
+            sort {
+                id: audit_log::id,
+                created_at: audit_log::created_at,
+            }
+        ),
+        create
+    }
+);
@@ -1,6 +1,12 @@
+use crate::filtering::QueryError;
+use crate::validation::ValidationErrors;
+use chrono::NaiveDateTime;
+use diesel::result::DatabaseErrorInformation;
 use rocket::http::Status;
 use rocket::response::status::Custom;
+use rocket::response::{self, Responder, Response};
 use rocket::serde::json::{serde_json::json, Value};
+use rocket::Request;
 use std::fmt::Debug;
 
 pub fn handle_db_error<E: Debug>(
@@ -17,3 +23,88 @@ pub fn handle_db_error<E: Debug>(
         )}),
     )
 }
+
+pub fn not_found() -> Custom<Value> {
+    Custom(Status::NotFound, json!({ "error": "Not Found" }))
+}
+
+pub fn invalid_query_error(e: QueryError) -> Custom<Value> {
+    Custom(
+        Status::BadRequest,
+        json!({
+            "error": "Invalid query",
+            "field": e.field,
+            "message": e.message,
+        }),
+    )
+}
+
+pub fn validation_error(errors: ValidationErrors) -> Custom<Value> {
+    Custom(
+        Status::UnprocessableEntity,
+        json!({
+            "error": "Validation failed",
+            "fields": errors,
+        }),
+    )
+}
+
+/// Maps a unique constraint violation to a 409 naming the conflicting field.
+/// Relies on unique indexes being named `<table>_<field>_key`, as Postgres does by default.
+pub fn unique_violation_error(info: &dyn DatabaseErrorInformation) -> Custom<Value> {
+    let field = info.constraint_name().map(|constraint| {
+        let constraint = constraint.strip_suffix("_key").unwrap_or(constraint);
+        info.table_name()
+            .and_then(|table| constraint.strip_prefix(table)?.strip_prefix('_'))
+            .unwrap_or(constraint)
+            .to_string()
+    });
+    Custom(
+        Status::Conflict,
+        json!({
+            "error": "Conflict",
+            "field": field,
+            "message": "A record with this value already exists",
+        }),
+    )
+}
+
+/// A strong entity tag for a row, derived from its id and last modification time.
+pub fn etag(id: i32, updated_at: NaiveDateTime) -> String {
+    format!("\"{}-{}\"", id, updated_at.and_utc().timestamp_micros())
+}
+
+pub fn precondition_failed() -> Custom<Value> {
+    Custom(
+        Status::PreconditionFailed,
+        json!({ "error": "The resource has been modified since it was fetched" }),
+    )
+}
+
+/// Wraps a response to send it with an `ETag` header.
+pub struct WithETag<R> {
+    pub etag: String,
+    pub inner: R,
+}
+
+impl<'r, R: Responder<'r, 'static>> Responder<'r, 'static> for WithETag<R> {
+    fn respond_to(self, req: &'r Request<'_>) -> response::Result<'static> {
+        Response::build_from(self.inner.respond_to(req)?)
+            .raw_header("ETag", self.etag)
+            .ok()
+    }
+}
+
+/// Wraps a response to send it with a `Retry-After` header, in seconds.
+pub struct WithRetryAfter<R> {
+    pub seconds: u64,
+    pub inner: R,
+}
+
+impl<'r, R: Responder<'r, 'static>> Responder<'r, 'static> for WithRetryAfter<R> {
+    fn respond_to(self, req: &'r Request<'_>) -> response::Result<'static> {
+        Response::build_from(self.inner.respond_to(req)?)
+            .raw_header("Retry-After", self.seconds.to_string())
+            .ok()
+    }
+}
@@ -0,0 +1,42 @@
+use crate::filtering::{ListError, ListParams};
+use crate::pagination::Pagination;
+use crate::permissions::AuditRead;
+use crate::repositories::AuditLogRepository;
+use crate::responses::{handle_db_error, invalid_query_error};
+use crate::rocket_routes::{DbConn, Require};
+use rocket::response::status::Custom;
+use rocket::serde::json::{serde_json::json, Value};
+use rocket_db_pools::Connection;
+use std::collections::HashMap;
+
+/// Lists audit log entries, newest first, e.g. `?resource=crates&id=42`.
+#[rocket::get("/?<limit>&<offset>&<cursor>&<sort>&<filters..>")]
+pub async fn get_audit_log(
+    mut db: Connection<DbConn>,
+    limit: Option<String>,
+    offset: Option<String>,
+    cursor: Option<String>,
+    sort: Option<String>,
+    filters: HashMap<String, String>,
+    _user: Require<AuditRead>,
+) -> Result<Value, Custom<Value>> {
+    let pagination = Pagination::from_query(limit.as_deref(), offset.as_deref(), cursor.as_deref())
+        .map_err(invalid_query_error)?;
+    let params = ListParams::from_query(filters, sort.as_deref()).map_err(invalid_query_error)?;
+
+    AuditLogRepository::find_multiple(&mut db, pagination, &params)
+        .await
+        .map(|page| json!(page))
+        .map_err(|e| match e {
+            ListError::Query(e) => invalid_query_error(e),
+            ListError::Database(e) => handle_db_error(
+                e,
+                "Failed to fetch audit log".to_string(),
+                "fetching audit log".to_string(),
+            ),
+        })
+}
+
+pub fn routes() -> Vec<rocket::Route> {
+    rocket::routes![get_audit_log]
+}
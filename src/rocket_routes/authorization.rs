@@ -1,36 +1,363 @@
-use crate::auth::{authorize_user, Credentials};
-use crate::repositories::UserRepository;
-use crate::rocket_routes::{server_error, CacheConn, DbConn};
+use crate::auth::{
+    authorize_user, hash_password, Credentials, PasswordReset, PasswordResetRequest, RefreshRequest,
+};
+use crate::jwt::{self, Authentication};
+use crate::lockout::{self, LockoutConfig, Subject};
+use crate::mail::HtmlMailer;
+use crate::models::{AuditAction, NewAuditEntry, User};
+use crate::password::{self, PasswordConfig};
+use crate::repositories::{
+    AuditLogRepository, RoleRepository, RustaceanRepository, UserRepository,
+};
+use crate::responses::{validation_error, WithRetryAfter};
+use crate::rocket_routes::{server_error, CacheConn, ClientInfo, DbConn, SessionToken};
+use crate::sessions::{self, Refresh, SessionConfig};
+use crate::validation::ValidationErrors;
+use chrono::{Datelike, Utc};
 use diesel::result::Error as DieselError;
 use rocket::http::Status;
-use rocket::response::status::Custom;
+use rocket::response::status::{Custom, NoContent};
 use rocket::serde::json::{serde_json::json, Json, Value};
-use rocket_db_pools::deadpool_redis::redis::AsyncCommands;
+use rocket::{Responder, State};
 use rocket_db_pools::Connection;
+use tera::{Context, Tera};
 
+/// Why a login, or another attempt limited like one, was refused.
+#[derive(Responder)]
+pub enum LoginError {
+    /// Too many failed attempts: the client has to wait before trying again.
+    Throttled(WithRetryAfter<Custom<Value>>),
+    Failed(Custom<Value>),
+}
+
+impl From<Custom<Value>> for LoginError {
+    fn from(error: Custom<Value>) -> Self {
+        LoginError::Failed(error)
+    }
+}
+
+/// Refuses an attempt at a user's password while failed ones have it waiting.
+pub(crate) async fn check_throttle(
+    cache: &mut Connection<CacheConn>,
+    username: &str,
+    ip: Option<&str>,
+) -> Result<(), LoginError> {
+    let wait = lockout::retry_after(&mut **cache, Subject::Username(username), ip)
+        .await
+        .map_err(|e| server_error(e.into()))?;
+    match wait {
+        Some(seconds) => Err(LoginError::Throttled(WithRetryAfter {
+            seconds,
+            inner: Custom(
+                Status::TooManyRequests,
+                json!({ "error": "Too many failed login attempts" }),
+            ),
+        })),
+        None => Ok(()),
+    }
+}
+
+/// Counts a failed login against its username and client IP, recording any lockout
+/// of an existing user in the audit log.
+pub(crate) async fn record_failed_login(
+    db: &mut Connection<DbConn>,
+    cache: &mut Connection<CacheConn>,
+    config: &LockoutConfig,
+    user: Option<&User>,
+    username: &str,
+    ip: Option<&str>,
+) -> Result<(), Custom<Value>> {
+    let locked = lockout::record_failure(&mut **cache, config, Subject::Username(username), ip)
+        .await
+        .map_err(|e| server_error(e.into()))?;
+
+    for subject in locked {
+        match subject {
+            Subject::Username(username) => {
+                rocket::warn!("Locked out user {} after failed logins", username);
+                let Some(user) = user else {
+                    continue;
+                };
+                let entry = NewAuditEntry {
+                    user_id: None,
+                    action: AuditAction::Lock,
+                    resource: "users".to_string(),
+                    resource_id: user.id,
+                    before: None,
+                    after: Some(json!({ "ip": ip, "seconds": config.login_lockout_duration })),
+                };
+                AuditLogRepository::create(db, entry)
+                    .await
+                    .map_err(|e| server_error(e.into()))?;
+            }
+            Subject::Email(subject) | Subject::Ip(_, subject) => {
+                rocket::warn!("Locked out {} after failed logins", subject)
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Logs a user in. Failed attempts are limited per username and client IP: past
+/// the limits further attempts get a 429 with `Retry-After`.
 #[rocket::post("/login", format = "json", data = "<credentials>")]
 pub async fn login(
     mut db: Connection<DbConn>,
     mut cache: Connection<CacheConn>,
     credentials: Json<Credentials>,
+    client: ClientInfo,
+    config: &State<SessionConfig>,
+    lockout_config: &State<LockoutConfig>,
+    authentication: &State<Authentication>,
+) -> Result<Value, LoginError> {
+    let credentials = credentials.into_inner();
+    let username = credentials.username.clone();
+    let ip = client.ip.as_deref();
+
+    check_throttle(&mut cache, &username, ip).await?;
+
+    let user = match UserRepository::find_by_username(&mut db, &username).await {
+        Ok(user) => Some(user),
+        Err(DieselError::NotFound) => None,
+        Err(e) => return Err(server_error(e.into()).into()),
+    };
+    let session_id = user
+        .as_ref()
+        .and_then(|user| authorize_user(user, credentials).ok());
+    let (Some(user), Some(session_id)) = (&user, session_id) else {
+        record_failed_login(
+            &mut db,
+            &mut cache,
+            lockout_config,
+            user.as_ref(),
+            &username,
+            ip,
+        )
+        .await?;
+        return Err(Custom(Status::Unauthorized, json!("Wrong credentials")).into());
+    };
+    lockout::clear(&mut *cache, &username)
+        .await
+        .map_err(|e| server_error(e.into()))?;
+
+    if let Some(keys) = authentication.jwt_keys() {
+        let roles = RoleRepository::find_by_user(&mut db, user)
+            .await
+            .map_err(|e| server_error(e.into()))?
+            .into_iter()
+            .map(|role| role.code)
+            .collect();
+        let token = keys
+            .issue(user, roles)
+            .map_err(|e| server_error(e.into()))?;
+        return Ok(json!({
+            "token": token,
+        }));
+    }
+
+    let session = sessions::start(
+        &mut *cache,
+        config,
+        user.id,
+        session_id,
+        client.ip,
+        client.user_agent,
+    )
+    .await
+    .map_err(|e| server_error(e.into()))?;
+
+    Ok(json!({
+        "token": session.token,
+        "refresh_token": session.refresh_token,
+    }))
+}
+
+/// Trades a refresh token for a new session token and refresh token.
+#[rocket::post("/token/refresh", format = "json", data = "<request>")]
+pub async fn refresh_token(
+    mut cache: Connection<CacheConn>,
+    request: Json<RefreshRequest>,
+    config: &State<SessionConfig>,
 ) -> Result<Value, Custom<Value>> {
-    let user = match UserRepository::find_by_username(&mut db, &credentials.username).await {
-        Ok(user) => user,
-        Err(DieselError::NotFound) => {
-            return Err(Custom(Status::Unauthorized, json!("Wrong credentials")));
+    match sessions::refresh(&mut *cache, config, &request.refresh_token).await {
+        Ok(Refresh::Rotated(session)) => Ok(json!({
+            "token": session.token,
+            "refresh_token": session.refresh_token,
+        })),
+        Ok(Refresh::Invalid) => Err(Custom(Status::Unauthorized, json!("Invalid refresh token"))),
+        Ok(Refresh::Reused) => {
+            rocket::warn!("Refresh token reused, revoked its session");
+            Err(Custom(Status::Unauthorized, json!("Invalid refresh token")))
         }
+        Err(e) => Err(server_error(e.into())),
+    }
+}
+
+/// Ends the session the request was made with, or in JWT mode denies its token.
+#[rocket::post("/logout")]
+pub async fn logout(
+    mut cache: Connection<CacheConn>,
+    token: SessionToken,
+    _user: User,
+    authentication: &State<Authentication>,
+) -> Result<NoContent, Custom<Value>> {
+    let result = match authentication
+        .jwt_keys()
+        .and_then(|keys| keys.verify(&token.0))
+    {
+        Some(claims) => jwt::deny(&mut *cache, &claims).await,
+        None => sessions::end(&mut *cache, &token.0).await,
+    };
+    result
+        .map(|_| NoContent)
+        .map_err(|e| server_error(e.into()))
+}
+
+/// Emails the password reset template to a user, in the background so that the
+/// response does not tell whether there was anyone to email.
+fn send_reset_email(to: String, username: String, link: String, minutes: u64) {
+    rocket::tokio::task::spawn_blocking(move || {
+        let mut context = Context::new();
+        context.insert("username", &username);
+        context.insert("link", &link);
+        context.insert("minutes", &minutes);
+        context.insert("year", &Utc::now().year());
+
+        let result = Tera::new("templates/**/*.html")
+            .map_err(|e| e.into())
+            .and_then(|tera| {
+                HtmlMailer::builder()
+                    .template_engine(tera)
+                    .smtp_from_env()
+                    .map_err(|e| e.into())
+            })
+            .and_then(|builder| {
+                builder.build().send_with_subject(
+                    to,
+                    "Reset your Cr8s password".to_string(),
+                    "email/password_reset.html",
+                    context,
+                )
+            });
+        if let Err(e) = result {
+            rocket::error!("Failed to send password reset email: {}", e);
+        }
+    });
+}
+
+/// Emails a password reset link to the rustacean with the given address, when it is
+/// linked to a user. The answer is the same either way, so as not to reveal accounts.
+/// Requests are limited per address and client IP like logins, but counted apart from
+/// them, so past the limits further requests get a 429 with `Retry-After`.
+#[rocket::post("/password/reset", format = "json", data = "<request>")]
+pub async fn request_password_reset(
+    mut db: Connection<DbConn>,
+    mut cache: Connection<CacheConn>,
+    request: Json<PasswordResetRequest>,
+    client: ClientInfo,
+    config: &State<PasswordConfig>,
+    lockout_config: &State<LockoutConfig>,
+) -> Result<Custom<Value>, LoginError> {
+    let email = request.email.to_lowercase();
+    let ip = client.ip.as_deref();
+    let wait = lockout::retry_after(&mut *cache, Subject::Email(&email), ip)
+        .await
+        .map_err(|e| server_error(e.into()))?;
+    if let Some(seconds) = wait {
+        return Err(LoginError::Throttled(WithRetryAfter {
+            seconds,
+            inner: Custom(
+                Status::TooManyRequests,
+                json!({ "error": "Too many password reset requests" }),
+            ),
+        }));
+    }
+    lockout::record_failure(&mut *cache, lockout_config, Subject::Email(&email), ip)
+        .await
+        .map_err(|e| server_error(e.into()))?;
+
+    let rustacean = match RustaceanRepository::find_by_email(&mut db, &request.email).await {
+        Ok(rustacean) => Some(rustacean),
+        Err(DieselError::NotFound) => None,
+        Err(e) => return Err(server_error(e.into()).into()),
+    };
+
+    if let Some((email, user_id)) = rustacean.and_then(|r| Some((r.email, r.user_id?))) {
+        let user = UserRepository::find(&mut db, user_id)
+            .await
+            .map_err(|e| server_error(e.into()))?;
+        let token = password::issue_reset(&mut *cache, config, user.id)
+            .await
+            .map_err(|e| server_error(e.into()))?;
+        send_reset_email(
+            email,
+            user.username,
+            config.reset_link(&token),
+            config.password_reset_timeout / 60,
+        );
+    }
+
+    Ok(Custom(
+        Status::Accepted,
+        json!({ "message": "If the address belongs to an account, a reset link is on its way" }),
+    ))
+}
+
+/// Sets a new password with a token from a reset email. The user is signed out
+/// everywhere, in case someone else knew the old password, and any lockout is lifted.
+#[rocket::post("/password/reset/confirm", format = "json", data = "<reset>")]
+pub async fn reset_password(
+    mut db: Connection<DbConn>,
+    mut cache: Connection<CacheConn>,
+    reset: Json<PasswordReset>,
+    config: &State<PasswordConfig>,
+    authentication: &State<Authentication>,
+) -> Result<NoContent, Custom<Value>> {
+    let reset = reset.into_inner();
+    let invalid_token = || {
+        Custom(
+            Status::BadRequest,
+            json!({ "error": "Invalid or expired reset token" }),
+        )
+    };
+
+    let user_id = password::find_reset(&mut *cache, &reset.token)
+        .await
+        .map_err(|e| server_error(e.into()))?
+        .ok_or_else(invalid_token)?;
+    let user = match UserRepository::find(&mut db, user_id).await {
+        Ok(user) => user,
+        Err(DieselError::NotFound) => return Err(invalid_token()),
         Err(e) => return Err(server_error(e.into())),
     };
+    config
+        .check(&user.username, &reset.password)
+        .map_err(|e| validation_error(ValidationErrors::single("password", e)))?;
 
-    let session_id = authorize_user(&user, credentials.into_inner())
-        .map_err(|_| Custom(Status::Unauthorized, json!("Wrong credentials")))?;
+    // Only used up once the password passes, so a refused one can be fixed with the same link.
+    let taken = password::take_reset(&mut *cache, &reset.token)
+        .await
+        .map_err(|e| server_error(e.into()))?;
+    if taken != Some(user.id) {
+        return Err(invalid_token());
+    }
 
-    cache
-        .set_ex::<String, i32, ()>(format!("sessions/{}", session_id), user.id, 3 * 60 * 60)
+    let password_hash =
+        hash_password(reset.password).map_err(|e| server_error(e.to_string().into()))?;
+    UserRepository::set_password(&mut db, user.id, password_hash)
         .await
         .map_err(|e| server_error(e.into()))?;
 
-    Ok(json!({
-        "token": session_id,
-    }))
+    sessions::revoke_all(&mut *cache, user.id)
+        .await
+        .map_err(|e| server_error(e.into()))?;
+    if let Some(keys) = authentication.jwt_keys() {
+        jwt::deny_all(&mut *cache, user.id, keys.ttl(), None)
+            .await
+            .map_err(|e| server_error(e.into()))?;
+    }
+    lockout::clear(&mut *cache, &user.username)
+        .await
+        .map_err(|e| server_error(e.into()))?;
+    Ok(NoContent)
 }
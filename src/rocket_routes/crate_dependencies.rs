@@ -0,0 +1,168 @@
+use crate::filtering::QueryError;
+use crate::models::{Crate, CrateDependency, DependencyKind, NewCrateDependency, User};
+use crate::permissions::CratesWrite;
+use crate::repositories::{CrateDependencyRepository, DependencyError};
+use crate::responses::{handle_db_error, invalid_query_error, not_found, validation_error};
+use crate::rocket_routes::crates::{find_crate, find_owned_crate};
+use crate::rocket_routes::{DbConn, Require};
+use crate::validation::Validate;
+use diesel::result::{DatabaseErrorKind, Error as DieselError};
+use rocket::http::Status;
+use rocket::response::status::{Custom, NoContent};
+use rocket::serde::json::{serde_json::json, Json, Value};
+use rocket_db_pools::Connection;
+use std::str::FromStr;
+
+type HandlerResult<T> = Result<T, Custom<Value>>;
+type Db = Connection<DbConn>;
+
+const DEFAULT_TREE_DEPTH: i32 = 5;
+const MAX_TREE_DEPTH: i32 = 20;
+
+fn edges_json(edges: Vec<(CrateDependency, Crate)>) -> Value {
+    let items: Vec<Value> = edges
+        .into_iter()
+        .map(|(edge, krate)| json!({ "edge": edge, "crate": krate }))
+        .collect();
+    json!({ "items": items })
+}
+
+#[rocket::get("/<crate_id>/dependencies")]
+pub async fn get_crate_dependencies(
+    mut db: Db,
+    crate_id: i32,
+    _user: User,
+) -> HandlerResult<Value> {
+    let context = format!("fetching dependencies of crate {}", crate_id);
+    find_crate(&mut db, crate_id, &context).await?;
+
+    CrateDependencyRepository::find_dependencies(&mut db, crate_id)
+        .await
+        .map(edges_json)
+        .map_err(|e| handle_db_error(e, format!("Failed {}", context), context))
+}
+
+#[rocket::get("/<crate_id>/dependents")]
+pub async fn get_crate_dependents(mut db: Db, crate_id: i32, _user: User) -> HandlerResult<Value> {
+    let context = format!("fetching dependents of crate {}", crate_id);
+    find_crate(&mut db, crate_id, &context).await?;
+
+    CrateDependencyRepository::find_dependents(&mut db, crate_id)
+        .await
+        .map(edges_json)
+        .map_err(|e| handle_db_error(e, format!("Failed {}", context), context))
+}
+
+#[rocket::get("/<crate_id>/dependency-tree?<depth>")]
+pub async fn get_crate_dependency_tree(
+    mut db: Db,
+    crate_id: i32,
+    depth: Option<i32>,
+    _user: User,
+) -> HandlerResult<Value> {
+    let depth = depth.unwrap_or(DEFAULT_TREE_DEPTH);
+    if !(1..=MAX_TREE_DEPTH).contains(&depth) {
+        return Err(invalid_query_error(QueryError::new(
+            "depth",
+            format!("Must be between 1 and {}", MAX_TREE_DEPTH),
+        )));
+    }
+    let context = format!("fetching dependency tree of crate {}", crate_id);
+    find_crate(&mut db, crate_id, &context).await?;
+
+    CrateDependencyRepository::find_tree(&mut db, crate_id, depth)
+        .await
+        .map(|edges| json!({ "crate_id": crate_id, "depth": depth, "items": edges }))
+        .map_err(|e| handle_db_error(e, format!("Failed {}", context), context))
+}
+
+#[rocket::post("/<crate_id>/dependencies", format = "json", data = "<data>")]
+pub async fn create_crate_dependency(
+    mut db: Db,
+    crate_id: i32,
+    data: Json<NewCrateDependency>,
+    user: Require<CratesWrite>,
+) -> HandlerResult<Custom<Value>> {
+    let mut new_dependency = data.into_inner();
+    new_dependency.crate_id = crate_id;
+    new_dependency.validate().map_err(validation_error)?;
+    // Deleted crates are still there for the foreign keys, so look them up first.
+    let context = format!("adding dependency to crate {}", crate_id);
+    find_owned_crate(&mut db, &user.0, crate_id, &context).await?;
+    find_crate(&mut db, new_dependency.dependency_id, &context).await?;
+
+    CrateDependencyRepository::create(&mut db, new_dependency)
+        .await
+        .map(|edge| Custom(Status::Created, json!(edge)))
+        .map_err(|e| match e {
+            DependencyError::Cycle => Custom(
+                Status::Conflict,
+                json!({ "error": "Dependency would create a cycle" }),
+            ),
+            DependencyError::Database(DieselError::DatabaseError(
+                DatabaseErrorKind::ForeignKeyViolation,
+                _,
+            )) => not_found(),
+            DependencyError::Database(DieselError::DatabaseError(
+                DatabaseErrorKind::UniqueViolation,
+                _,
+            )) => Custom(
+                Status::Conflict,
+                json!({ "error": "Dependency already exists" }),
+            ),
+            DependencyError::Database(e) => handle_db_error(
+                e,
+                format!("Failed to add dependency to crate {}", crate_id),
+                "adding crate dependency".to_string(),
+            ),
+        })
+}
+
+#[rocket::delete("/<crate_id>/dependencies/<dependency_id>?<kind>")]
+pub async fn delete_crate_dependency(
+    mut db: Db,
+    crate_id: i32,
+    dependency_id: i32,
+    kind: Option<&str>,
+    user: Require<CratesWrite>,
+) -> HandlerResult<NoContent> {
+    let kind = kind
+        .map(|kind| {
+            DependencyKind::from_str(kind)
+                .map_err(|_| invalid_query_error(QueryError::invalid_value("kind", kind)))
+        })
+        .transpose()?;
+    find_owned_crate(
+        &mut db,
+        &user.0,
+        crate_id,
+        &format!(
+            "removing dependency {} from crate {}",
+            dependency_id, crate_id
+        ),
+    )
+    .await?;
+
+    match CrateDependencyRepository::delete(&mut db, crate_id, dependency_id, kind).await {
+        Ok(0) => Err(not_found()),
+        Ok(_) => Ok(NoContent),
+        Err(e) => Err(handle_db_error(
+            e,
+            format!(
+                "Failed to remove dependency {} from crate {}",
+                dependency_id, crate_id
+            ),
+            "removing crate dependency".to_string(),
+        )),
+    }
+}
+
+pub fn routes() -> Vec<rocket::Route> {
+    rocket::routes![
+        get_crate_dependencies,
+        get_crate_dependents,
+        get_crate_dependency_tree,
+        create_crate_dependency,
+        delete_crate_dependency
+    ]
+}
@@ -0,0 +1,175 @@
+use crate::models::{NewCrateVersion, UpdateCrateVersion, User};
+use crate::permissions::CratesWrite;
+use crate::repositories::{CrateRepository, CrateVersionRepository};
+use crate::responses::{handle_db_error, not_found, validation_error};
+use crate::rocket_routes::crates::{find_crate, find_owned_crate, is_owner, not_owner};
+use crate::rocket_routes::{DbConn, Require};
+use crate::validation::{parse_semver, ValidationErrors};
+use diesel::result::{DatabaseErrorKind, Error as DieselError};
+use diesel_async::scoped_futures::ScopedFutureExt;
+use diesel_async::AsyncConnection;
+use rocket::http::Status;
+use rocket::response::status::{Custom, NoContent};
+use rocket::serde::json::{serde_json::json, Json, Value};
+use rocket_db_pools::Connection;
+
+type HandlerResult<T> = Result<T, Custom<Value>>;
+type Db = Connection<DbConn>;
+
+#[rocket::get("/<crate_id>/versions")]
+pub async fn get_crate_versions(mut db: Db, crate_id: i32, _user: User) -> HandlerResult<Value> {
+    let context = || format!("fetching versions of crate {}", crate_id);
+    find_crate(&mut db, crate_id, &context()).await?;
+
+    let versions = CrateVersionRepository::find_by_crate(&mut db, crate_id)
+        .await
+        .map_err(|e| handle_db_error(e, format!("Failed {}", context()), context()))?;
+    let latest = CrateVersionRepository::latest(&versions).map(|v| v.version.clone());
+
+    Ok(json!({
+        "items": versions,
+        "latest": latest,
+    }))
+}
+
+#[rocket::get("/<crate_id>/versions/<version>")]
+pub async fn view_crate_version(
+    mut db: Db,
+    crate_id: i32,
+    version: &str,
+    _user: User,
+) -> HandlerResult<Value> {
+    find_crate(
+        &mut db,
+        crate_id,
+        &format!("fetching version {} of crate {}", version, crate_id),
+    )
+    .await?;
+
+    CrateVersionRepository::find_by_version(&mut db, crate_id, version)
+        .await
+        .map(|v| json!(v))
+        .map_err(|e| match e {
+            DieselError::NotFound => not_found(),
+            e => handle_db_error(
+                e,
+                format!("Failed to fetch version {} of crate {}", version, crate_id),
+                "fetching crate version".to_string(),
+            ),
+        })
+}
+
+#[rocket::post("/<crate_id>/versions", format = "json", data = "<data>")]
+pub async fn create_crate_version(
+    mut db: Db,
+    crate_id: i32,
+    data: Json<NewCrateVersion>,
+    user: Require<CratesWrite>,
+) -> HandlerResult<Custom<Value>> {
+    let mut new_version = data.into_inner();
+    new_version.crate_id = crate_id;
+    parse_semver(&new_version.version)
+        .map_err(|e| validation_error(ValidationErrors::single("version", e)))?;
+    // Deleted crates are still there for the foreign key, so look the crate up first.
+    find_owned_crate(
+        &mut db,
+        &user.0,
+        crate_id,
+        &format!("creating version of crate {}", crate_id),
+    )
+    .await?;
+
+    CrateVersionRepository::create(&mut db, new_version)
+        .await
+        .map(|v| Custom(Status::Created, json!(v)))
+        .map_err(|e| match e {
+            DieselError::DatabaseError(DatabaseErrorKind::ForeignKeyViolation, _) => not_found(),
+            DieselError::DatabaseError(DatabaseErrorKind::UniqueViolation, _) => Custom(
+                Status::Conflict,
+                json!({ "error": "Version already exists" }),
+            ),
+            e => handle_db_error(
+                e,
+                format!("Failed to create version of crate {}", crate_id),
+                "creating crate version".to_string(),
+            ),
+        })
+}
+
+#[rocket::put("/<crate_id>/versions/<version>", format = "json", data = "<data>")]
+pub async fn update_crate_version(
+    mut db: Db,
+    crate_id: i32,
+    version: &str,
+    data: Json<UpdateCrateVersion>,
+    user: Require<CratesWrite>,
+) -> HandlerResult<Value> {
+    find_owned_crate(
+        &mut db,
+        &user.0,
+        crate_id,
+        &format!("updating version {} of crate {}", version, crate_id),
+    )
+    .await?;
+
+    CrateVersionRepository::update(&mut db, crate_id, version.to_string(), data.into_inner())
+        .await
+        .map(|v| json!(v))
+        .map_err(|e| match e {
+            DieselError::NotFound => not_found(),
+            e => handle_db_error(
+                e,
+                format!("Failed to update version {} of crate {}", version, crate_id),
+                "updating crate version".to_string(),
+            ),
+        })
+}
+
+#[rocket::delete("/<crate_id>/versions/<version>")]
+pub async fn delete_crate_version(
+    mut db: Db,
+    crate_id: i32,
+    version: &str,
+    user: Require<CratesWrite>,
+) -> HandlerResult<NoContent> {
+    let context = || format!("deleting version {} of crate {}", version, crate_id);
+
+    db.transaction(|conn| {
+        async move {
+            // Locking the crate serializes deletes of its versions, so that two of them
+            // cannot each see the other's version as the one left over.
+            let krate = CrateRepository::find_for_update(conn, crate_id).await?;
+            if !is_owner(conn, &user.0, &krate).await? {
+                return Ok(Err(not_owner()));
+            }
+            let versions = CrateVersionRepository::find_by_crate(conn, crate_id).await?;
+            if !versions.iter().any(|v| v.version == version) {
+                return Ok(Err(not_found()));
+            }
+            if versions.len() == 1 {
+                return Ok(Err(Custom(
+                    Status::Conflict,
+                    json!({ "error": "Cannot delete the only version of a crate" }),
+                )));
+            }
+            CrateVersionRepository::delete(conn, crate_id, version.to_string()).await?;
+            Ok(Ok(NoContent))
+        }
+        .scope_boxed()
+    })
+    .await
+    .map_err(|e| match e {
+        DieselError::NotFound => not_found(),
+        e => handle_db_error(e, format!("Failed {}", context()), context()),
+    })?
+}
+
+pub fn routes() -> Vec<rocket::Route> {
+    rocket::routes![
+        get_crate_versions,
+        view_crate_version,
+        create_crate_version,
+        update_crate_version,
+        delete_crate_version
+    ]
+}
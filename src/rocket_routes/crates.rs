@@ -1,6 +1,13 @@
 use crate::crud_handlers;
-use crate::models::{NewCrate, UpdateCrate};
-use crate::repositories::CrateRepository;
+use crate::filtering::{ListError, QueryError};
+use crate::models::{Crate, CrateVersion, NewCrate, RustaceanOwned, UpdateCrate, User};
+use crate::pagination::Pagination;
+use crate::permissions::{CratesAny, CratesDelete, CratesRestore, CratesWrite, Permission};
+use crate::repositories::{
+    CrateRepository, CrateVersionRepository, RustaceanRepository, UserRepository,
+};
+use crate::responses::{handle_db_error, invalid_query_error, not_found, validation_error};
+use crate::validation::ValidationErrors;
 
 crud_handlers!(
     "crate",
@@ -8,19 +15,152 @@ crud_handlers!(
     CrateRepository,
     NewCrate,
     UpdateCrate,
+    permissions(
+        write: CratesWrite,
+        restore: CratesRestore,
+        delete: CratesDelete,
+        owner: is_owner,
+        replace: check_version
+    ),
     get_crates,
     view_crate,
     create_crate,
     update_crate,
-    delete_crate
+    patch_crate,
+    restore_crate,
+    bulk_create_crates,
+    bulk_patch_crates,
+    delete_crate,
+    bulk_delete_crates
 );
 
+/// Whether a user may create, update or delete a crate, or its versions and dependencies:
+/// those without the `crates:any` permission only get to change crates of the rustacean
+/// linked to their account, and cannot hand them over to another rustacean.
+pub(crate) async fn is_owner(
+    conn: &mut diesel_async::AsyncPgConnection,
+    user: &User,
+    krate: &impl RustaceanOwned,
+) -> diesel::QueryResult<bool> {
+    if UserRepository::has_permission(conn, user.id, CratesAny::CODE).await? {
+        return Ok(true);
+    }
+    RustaceanRepository::is_linked_to(conn, krate.rustacean_id(), user.id).await
+}
+
+/// Rejects a `PUT` or `PATCH` whose `version` would not become the crate's latest one:
+/// a crate always points at the latest entry of its version history, so older versions
+/// are added through `/crates/<id>/versions` instead.
+async fn check_version(
+    conn: &mut diesel_async::AsyncPgConnection,
+    _user: &User,
+    before: &Crate,
+    data: &NewCrate,
+) -> diesel::QueryResult<HandlerResult<()>> {
+    if data.version == before.version {
+        return Ok(Ok(()));
+    }
+    let mut versions = CrateVersionRepository::find_by_crate(conn, before.id).await?;
+    if !versions.iter().any(|v| v.version == data.version) {
+        versions.push(CrateVersion {
+            id: 0,
+            crate_id: before.id,
+            version: data.version.clone(),
+            changelog: None,
+            yanked: false,
+            published_at: chrono::Utc::now().naive_utc(),
+        });
+    }
+    if CrateVersionRepository::latest(&versions).is_some_and(|v| v.version == data.version) {
+        return Ok(Ok(()));
+    }
+    Ok(Err(validation_error(ValidationErrors::single(
+        "version",
+        format!(
+            "'{}' would not be the latest version of the crate, add it to its versions instead",
+            data.version
+        ),
+    ))))
+}
+
+/// The answer to a user changing a crate they do not own.
+pub(crate) fn not_owner() -> Custom<Value> {
+    Custom(
+        Status::Forbidden,
+        json!({ "error": "Not allowed to change this crate" }),
+    )
+}
+
+/// Fails with a 404 unless the crate exists and is not deleted.
+pub(crate) async fn find_crate(db: &mut Db, crate_id: i32, context: &str) -> HandlerResult<Crate> {
+    CrateRepository::find(db, crate_id)
+        .await
+        .map_err(|e| match e {
+            diesel::result::Error::NotFound => not_found(),
+            e => handle_db_error(e, format!("Failed {}", context), context.to_string()),
+        })
+}
+
+/// Fails with a 404 unless the crate exists and is not deleted, and with a 403 unless
+/// the user may change it.
+pub(crate) async fn find_owned_crate(
+    db: &mut Db,
+    user: &User,
+    crate_id: i32,
+    context: &str,
+) -> HandlerResult<Crate> {
+    let krate = find_crate(db, crate_id, context).await?;
+    match is_owner(db, user, &krate).await {
+        Ok(true) => Ok(krate),
+        Ok(false) => Err(not_owner()),
+        Err(e) => Err(handle_db_error(
+            e,
+            format!("Failed {}", context),
+            context.to_string(),
+        )),
+    }
+}
+
+#[rocket::get("/search?<q>&<limit>&<offset>&<cursor>")]
+pub async fn search_crates(
+    mut db: Db,
+    q: Option<String>,
+    limit: Option<String>,
+    offset: Option<String>,
+    cursor: Option<String>,
+    _user: User,
+) -> HandlerResult<Value> {
+    let q = q
+        .filter(|q| !q.trim().is_empty())
+        .ok_or_else(|| invalid_query_error(QueryError::new("q", "Search query is required")))?;
+    let pagination = Pagination::from_query(limit.as_deref(), offset.as_deref(), cursor.as_deref())
+        .map_err(invalid_query_error)?;
+
+    CrateRepository::search(&mut db, &q, pagination)
+        .await
+        .map(|page| json!(page))
+        .map_err(|e| match e {
+            ListError::Query(e) => invalid_query_error(e),
+            ListError::Database(e) => handle_db_error(
+                e,
+                "Failed to search crates".to_string(),
+                "searching crates".to_string(),
+            ),
+        })
+}
+
 pub fn routes() -> Vec<rocket::Route> {
     rocket::routes![
         get_crates,
+        search_crates,
         view_crate,
         create_crate,
         update_crate,
-        delete_crate
+        patch_crate,
+        restore_crate,
+        bulk_create_crates,
+        bulk_patch_crates,
+        delete_crate,
+        bulk_delete_crates
     ]
 }
@@ -0,0 +1,269 @@
+use crate::auth::{generate_api_key, hash_password, verify_secret, PasswordChange};
+use crate::jwt::{self, Authentication};
+use crate::lockout::LockoutConfig;
+use crate::models::{ApiKeySettings, NewApiKey, User};
+use crate::password::PasswordConfig;
+use crate::repositories::{ApiKeyRepository, UserRepository};
+use crate::responses::{handle_db_error, not_found, validation_error};
+use crate::rocket_routes::authorization::{check_throttle, record_failed_login, LoginError};
+use crate::rocket_routes::{
+    server_error, CacheConn, ClientInfo, DbConn, InteractiveUser, SessionToken,
+};
+use crate::sessions;
+use crate::validation::{Validate, ValidationErrors};
+use diesel::result::Error as DieselError;
+use rocket::http::Status;
+use rocket::response::status::{Custom, NoContent};
+use rocket::serde::json::{serde_json::json, Json, Value};
+use rocket::State;
+use rocket_db_pools::Connection;
+
+type HandlerResult<T> = Result<T, Custom<Value>>;
+
+/// Lists the signed-in user's live sessions, oldest first, flagging the one
+/// the request was made with.
+#[rocket::get("/sessions")]
+pub async fn get_sessions(
+    mut cache: Connection<CacheConn>,
+    user: InteractiveUser,
+    token: SessionToken,
+) -> Result<Value, Custom<Value>> {
+    let sessions = sessions::list(&mut *cache, user.0.id)
+        .await
+        .map_err(|e| server_error(e.into()))?;
+
+    Ok(json!(sessions
+        .into_iter()
+        .map(|session| json!({
+            "id": session.id,
+            "created_at": session.created_at,
+            "ip": session.ip,
+            "user_agent": session.user_agent,
+            "current": session.token == token.0,
+        }))
+        .collect::<Vec<_>>()))
+}
+
+/// Ends one of the signed-in user's sessions, e.g. one left open on another device.
+#[rocket::delete("/sessions/<id>")]
+pub async fn delete_session(
+    mut cache: Connection<CacheConn>,
+    id: &str,
+    user: InteractiveUser,
+) -> Result<NoContent, Custom<Value>> {
+    match sessions::revoke(&mut *cache, user.0.id, id).await {
+        Ok(true) => Ok(NoContent),
+        Ok(false) => Err(not_found()),
+        Err(e) => Err(server_error(e.into())),
+    }
+}
+
+/// Changes the signed-in user's password, given the current one. Wrong guesses at
+/// the current password count as failed logins. Every other session and token of the
+/// user ends, in case someone else knew the old password.
+#[rocket::post("/password", format = "json", data = "<change>")]
+#[allow(clippy::too_many_arguments)]
+pub async fn change_password(
+    mut db: Connection<DbConn>,
+    mut cache: Connection<CacheConn>,
+    change: Json<PasswordChange>,
+    client: ClientInfo,
+    config: &State<PasswordConfig>,
+    lockout_config: &State<LockoutConfig>,
+    authentication: &State<Authentication>,
+    user: InteractiveUser,
+    token: SessionToken,
+) -> Result<NoContent, LoginError> {
+    let change = change.into_inner();
+    let ip = client.ip.as_deref();
+    // Loaded afresh, as in JWT mode the user comes from a token, without a password hash.
+    let user = UserRepository::find(&mut db, user.0.id)
+        .await
+        .map_err(|e| server_error(e.into()))?;
+    check_throttle(&mut cache, &user.username, ip).await?;
+
+    if !verify_secret(&user.password, &change.current_password) {
+        record_failed_login(
+            &mut db,
+            &mut cache,
+            lockout_config,
+            Some(&user),
+            &user.username,
+            ip,
+        )
+        .await?;
+        return Err(validation_error(ValidationErrors::single(
+            "current_password",
+            "Does not match",
+        ))
+        .into());
+    }
+    config
+        .check(&user.username, &change.new_password)
+        .map_err(|e| validation_error(ValidationErrors::single("new_password", e)))?;
+
+    let password_hash =
+        hash_password(change.new_password).map_err(|e| server_error(e.to_string().into()))?;
+    UserRepository::set_password(&mut db, user.id, password_hash)
+        .await
+        .map_err(|e| server_error(e.into()))?;
+
+    match authentication.jwt_keys() {
+        Some(keys) => {
+            let current = keys.verify(&token.0).map(|claims| claims.jti);
+            jwt::deny_all(&mut *cache, user.id, keys.ttl(), current.as_deref())
+                .await
+                .map_err(|e| server_error(e.into()))?;
+        }
+        None => {
+            sessions::revoke_others(&mut *cache, user.id, &token.0)
+                .await
+                .map_err(|e| server_error(e.into()))?;
+        }
+    }
+    Ok(NoContent)
+}
+
+/// Checks the settings of an API key, whose scopes must be permissions its user holds.
+async fn validate_api_key(
+    db: &mut Connection<DbConn>,
+    user: &User,
+    settings: &ApiKeySettings,
+) -> HandlerResult<()> {
+    let mut errors = settings.validate().err().unwrap_or_default();
+
+    let held = UserRepository::permission_codes(db, user.id)
+        .await
+        .map_err(|e| server_error(e.into()))?;
+    if let Some(scope) = settings.scopes.iter().find(|scope| !held.contains(scope)) {
+        errors.add(
+            "scopes",
+            format!("Not a permission of this user: {}", scope),
+        );
+    }
+    errors.into_result().map_err(validation_error)
+}
+
+#[rocket::get("/api-keys")]
+pub async fn get_api_keys(
+    mut db: Connection<DbConn>,
+    user: InteractiveUser,
+) -> HandlerResult<Value> {
+    ApiKeyRepository::find_by_user(&mut db, user.0.id)
+        .await
+        .map(|keys| json!(keys))
+        .map_err(|e| {
+            handle_db_error(
+                e,
+                "Failed to fetch API keys".to_string(),
+                "fetching API keys".to_string(),
+            )
+        })
+}
+
+#[rocket::get("/api-keys/<id>")]
+pub async fn view_api_key(
+    mut db: Connection<DbConn>,
+    id: i32,
+    user: InteractiveUser,
+) -> HandlerResult<Value> {
+    ApiKeyRepository::find_for_user(&mut db, user.0.id, id)
+        .await
+        .map(|key| json!(key))
+        .map_err(|e| match e {
+            DieselError::NotFound => not_found(),
+            e => handle_db_error(
+                e,
+                format!("Failed to fetch API key with id {}", id),
+                "fetching API key".to_string(),
+            ),
+        })
+}
+
+/// Makes a new API key. The key itself is in the response and nowhere else, as
+/// only its hash is kept.
+#[rocket::post("/api-keys", format = "json", data = "<settings>")]
+pub async fn create_api_key(
+    mut db: Connection<DbConn>,
+    settings: Json<ApiKeySettings>,
+    user: InteractiveUser,
+) -> HandlerResult<Custom<Value>> {
+    let settings = settings.into_inner();
+    validate_api_key(&mut db, &user.0, &settings).await?;
+
+    let (prefix, key) = generate_api_key();
+    let new_key = NewApiKey {
+        user_id: user.0.id,
+        name: settings.name,
+        prefix,
+        key_hash: hash_password(key.clone()).map_err(|e| server_error(e.to_string().into()))?,
+        scopes: settings.scopes,
+        expires_at: settings.expires_at,
+    };
+    let api_key = ApiKeyRepository::create(&mut db, new_key)
+        .await
+        .map_err(|e| {
+            handle_db_error(
+                e,
+                "Failed to create API key".to_string(),
+                "creating API key".to_string(),
+            )
+        })?;
+
+    let mut response = json!(api_key);
+    response["key"] = json!(key);
+    Ok(Custom(Status::Created, response))
+}
+
+#[rocket::put("/api-keys/<id>", format = "json", data = "<settings>")]
+pub async fn update_api_key(
+    mut db: Connection<DbConn>,
+    id: i32,
+    settings: Json<ApiKeySettings>,
+    user: InteractiveUser,
+) -> HandlerResult<Value> {
+    let settings = settings.into_inner();
+    validate_api_key(&mut db, &user.0, &settings).await?;
+
+    ApiKeyRepository::update_for_user(&mut db, user.0.id, id, settings)
+        .await
+        .map(|key| json!(key))
+        .map_err(|e| match e {
+            DieselError::NotFound => not_found(),
+            e => handle_db_error(
+                e,
+                format!("Failed to update API key with id {}", id),
+                "updating API key".to_string(),
+            ),
+        })
+}
+
+#[rocket::delete("/api-keys/<id>")]
+pub async fn delete_api_key(
+    mut db: Connection<DbConn>,
+    id: i32,
+    user: InteractiveUser,
+) -> HandlerResult<NoContent> {
+    match ApiKeyRepository::delete_for_user(&mut db, user.0.id, id).await {
+        Ok(0) => Err(not_found()),
+        Ok(_) => Ok(NoContent),
+        Err(e) => Err(handle_db_error(
+            e,
+            format!("Failed to delete API key with id {}", id),
+            "deleting API key".to_string(),
+        )),
+    }
+}
+
+pub fn routes() -> Vec<rocket::Route> {
+    rocket::routes![
+        get_sessions,
+        delete_session,
+        change_password,
+        get_api_keys,
+        view_api_key,
+        create_api_key,
+        update_api_key,
+        delete_api_key
+    ]
+}
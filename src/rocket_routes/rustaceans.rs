@@ -1,6 +1,23 @@
+use crate::bulk::{self, BulkMode};
 use crate::crud_handlers;
-use crate::models::{NewRustacean, UpdateRustacean};
-use crate::repositories::RustaceanRepository;
+use crate::filtering::QueryError;
+use crate::models::{
+    AuditAction, NewAuditEntry, NewRustacean, Rustacean, RustaceanUser, UpdateRustacean, User,
+};
+use crate::permissions::{
+    CratesAny, Permission, RustaceansCascade, RustaceansDelete, RustaceansRestore, RustaceansWrite,
+    UsersAdmin,
+};
+use crate::repositories::{
+    AuditLogRepository, DeleteRustaceanError, OwnedCratesPolicy, RustaceanRepository,
+    UserRepository,
+};
+use crate::responses::{
+    etag, handle_db_error, invalid_query_error, not_found, precondition_failed,
+};
+use crate::rocket_routes::{IfMatch, Require};
+use diesel::OptionalExtension;
+use diesel_async::AsyncPgConnection;
 
 crud_handlers!(
     "rustacean",
@@ -8,19 +25,260 @@ crud_handlers!(
     RustaceanRepository,
     NewRustacean,
     UpdateRustacean,
+    permissions(
+        write: RustaceansWrite,
+        restore: RustaceansRestore,
+        replace: check_email
+    ),
     get_rustaceans,
     view_rustacean,
     create_rustacean,
     update_rustacean,
-    delete_rustacean
+    patch_rustacean,
+    restore_rustacean,
+    bulk_create_rustaceans,
+    bulk_patch_rustaceans
 );
 
+/// Takes the `users:admin` permission to change the email of a rustacean linked to a
+/// user, as password resets for that user are sent there.
+async fn check_email(
+    conn: &mut AsyncPgConnection,
+    user: &User,
+    before: &Rustacean,
+    data: &NewRustacean,
+) -> diesel::QueryResult<HandlerResult<()>> {
+    if before.user_id.is_none() || before.email.to_lowercase() == data.email.to_lowercase() {
+        return Ok(Ok(()));
+    }
+    if UserRepository::has_permission(conn, user.id, UsersAdmin::CODE).await? {
+        return Ok(Ok(()));
+    }
+    Ok(Err(Custom(
+        Status::Forbidden,
+        json!({ "error": "Not allowed to change the email of a rustacean linked to a user" }),
+    )))
+}
+
+/// Links a rustacean to a user account, letting that user change the rustacean's crates.
+#[rocket::put("/<id>/user", format = "json", data = "<link>")]
+pub async fn link_rustacean_user(
+    mut db: Db,
+    id: i32,
+    link: Json<RustaceanUser>,
+    user: Require<UsersAdmin>,
+) -> HandlerResult<Value> {
+    let user_id = link.into_inner().user_id;
+    let user = user.0;
+    db.transaction(|conn| {
+        async move {
+            let before = RustaceanRepository::find_for_update(conn, id).await?;
+            let item = RustaceanRepository::set_user(conn, id, user_id).await?;
+            record_audit(
+                conn,
+                &user,
+                AuditAction::Update,
+                id,
+                Some(json!(before)),
+                Some(json!(item)),
+            )
+            .await?;
+            Ok(item)
+        }
+        .scope_boxed()
+    })
+    .await
+    .map(|item| json!(item))
+    .map_err(|e| match e {
+        diesel::result::Error::NotFound => not_found(),
+        e => map_foreign_key_error(e, |e| {
+            handle_db_error(
+                e,
+                format!("Failed linking rustacean with id {}", id),
+                format!("linking rustacean with id {}", id),
+            )
+        }),
+    })
+}
+
+/// Soft-deletes a rustacean. One who still owns crates is only deleted with `?cascade=true`
+/// (given the `rustaceans:cascade` permission), which deletes the crates too, or `?reassign_to=<id>`,
+/// which hands them over to another rustacean first (given the `crates:any` permission).
+#[rocket::delete("/<id>?<cascade>&<reassign_to>")]
+#[allow(clippy::too_many_arguments)]
+pub async fn delete_rustacean(
+    mut db: Db,
+    id: i32,
+    cascade: Option<bool>,
+    reassign_to: Option<i32>,
+    if_match: IfMatch,
+    user: Require<RustaceansDelete>,
+    cascade_permission: Option<Require<RustaceansCascade>>,
+    reassign_permission: Option<Require<CratesAny>>,
+) -> HandlerResult<NoContent> {
+    let policy = match (cascade.unwrap_or(false), reassign_to) {
+        (true, Some(_)) => {
+            return Err(invalid_query_error(QueryError::new(
+                "cascade",
+                "Cannot be combined with reassign_to",
+            )));
+        }
+        (false, Some(assignee_id)) if assignee_id == id => {
+            return Err(invalid_query_error(QueryError::new(
+                "reassign_to",
+                "Cannot reassign crates to the rustacean being deleted",
+            )));
+        }
+        (false, Some(assignee_id)) => OwnedCratesPolicy::ReassignTo(assignee_id),
+        (true, None) => OwnedCratesPolicy::Cascade,
+        (false, None) => OwnedCratesPolicy::Restrict,
+    };
+
+    if matches!(policy, OwnedCratesPolicy::Cascade) && cascade_permission.is_none() {
+        return Err(Custom(
+            Status::Forbidden,
+            json!({ "error": "Not allowed to delete crates along with their owner" }),
+        ));
+    }
+    // Otherwise anyone deleting rustaceans could take over crates that are not theirs.
+    if matches!(policy, OwnedCratesPolicy::ReassignTo(_)) && reassign_permission.is_none() {
+        return Err(Custom(
+            Status::Forbidden,
+            json!({ "error": "Not allowed to hand crates over to another rustacean" }),
+        ));
+    }
+
+    let user = user.0;
+    let deleted = db
+        .transaction(|conn| delete_in(conn, id, policy, &if_match, &user).scope_boxed())
+        .await
+        .map_err(|e| delete_error(id, e))?;
+
+    if deleted {
+        Ok(NoContent)
+    } else {
+        Err(precondition_failed())
+    }
+}
+
+/// Soft-deletes the rustaceans with the given ids. Ones still owning crates are
+/// reported as conflicts rather than deleted.
+#[rocket::post("/bulk/delete?<mode>", format = "json", data = "<ids>")]
+pub async fn bulk_delete_rustaceans(
+    mut db: Db,
+    mode: Option<String>,
+    ids: Json<Vec<i32>>,
+    user: Require<RustaceansDelete>,
+) -> HandlerResult<Custom<Value>> {
+    let mode = BulkMode::from_query(mode.as_deref()).map_err(invalid_query_error)?;
+    let ids = bulk::check_size(ids.into_inner())?;
+
+    let user = user.0;
+    let outcome = db
+        .transaction(|conn| {
+            async move {
+                let no_precondition = IfMatch::default();
+                let mut outcomes = Vec::with_capacity(ids.len());
+                for id in ids {
+                    // Each item gets a savepoint, so a failed one leaves the rest intact.
+                    let outcome = conn
+                        .transaction(|c| {
+                            delete_in(c, id, OwnedCratesPolicy::Restrict, &no_precondition, &user)
+                                .scope_boxed()
+                        })
+                        .await
+                        .map(|_| None)
+                        .map_err(|e| delete_error(id, e));
+                    outcomes.push(outcome);
+                }
+                mode.finish(Status::NoContent, outcomes)
+            }
+            .scope_boxed()
+        })
+        .await;
+
+    bulk::respond(outcome, "deleting rustaceans")
+}
+
+/// Deletes a rustacean unless it fails the `If-Match` precondition, in which case
+/// this evaluates to `false`. A missing rustacean counts as deleted.
+async fn delete_in(
+    conn: &mut AsyncPgConnection,
+    id: i32,
+    policy: OwnedCratesPolicy,
+    if_match: &IfMatch,
+    user: &User,
+) -> Result<bool, DeleteRustaceanError> {
+    let before = RustaceanRepository::find_for_update(conn, id)
+        .await
+        .optional()?;
+    let etag = before.as_ref().map(|r| etag(r.id, r.updated_at));
+    if !if_match.matches(etag.as_deref()) {
+        return Ok(false);
+    }
+    if let Some(before) = before {
+        let affected = RustaceanRepository::delete(conn, id, policy).await?;
+        // The crates deleted or handed over along with the rustacean get entries of their own.
+        for (krate, after) in affected {
+            let entry = NewAuditEntry {
+                user_id: Some(user.id),
+                action: match after {
+                    Some(_) => AuditAction::Update,
+                    None => AuditAction::Delete,
+                },
+                resource: "crates".to_string(),
+                resource_id: krate.id,
+                before: Some(json!(krate)),
+                after: after.map(|after| json!(after)),
+            };
+            AuditLogRepository::create(conn, entry).await?;
+        }
+        record_audit(
+            conn,
+            user,
+            AuditAction::Delete,
+            id,
+            Some(json!(before)),
+            None,
+        )
+        .await?;
+    }
+    Ok(true)
+}
+
+fn delete_error(id: i32, e: DeleteRustaceanError) -> Custom<Value> {
+    match e {
+        DeleteRustaceanError::OwnsCrates(crates) => Custom(
+            Status::Conflict,
+            json!({
+                "error": "Rustacean still owns crates",
+                "crates": crates,
+            }),
+        ),
+        DeleteRustaceanError::UnknownAssignee(assignee_id) => invalid_query_error(QueryError::new(
+            "reassign_to",
+            format!("Rustacean {} does not exist", assignee_id),
+        )),
+        DeleteRustaceanError::Database(e) => handle_db_error(
+            e,
+            format!("Failed deleting rustacean with id {}", id),
+            format!("deleting rustacean with id {}", id),
+        ),
+    }
+}
+
 pub fn routes() -> Vec<rocket::Route> {
     rocket::routes![
         get_rustaceans,
         view_rustacean,
         create_rustacean,
         update_rustacean,
-        delete_rustacean
+        patch_rustacean,
+        restore_rustacean,
+        bulk_create_rustaceans,
+        bulk_patch_rustaceans,
+        link_rustacean_user,
+        delete_rustacean,
+        bulk_delete_rustaceans
     ]
 }
@@ -0,0 +1,276 @@
+use crate::auth::hash_password;
+use crate::jwt::{self, Authentication};
+use crate::models::{NewUser, NewUserWithRoles, RoleCode, User, UserRoles, UserWithRoles};
+use crate::password::PasswordConfig;
+use crate::permissions::UsersAdmin;
+use crate::repositories::{RoleRepository, UserRepository};
+use crate::responses::{handle_db_error, not_found, unique_violation_error, validation_error};
+use crate::rocket_routes::{server_error, CacheConn, DbConn, Require};
+use crate::sessions;
+use crate::validation::Validate;
+use diesel::result::{DatabaseErrorKind, Error as DieselError};
+use rocket::http::Status;
+use rocket::response::status::{Custom, NoContent};
+use rocket::serde::json::{serde_json::json, Json, Value};
+use rocket::State;
+use rocket_db_pools::Connection;
+use std::str::FromStr;
+
+type HandlerResult<T> = Result<T, Custom<Value>>;
+
+/// Refuses changes that would lock the acting admin out.
+fn forbid_self(admin: &Require<UsersAdmin>, id: i32, message: &str) -> HandlerResult<()> {
+    if admin.0.id == id {
+        return Err(Custom(Status::Conflict, json!({ "error": message })));
+    }
+    Ok(())
+}
+
+/// Ends every session of a user and, in JWT mode, denies their tokens, so that a deleted
+/// user, or one who lost roles, has to sign in again.
+async fn sign_out_everywhere(
+    cache: &mut Connection<CacheConn>,
+    authentication: &Authentication,
+    id: i32,
+) -> HandlerResult<()> {
+    sessions::revoke_all(&mut **cache, id)
+        .await
+        .map_err(|e| server_error(e.into()))?;
+    if let Some(keys) = authentication.jwt_keys() {
+        jwt::deny_all(&mut **cache, id, keys.ttl(), None)
+            .await
+            .map_err(|e| server_error(e.into()))?;
+    }
+    Ok(())
+}
+
+async fn find_user(db: &mut Connection<DbConn>, id: i32) -> HandlerResult<User> {
+    UserRepository::find(db, id).await.map_err(|e| match e {
+        DieselError::NotFound => not_found(),
+        e => handle_db_error(
+            e,
+            format!("Failed to fetch user with id {}", id),
+            "fetching user".to_string(),
+        ),
+    })
+}
+
+async fn with_roles(db: &mut Connection<DbConn>, user: User) -> HandlerResult<UserWithRoles> {
+    let roles = RoleRepository::find_by_user(db, &user).await.map_err(|e| {
+        handle_db_error(
+            e,
+            format!("Failed to fetch roles of user {}", user.id),
+            "fetching user".to_string(),
+        )
+    })?;
+    Ok(UserWithRoles {
+        user,
+        roles: roles.into_iter().map(|role| role.code).collect(),
+    })
+}
+
+/// Lists all users with their roles.
+#[rocket::get("/")]
+pub async fn get_users(
+    mut db: Connection<DbConn>,
+    _admin: Require<UsersAdmin>,
+) -> HandlerResult<Value> {
+    let users = UserRepository::find_with_roles(&mut db)
+        .await
+        .map_err(|e| {
+            handle_db_error(
+                e,
+                "Failed to fetch users".to_string(),
+                "fetching users".to_string(),
+            )
+        })?;
+
+    let users: Vec<_> = users
+        .into_iter()
+        .map(|(user, roles)| UserWithRoles {
+            user,
+            roles: roles.into_iter().map(|(_, role)| role.code).collect(),
+        })
+        .collect();
+    Ok(json!(users))
+}
+
+#[rocket::get("/<id>")]
+pub async fn view_user(
+    mut db: Connection<DbConn>,
+    id: i32,
+    _admin: Require<UsersAdmin>,
+) -> HandlerResult<Value> {
+    let user = find_user(&mut db, id).await?;
+    with_roles(&mut db, user).await.map(|user| json!(user))
+}
+
+#[rocket::post("/", format = "json", data = "<data>")]
+pub async fn create_user(
+    mut db: Connection<DbConn>,
+    data: Json<NewUserWithRoles>,
+    passwords: &State<PasswordConfig>,
+    _admin: Require<UsersAdmin>,
+) -> HandlerResult<Custom<Value>> {
+    let data = data.into_inner();
+    let mut errors = data.validate().err().unwrap_or_default();
+    if let Err(e) = passwords.check(&data.username, &data.password) {
+        errors.add("password", e);
+    }
+    errors.into_result().map_err(validation_error)?;
+
+    let new_user = NewUser {
+        username: data.username,
+        password: hash_password(data.password).map_err(|e| server_error(e.to_string().into()))?,
+    };
+    let user = UserRepository::create_with_roles(&mut db, new_user, data.roles)
+        .await
+        .map_err(|e| match e {
+            DieselError::DatabaseError(DatabaseErrorKind::UniqueViolation, info) => {
+                unique_violation_error(info.as_ref())
+            }
+            e => handle_db_error(
+                e,
+                "Failed to create user".to_string(),
+                "creating user".to_string(),
+            ),
+        })?;
+
+    let user = with_roles(&mut db, user).await?;
+    Ok(Custom(Status::Created, json!(user)))
+}
+
+/// Replaces the roles of a user, signing them out everywhere.
+#[rocket::put("/<id>/roles", format = "json", data = "<data>")]
+pub async fn update_user_roles(
+    mut db: Connection<DbConn>,
+    mut cache: Connection<CacheConn>,
+    authentication: &State<Authentication>,
+    id: i32,
+    data: Json<UserRoles>,
+    admin: Require<UsersAdmin>,
+) -> HandlerResult<Value> {
+    let data = data.into_inner();
+    data.validate().map_err(validation_error)?;
+    if !data.roles.contains(&RoleCode::Admin) {
+        forbid_self(&admin, id, "Cannot take away your own admin role")?;
+    }
+
+    let roles = UserRepository::set_roles(&mut db, id, data.roles)
+        .await
+        .map_err(|e| match e {
+            DieselError::NotFound => not_found(),
+            e => handle_db_error(
+                e,
+                format!("Failed to update roles of user {}", id),
+                "updating user roles".to_string(),
+            ),
+        })?;
+    sign_out_everywhere(&mut cache, authentication, id).await?;
+    Ok(json!({
+        "roles": roles.into_iter().map(|role| role.code).collect::<Vec<_>>(),
+    }))
+}
+
+fn parse_role(role: &str) -> HandlerResult<RoleCode> {
+    RoleCode::from_str(role)
+        .map_err(|_| Custom(Status::NotFound, json!({ "error": "Unknown role" })))
+}
+
+/// Responds with the roles a user holds after a change to them.
+async fn current_roles(db: &mut Connection<DbConn>, id: i32) -> HandlerResult<Value> {
+    let user = find_user(db, id).await?;
+    let user = with_roles(db, user).await?;
+    Ok(json!({ "roles": user.roles }))
+}
+
+/// Gives a user one more role, keeping the ones they hold.
+#[rocket::put("/<id>/roles/<role>")]
+pub async fn grant_user_role(
+    mut db: Connection<DbConn>,
+    id: i32,
+    role: &str,
+    _admin: Require<UsersAdmin>,
+) -> HandlerResult<Value> {
+    let role = parse_role(role)?;
+
+    UserRepository::grant_role(&mut db, id, role)
+        .await
+        .map_err(|e| match e {
+            DieselError::NotFound => not_found(),
+            e => handle_db_error(
+                e,
+                format!("Failed to grant role to user {}", id),
+                "granting role".to_string(),
+            ),
+        })?;
+    current_roles(&mut db, id).await
+}
+
+/// Takes a single role away from a user, signing them out everywhere if they held it.
+#[rocket::delete("/<id>/roles/<role>")]
+pub async fn revoke_user_role(
+    mut db: Connection<DbConn>,
+    mut cache: Connection<CacheConn>,
+    authentication: &State<Authentication>,
+    id: i32,
+    role: &str,
+    admin: Require<UsersAdmin>,
+) -> HandlerResult<Value> {
+    let role = parse_role(role)?;
+    if role == RoleCode::Admin {
+        forbid_self(&admin, id, "Cannot take away your own admin role")?;
+    }
+    find_user(&mut db, id).await?;
+
+    let revoked = UserRepository::revoke_role(&mut db, id, role)
+        .await
+        .map_err(|e| {
+            handle_db_error(
+                e,
+                format!("Failed to revoke role from user {}", id),
+                "revoking role".to_string(),
+            )
+        })?;
+    if revoked {
+        sign_out_everywhere(&mut cache, authentication, id).await?;
+    }
+    current_roles(&mut db, id).await
+}
+
+/// Deletes a user, signing them out everywhere.
+#[rocket::delete("/<id>")]
+pub async fn delete_user(
+    mut db: Connection<DbConn>,
+    mut cache: Connection<CacheConn>,
+    authentication: &State<Authentication>,
+    id: i32,
+    admin: Require<UsersAdmin>,
+) -> HandlerResult<NoContent> {
+    forbid_self(&admin, id, "Cannot delete your own account")?;
+
+    UserRepository::delete(&mut db, id)
+        .await
+        .map_err(|e| match e {
+            DieselError::NotFound => not_found(),
+            e => handle_db_error(
+                e,
+                format!("Failed to delete user with id {}", id),
+                "deleting user".to_string(),
+            ),
+        })?;
+    sign_out_everywhere(&mut cache, authentication, id).await?;
+    Ok(NoContent)
+}
+
+pub fn routes() -> Vec<rocket::Route> {
+    rocket::routes![
+        get_users,
+        view_user,
+        create_user,
+        update_user_roles,
+        grant_user_role,
+        revoke_user_role,
+        delete_user
+    ]
+}
@@ -1,6 +1,72 @@
 // @generated automatically by Diesel CLI.
 
+pub mod sql_types {
+    #[derive(diesel::query_builder::QueryId, Clone, diesel::sql_types::SqlType)]
+    #[diesel(postgres_type(name = "tsvector", schema = "pg_catalog"))]
+    pub struct Tsvector;
+}
+
+diesel::table! {
+    api_keys (id) {
+        id -> Int4,
+        user_id -> Int4,
+        #[max_length = 128]
+        name -> Varchar,
+        #[max_length = 16]
+        prefix -> Varchar,
+        #[max_length = 128]
+        key_hash -> Varchar,
+        scopes -> Array<Text>,
+        expires_at -> Nullable<Timestamp>,
+        last_used_at -> Nullable<Timestamp>,
+        created_at -> Timestamp,
+    }
+}
+
+diesel::table! {
+    audit_log (id) {
+        id -> Int4,
+        user_id -> Nullable<Int4>,
+        #[max_length = 16]
+        action -> Varchar,
+        #[max_length = 64]
+        resource -> Varchar,
+        resource_id -> Int4,
+        before -> Nullable<Jsonb>,
+        after -> Nullable<Jsonb>,
+        created_at -> Timestamp,
+    }
+}
+
+diesel::table! {
+    crate_dependencies (id) {
+        id -> Int4,
+        crate_id -> Int4,
+        dependency_id -> Int4,
+        #[max_length = 64]
+        requirement -> Varchar,
+        #[max_length = 16]
+        kind -> Varchar,
+        created_at -> Timestamp,
+    }
+}
+
 diesel::table! {
+    crate_versions (id) {
+        id -> Int4,
+        crate_id -> Int4,
+        #[max_length = 64]
+        version -> Varchar,
+        changelog -> Nullable<Text>,
+        yanked -> Bool,
+        published_at -> Timestamp,
+    }
+}
+
+diesel::table! {
+    use diesel::sql_types::*;
+    use super::sql_types::Tsvector;
+
     crates (id) {
         id -> Int4,
         rustacean_id -> Int4,
@@ -12,6 +78,28 @@ diesel::table! {
         version -> Varchar,
         description -> Nullable<Text>,
         created_at -> Timestamp,
+        search_vector -> Nullable<Tsvector>,
+        deleted_at -> Nullable<Timestamp>,
+        updated_at -> Timestamp,
+    }
+}
+
+diesel::table! {
+    permissions (id) {
+        id -> Int4,
+        #[max_length = 64]
+        code -> Varchar,
+        #[max_length = 128]
+        name -> Varchar,
+        created_at -> Timestamp,
+    }
+}
+
+diesel::table! {
+    role_permissions (id) {
+        id -> Int4,
+        role_id -> Int4,
+        permission_id -> Int4,
     }
 }
 
@@ -32,6 +120,9 @@ diesel::table! {
         name -> Varchar,
         email -> Varchar,
         created_at -> Timestamp,
+        deleted_at -> Nullable<Timestamp>,
+        updated_at -> Timestamp,
+        user_id -> Nullable<Int4>,
     }
 }
 
@@ -54,12 +145,24 @@ diesel::table! {
     }
 }
 
+diesel::joinable!(api_keys -> users (user_id));
+diesel::joinable!(audit_log -> users (user_id));
+diesel::joinable!(crate_versions -> crates (crate_id));
 diesel::joinable!(crates -> rustaceans (rustacean_id));
+diesel::joinable!(role_permissions -> permissions (permission_id));
+diesel::joinable!(role_permissions -> roles (role_id));
+diesel::joinable!(rustaceans -> users (user_id));
 diesel::joinable!(user_roles -> roles (role_id));
 diesel::joinable!(user_roles -> users (user_id));
 
 diesel::allow_tables_to_appear_in_same_query!(
+    api_keys,
+    audit_log,
+    crate_dependencies,
+    crate_versions,
     crates,
+    permissions,
+    role_permissions,
     roles,
     rustaceans,
     user_roles,
@@ -0,0 +1,326 @@
+use crate::auth::generate_token;
+use chrono::{Duration, NaiveDateTime, Utc};
+use rocket_db_pools::deadpool_redis::redis::aio::ConnectionLike;
+use rocket_db_pools::deadpool_redis::redis::{
+    AsyncCommands, ExistenceCheck, RedisResult, SetExpiry, SetOptions,
+};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+/// Session lifetimes in seconds, read from the Rocket configuration, e.g.
+/// `ROCKET_SESSION_IDLE_TIMEOUT=1800`.
+#[derive(Clone, Deserialize)]
+#[serde(default)]
+pub struct SessionConfig {
+    /// How long a session token lasts without being used.
+    pub session_idle_timeout: u64,
+    /// How long a session token lasts at most, however busy.
+    pub session_absolute_timeout: u64,
+    /// How long after logging in a session can still be refreshed.
+    pub refresh_token_timeout: u64,
+}
+
+impl Default for SessionConfig {
+    fn default() -> Self {
+        Self {
+            session_idle_timeout: 3 * 60 * 60,
+            session_absolute_timeout: 24 * 60 * 60,
+            refresh_token_timeout: 30 * 24 * 60 * 60,
+        }
+    }
+}
+
+/// A login session, as kept in the index of its user's sessions.
+///
+/// The session token lives under `sessions/{token}` and the refresh token under
+/// `refresh_tokens/{token}`, each expiring on its own, with a used refresh token marked
+/// under `used_refresh_tokens/{token}`; the index under
+/// `users/{user_id}/sessions` maps each session's public id to this record.
+#[derive(Serialize, Deserialize)]
+pub struct Session {
+    /// Identifies the session without giving away its tokens, which change on refresh.
+    pub id: String,
+    pub token: String,
+    pub refresh_token: String,
+    pub created_at: NaiveDateTime,
+    /// When the session token runs out, however busy.
+    pub expires_at: NaiveDateTime,
+    /// When the session can no longer be refreshed, from logging in.
+    pub refresh_expires_at: NaiveDateTime,
+    pub ip: Option<String>,
+    pub user_agent: Option<String>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct TokenRecord {
+    user_id: i32,
+    session_id: String,
+    expires_at: NaiveDateTime,
+}
+
+/// What came of presenting a refresh token.
+pub enum Refresh {
+    /// The session got a new pair of tokens.
+    Rotated(Session),
+    /// The token is unknown, expired or its session has ended.
+    Invalid,
+    /// The token was already used, so it leaked: the session is revoked.
+    Reused,
+}
+
+fn session_key(token: &str) -> String {
+    format!("sessions/{}", token)
+}
+
+fn refresh_key(token: &str) -> String {
+    format!("refresh_tokens/{}", token)
+}
+
+fn used_refresh_key(token: &str) -> String {
+    format!("used_refresh_tokens/{}", token)
+}
+
+fn index_key(user_id: i32) -> String {
+    format!("users/{}/sessions", user_id)
+}
+
+/// Seconds until a point in time, if it is still ahead.
+fn seconds_until(time: NaiveDateTime) -> Option<u64> {
+    u64::try_from((time - Utc::now().naive_utc()).num_seconds())
+        .ok()
+        .filter(|seconds| *seconds > 0)
+}
+
+/// Stores a session's tokens and its entry in the index.
+async fn save<C: ConnectionLike + Send>(
+    cache: &mut C,
+    config: &SessionConfig,
+    user_id: i32,
+    session: &Session,
+) -> RedisResult<()> {
+    let session_ttl = seconds_until(session.expires_at).unwrap_or(1);
+    let refresh_ttl = seconds_until(session.refresh_expires_at).unwrap_or(1);
+    let record = |expires_at| {
+        serde_json::to_string(&TokenRecord {
+            user_id,
+            session_id: session.id.clone(),
+            expires_at,
+        })
+        .expect("Token record serializes to JSON")
+    };
+
+    cache
+        .set_ex::<_, _, ()>(
+            session_key(&session.token),
+            record(session.expires_at),
+            session_ttl.min(config.session_idle_timeout),
+        )
+        .await?;
+    cache
+        .set_ex::<_, _, ()>(
+            refresh_key(&session.refresh_token),
+            record(session.refresh_expires_at),
+            refresh_ttl,
+        )
+        .await?;
+    cache
+        .hset::<_, _, _, ()>(
+            index_key(user_id),
+            &session.id,
+            serde_json::to_string(session).expect("Session serializes to JSON"),
+        )
+        .await?;
+    // No session outlives its refresh token, so neither does the index.
+    cache
+        .expire::<_, ()>(index_key(user_id), config.refresh_token_timeout as i64)
+        .await
+}
+
+/// Starts a session for a user under a token fresh from logging in.
+pub async fn start<C: ConnectionLike + Send>(
+    cache: &mut C,
+    config: &SessionConfig,
+    user_id: i32,
+    token: String,
+    ip: Option<String>,
+    user_agent: Option<String>,
+) -> RedisResult<Session> {
+    let now = Utc::now().naive_utc();
+    let refresh_expires_at = now + Duration::seconds(config.refresh_token_timeout as i64);
+    let session = Session {
+        id: generate_token(16),
+        token,
+        refresh_token: generate_token(128),
+        created_at: now,
+        expires_at: (now + Duration::seconds(config.session_absolute_timeout as i64))
+            .min(refresh_expires_at),
+        refresh_expires_at,
+        ip,
+        user_agent,
+    };
+    save(cache, config, user_id, &session).await?;
+    Ok(session)
+}
+
+/// Finds the user a session token belongs to, extending the session by the idle
+/// timeout as it is used, though never past its absolute expiry.
+pub async fn touch<C: ConnectionLike + Send>(
+    cache: &mut C,
+    config: &SessionConfig,
+    token: &str,
+) -> RedisResult<Option<i32>> {
+    let record: Option<String> = cache.get(session_key(token)).await?;
+    let Some(record) = record.and_then(|r| serde_json::from_str::<TokenRecord>(&r).ok()) else {
+        return Ok(None);
+    };
+    let Some(remaining) = seconds_until(record.expires_at) else {
+        return Ok(None);
+    };
+    cache
+        .expire::<_, ()>(
+            session_key(token),
+            remaining.min(config.session_idle_timeout) as i64,
+        )
+        .await?;
+    Ok(Some(record.user_id))
+}
+
+/// Swaps a refresh token for a new session token and refresh token. Each refresh
+/// token works once: presenting one again revokes the session, as someone else
+/// must have a copy.
+pub async fn refresh<C: ConnectionLike + Send>(
+    cache: &mut C,
+    config: &SessionConfig,
+    refresh_token: &str,
+) -> RedisResult<Refresh> {
+    let record: Option<String> = cache.get(refresh_key(refresh_token)).await?;
+    let Some(record) = record.and_then(|r| serde_json::from_str::<TokenRecord>(&r).ok()) else {
+        return Ok(Refresh::Invalid);
+    };
+    let Some(remaining) = seconds_until(record.expires_at) else {
+        return Ok(Refresh::Invalid);
+    };
+    // Marking the token used is what uses it up, in one step: of two requests racing
+    // with the same token, only one gets to rotate the session.
+    let claimed: bool = cache
+        .set_options(
+            used_refresh_key(refresh_token),
+            &record.session_id,
+            SetOptions::default()
+                .conditional_set(ExistenceCheck::NX)
+                .with_expiration(SetExpiry::EX(remaining as usize)),
+        )
+        .await?;
+    if !claimed {
+        revoke(cache, record.user_id, &record.session_id).await?;
+        return Ok(Refresh::Reused);
+    }
+    let entry: Option<String> = cache
+        .hget(index_key(record.user_id), &record.session_id)
+        .await?;
+    let Some(mut session) = entry.and_then(|e| serde_json::from_str::<Session>(&e).ok()) else {
+        return Ok(Refresh::Invalid);
+    };
+    if session.refresh_token != refresh_token {
+        revoke(cache, record.user_id, &session.id).await?;
+        return Ok(Refresh::Reused);
+    }
+
+    // The used refresh token is left to expire along with its mark, so that a replay
+    // is caught above.
+    cache.del::<_, ()>(session_key(&session.token)).await?;
+    session.token = generate_token(128);
+    session.refresh_token = generate_token(128);
+    session.expires_at = (Utc::now().naive_utc()
+        + Duration::seconds(config.session_absolute_timeout as i64))
+    .min(session.refresh_expires_at);
+    save(cache, config, record.user_id, &session).await?;
+    Ok(Refresh::Rotated(session))
+}
+
+/// Lists the live sessions of a user, oldest first, dropping expired ones from the index.
+pub async fn list<C: ConnectionLike + Send>(
+    cache: &mut C,
+    user_id: i32,
+) -> RedisResult<Vec<Session>> {
+    let records: HashMap<String, String> = cache.hgetall(index_key(user_id)).await?;
+
+    let mut sessions = Vec::with_capacity(records.len());
+    for (id, record) in records {
+        match serde_json::from_str::<Session>(&record) {
+            Ok(session) if seconds_until(session.refresh_expires_at).is_some() => {
+                sessions.push(session)
+            }
+            _ => cache.hdel::<_, _, ()>(index_key(user_id), id).await?,
+        }
+    }
+    sessions.sort_by_key(|session| session.created_at);
+    Ok(sessions)
+}
+
+/// Ends one session of a user, evaluating to `false` when there is no such session.
+pub async fn revoke<C: ConnectionLike + Send>(
+    cache: &mut C,
+    user_id: i32,
+    id: &str,
+) -> RedisResult<bool> {
+    let record: Option<String> = cache.hget(index_key(user_id), id).await?;
+    let Some(record) = record else {
+        return Ok(false);
+    };
+    if let Ok(session) = serde_json::from_str::<Session>(&record) {
+        cache
+            .del::<_, ()>(&[
+                session_key(&session.token),
+                refresh_key(&session.refresh_token),
+            ])
+            .await?;
+    }
+    cache.hdel::<_, _, ()>(index_key(user_id), id).await?;
+    Ok(true)
+}
+
+/// Ends the session a token belongs to, as when logging out.
+pub async fn end<C: ConnectionLike + Send>(cache: &mut C, token: &str) -> RedisResult<()> {
+    let record: Option<String> = cache.get(session_key(token)).await?;
+    match record.and_then(|r| serde_json::from_str::<TokenRecord>(&r).ok()) {
+        Some(record) => revoke(cache, record.user_id, &record.session_id)
+            .await
+            .map(|_| ()),
+        None => Ok(()),
+    }
+}
+
+/// Ends every session of a user but the one a token belongs to, evaluating to how many
+/// were ended.
+pub async fn revoke_others<C: ConnectionLike + Send>(
+    cache: &mut C,
+    user_id: i32,
+    token: &str,
+) -> RedisResult<usize> {
+    let sessions = list(cache, user_id).await?;
+    let mut revoked = 0;
+    for session in sessions.iter().filter(|session| session.token != token) {
+        revoke(cache, user_id, &session.id).await?;
+        revoked += 1;
+    }
+    Ok(revoked)
+}
+
+/// Ends every session of a user, evaluating to how many there were.
+pub async fn revoke_all<C: ConnectionLike + Send>(
+    cache: &mut C,
+    user_id: i32,
+) -> RedisResult<usize> {
+    let sessions = list(cache, user_id).await?;
+    for session in &sessions {
+        cache
+            .del::<_, ()>(&[
+                session_key(&session.token),
+                refresh_key(&session.refresh_token),
+            ])
+            .await?;
+    }
+    cache.del::<_, ()>(index_key(user_id)).await?;
+    Ok(sessions.len())
+}
@@ -0,0 +1,197 @@
+use crate::models::{
+    ApiKeySettings, NewCrate, NewCrateDependency, NewRustacean, NewUserWithRoles, RoleCode,
+    UserRoles,
+};
+use chrono::Utc;
+use semver::{Version, VersionReq};
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::fmt;
+
+/// Field-level validation failures, reported back to the client as a 422.
+#[derive(Debug, Default, Serialize)]
+pub struct ValidationErrors(BTreeMap<String, String>);
+
+impl ValidationErrors {
+    pub fn add(&mut self, field: &str, message: impl Into<String>) {
+        self.0.insert(field.to_string(), message.into());
+    }
+
+    pub fn single(field: &str, message: impl Into<String>) -> Self {
+        let mut errors = Self::default();
+        errors.add(field, message);
+        errors
+    }
+
+    pub fn into_result(self) -> Result<(), Self> {
+        if self.0.is_empty() {
+            Ok(())
+        } else {
+            Err(self)
+        }
+    }
+}
+
+impl fmt::Display for ValidationErrors {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let messages: Vec<_> = self
+            .0
+            .iter()
+            .map(|(field, message)| format!("{}: {}", field, message))
+            .collect();
+        write!(f, "{}", messages.join("; "))
+    }
+}
+
+/// Request payloads checked by the generated create and update handlers.
+pub trait Validate {
+    fn validate(&self) -> Result<(), ValidationErrors> {
+        Ok(())
+    }
+}
+
+impl Validate for NewRustacean {
+    fn validate(&self) -> Result<(), ValidationErrors> {
+        let mut errors = ValidationErrors::default();
+        check_email(&mut errors, "email", &self.email);
+        errors.into_result()
+    }
+}
+
+impl Validate for NewCrate {
+    fn validate(&self) -> Result<(), ValidationErrors> {
+        let mut errors = ValidationErrors::default();
+        check_semver(&mut errors, "version", &self.version);
+        errors.into_result()
+    }
+}
+
+impl Validate for NewCrateDependency {
+    fn validate(&self) -> Result<(), ValidationErrors> {
+        let mut errors = ValidationErrors::default();
+        if self.dependency_id == self.crate_id {
+            errors.add("dependency_id", "A crate cannot depend on itself");
+        }
+        if let Err(e) = VersionReq::parse(&self.requirement) {
+            errors.add(
+                "requirement",
+                format!(
+                    "'{}' is not a valid version requirement: {}",
+                    self.requirement, e
+                ),
+            );
+        }
+        errors.into_result()
+    }
+}
+
+impl Validate for NewUserWithRoles {
+    fn validate(&self) -> Result<(), ValidationErrors> {
+        let mut errors = ValidationErrors::default();
+        if self.username.trim().is_empty() || self.username.len() > 64 {
+            errors.add("username", "Must be between 1 and 64 characters long");
+        }
+        check_roles(&mut errors, "roles", &self.roles);
+        errors.into_result()
+    }
+}
+
+impl Validate for ApiKeySettings {
+    fn validate(&self) -> Result<(), ValidationErrors> {
+        let mut errors = ValidationErrors::default();
+        if self.name.trim().is_empty() || self.name.len() > 128 {
+            errors.add("name", "Must be between 1 and 128 characters long");
+        }
+        if self
+            .expires_at
+            .is_some_and(|expires_at| expires_at <= Utc::now().naive_utc())
+        {
+            errors.add("expires_at", "Must be in the future");
+        }
+        errors.into_result()
+    }
+}
+
+impl Validate for UserRoles {
+    fn validate(&self) -> Result<(), ValidationErrors> {
+        let mut errors = ValidationErrors::default();
+        check_roles(&mut errors, "roles", &self.roles);
+        errors.into_result()
+    }
+}
+
+fn check_roles(errors: &mut ValidationErrors, field: &str, roles: &[RoleCode]) {
+    if roles.is_empty() {
+        errors.add(field, "At least one role is required");
+    }
+}
+
+fn check_email(errors: &mut ValidationErrors, field: &str, email: &str) {
+    if !is_valid_email(email) {
+        errors.add(field, format!("'{}' is not a valid email address", email));
+    }
+}
+
+/// A deliberately simple syntactic check: a single `@` with a non-empty local part
+/// and a dotted domain, no whitespace, within the lengths allowed by RFC 5321.
+fn is_valid_email(email: &str) -> bool {
+    let Some((local, domain)) = email.split_once('@') else {
+        return false;
+    };
+    let labels: Vec<&str> = domain.split('.').collect();
+
+    email.len() <= 254
+        && !local.is_empty()
+        && local.len() <= 64
+        && !local.starts_with('.')
+        && !local.ends_with('.')
+        && !local.contains("..")
+        && !email.chars().any(|c| c.is_whitespace() || c.is_control())
+        && labels.len() >= 2
+        && labels.iter().all(|label| {
+            !label.is_empty()
+                && label.len() <= 63
+                && !label.starts_with('-')
+                && !label.ends_with('-')
+                && label.chars().all(|c| c.is_alphanumeric() || c == '-')
+        })
+}
+
+fn check_semver(errors: &mut ValidationErrors, field: &str, version: &str) {
+    if let Err(e) = parse_semver(version) {
+        errors.add(field, e);
+    }
+}
+
+pub fn parse_semver(version: &str) -> Result<Version, String> {
+    Version::parse(version).map_err(|e| match normalize_version(version) {
+        Some(normalized) => format!(
+            "'{}' is not a valid semantic version, did you mean '{}'?",
+            version, normalized
+        ),
+        None => format!("'{}' is not a valid semantic version: {}", version, e),
+    })
+}
+
+/// Turns common almost-semver spellings such as `v2`, `1.0` or `1.0-beta`
+/// into a valid semantic version, or returns `None` when there is no obvious one.
+pub fn normalize_version(version: &str) -> Option<String> {
+    let version = version.trim();
+    let version = version.strip_prefix(['v', 'V']).unwrap_or(version);
+    let (core, suffix) = match version.find(['-', '+']) {
+        Some(i) => version.split_at(i),
+        None => (version, ""),
+    };
+
+    let mut numbers = core
+        .split('.')
+        .map(|part| part.parse::<u64>().ok())
+        .collect::<Option<Vec<_>>>()?;
+    if numbers.len() > 3 {
+        return None;
+    }
+    numbers.resize(3, 0);
+
+    let normalized = format!("{}.{}.{}{}", numbers[0], numbers[1], numbers[2], suffix);
+    Version::parse(&normalized).ok().map(|v| v.to_string())
+}
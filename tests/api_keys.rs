@@ -0,0 +1,130 @@
+use reqwest::blocking::{Client, ClientBuilder};
+use reqwest::{header, StatusCode};
+use rocket::serde::json::{serde_json::json, Value};
+
+mod common;
+use common::{CRATES_URL, RUSTACEANS_URL, SERVER_URL};
+
+/// Creates a `reqwest::Client` authenticated with an API key.
+fn get_client_for_key(key: &str) -> Client {
+    let mut headers = header::HeaderMap::new();
+    headers.insert(
+        header::AUTHORIZATION,
+        header::HeaderValue::from_str(&format!("ApiKey {}", key)).unwrap(),
+    );
+    ClientBuilder::new()
+        .default_headers(headers)
+        .build()
+        .unwrap()
+}
+
+#[test]
+fn test_manage_api_keys() {
+    let username = common::unique_value("key_user");
+    let client = common::get_client_for_user(&username, common::TEST_EDITOR_ROLE);
+    let api_keys_url = format!("{}/me/api-keys", SERVER_URL);
+
+    let response = client
+        .post(&api_keys_url)
+        .json(&json!({ "name": "ci", "scopes": ["crates:write"] }))
+        .send()
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::CREATED);
+    let api_key: Value = response.json().unwrap();
+    assert_eq!(api_key["name"], json!("ci"));
+    assert_eq!(api_key["scopes"], json!(["crates:write"]));
+    assert!(api_key.get("key_hash").is_none());
+    let key = api_key["key"].as_str().unwrap().to_string();
+    assert!(key.starts_with(api_key["prefix"].as_str().unwrap()));
+    let api_key_url = format!("{}/{}", api_keys_url, api_key["id"]);
+
+    let response = client
+        .post(&api_keys_url)
+        .json(&json!({ "name": "", "scopes": ["users:admin"] }))
+        .send()
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::UNPROCESSABLE_ENTITY);
+    let json: Value = response.json().unwrap();
+    assert!(json["fields"]["name"].is_string());
+    assert!(json["fields"]["scopes"].is_string());
+
+    let api_keys: Value = client.get(&api_keys_url).send().unwrap().json().unwrap();
+    assert_eq!(api_keys.as_array().unwrap().len(), 1);
+    assert!(api_keys[0].get("key").is_none());
+    assert!(api_keys[0].get("key_hash").is_none());
+
+    // The key acts for its user, within its scopes, but cannot manage credentials.
+    let key_client = get_client_for_key(&key);
+    let response = key_client.get(CRATES_URL).send().unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    let response = common::create_test_rustacean_with_data(
+        &key_client,
+        "John Doe",
+        &format!("{}@doe.com", common::unique_value("john")),
+    );
+    assert_eq!(response.status(), StatusCode::FORBIDDEN);
+    let response = key_client.get(&api_keys_url).send().unwrap();
+    assert_eq!(response.status(), StatusCode::FORBIDDEN);
+    let sessions_url = format!("{}/me/sessions", SERVER_URL);
+    let response = key_client.get(&sessions_url).send().unwrap();
+    assert_eq!(response.status(), StatusCode::FORBIDDEN);
+    let response = key_client
+        .delete(format!("{}/some_session", sessions_url))
+        .send()
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::FORBIDDEN);
+
+    let response = client
+        .put(&api_key_url)
+        .json(&json!({ "name": "ci", "scopes": ["crates:write", "rustaceans:write"] }))
+        .send()
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+
+    let response = common::create_test_rustacean_with_data(
+        &key_client,
+        "John Doe",
+        &format!("{}@doe.com", common::unique_value("john")),
+    );
+    assert_eq!(response.status(), StatusCode::CREATED);
+    let rustacean: Value = response.json().unwrap();
+    let admin_client = common::get_client_with_logged_in_admin();
+    let response = admin_client
+        .delete(format!("{}/{}", RUSTACEANS_URL, rustacean["id"]))
+        .send()
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::NO_CONTENT);
+
+    let api_key: Value = client.get(&api_key_url).send().unwrap().json().unwrap();
+    assert!(api_key["last_used_at"].is_string());
+
+    let response = client.delete(&api_key_url).send().unwrap();
+    assert_eq!(response.status(), StatusCode::NO_CONTENT);
+    let response = client.delete(&api_key_url).send().unwrap();
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+
+    let response = key_client.get(CRATES_URL).send().unwrap();
+    assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+}
+
+#[test]
+fn test_expired_api_key() {
+    let username = common::unique_value("key_user");
+    let client = common::get_client_for_user(&username, common::TEST_VIEWER_ROLE);
+    let api_keys_url = format!("{}/me/api-keys", SERVER_URL);
+
+    let response = client
+        .post(&api_keys_url)
+        .json(&json!({ "name": "old", "expires_at": "2000-01-01T00:00:00" }))
+        .send()
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::UNPROCESSABLE_ENTITY);
+    let json: Value = response.json().unwrap();
+    assert!(json["fields"]["expires_at"].is_string());
+
+    let response = get_client_for_key("unknown.key")
+        .get(CRATES_URL)
+        .send()
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+}
@@ -0,0 +1,97 @@
+use reqwest::StatusCode;
+use rocket::serde::json::{serde_json::json, Value};
+
+mod common;
+use common::{create_test_crate, create_test_rustacean, CRATES_URL, SERVER_URL};
+
+#[test]
+fn test_audit_log() {
+    let client = common::get_client_with_logged_in_admin();
+    let rustacean = create_test_rustacean(&client);
+    let rustacean_id = rustacean["id"].as_i64().unwrap() as i32;
+    let a_crate = create_test_crate(&client, rustacean_id);
+    let crate_url = format!("{}/{}", CRATES_URL, a_crate["id"]);
+
+    let response = common::merge_patch(&client, &crate_url, &json!({ "description": "Audited" }))
+        .send()
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    let response = client.delete(&crate_url).send().unwrap();
+    assert_eq!(response.status(), StatusCode::NO_CONTENT);
+
+    let audit_url = format!("{}/audit?resource=crates&id={}", SERVER_URL, a_crate["id"]);
+    let response = client.get(&audit_url).send().unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+
+    // Newest entries come first
+    let json: Value = response.json().unwrap();
+    let entries = json["items"].as_array().unwrap();
+    let actions: Vec<&Value> = entries.iter().map(|e| &e["action"]).collect();
+    assert_eq!(
+        actions,
+        [&json!("delete"), &json!("update"), &json!("create")]
+    );
+    assert!(entries.iter().all(|e| e["user_id"].is_i64()));
+
+    assert_eq!(entries[2]["before"], Value::Null);
+    assert_eq!(entries[2]["after"], *a_crate);
+    assert_eq!(entries[1]["before"]["description"], Value::Null);
+    assert_eq!(entries[1]["after"]["description"], json!("Audited"));
+    assert_eq!(entries[0]["before"]["description"], json!("Audited"));
+    assert_eq!(entries[0]["after"], Value::Null);
+
+    let viewer_client = common::get_client_with_logged_in_viewer();
+    let response = viewer_client.get(&audit_url).send().unwrap();
+    assert_eq!(response.status(), StatusCode::FORBIDDEN);
+}
+
+#[test]
+fn test_audit_log_of_crates_deleted_with_their_owner() {
+    let client = common::get_client_with_logged_in_admin();
+    let rustacean = create_test_rustacean(&client);
+    let assignee = create_test_rustacean(&client);
+    let a_crate = create_test_crate(&client, rustacean["id"].as_i64().unwrap() as i32);
+    let crate_entries = || {
+        let json: Value = client
+            .get(format!(
+                "{}/audit?resource=crates&id={}",
+                SERVER_URL, a_crate["id"]
+            ))
+            .send()
+            .unwrap()
+            .json()
+            .unwrap();
+        json["items"].as_array().unwrap().clone()
+    };
+
+    // Handing the crate over to another rustacean is an update of the crate...
+    let response = client
+        .delete(format!(
+            "{}/{}?reassign_to={}",
+            common::RUSTACEANS_URL,
+            rustacean["id"],
+            assignee["id"]
+        ))
+        .send()
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::NO_CONTENT);
+    let entries = crate_entries();
+    assert_eq!(entries[0]["action"], json!("update"));
+    assert_eq!(entries[0]["before"]["rustacean_id"], rustacean["id"]);
+    assert_eq!(entries[0]["after"]["rustacean_id"], assignee["id"]);
+
+    // ...and deleting it along with its owner a delete
+    let response = client
+        .delete(format!(
+            "{}/{}?cascade=true",
+            common::RUSTACEANS_URL,
+            assignee["id"]
+        ))
+        .send()
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::NO_CONTENT);
+    let entries = crate_entries();
+    assert_eq!(entries[0]["action"], json!("delete"));
+    assert_eq!(entries[0]["before"]["rustacean_id"], assignee["id"]);
+    assert_eq!(entries[0]["after"], Value::Null);
+}
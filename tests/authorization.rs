@@ -1,5 +1,6 @@
 use reqwest::{blocking::Client, StatusCode};
-use rocket::serde::json::serde_json::json;
+use rocket::serde::json::{serde_json::json, Value};
+use std::process::Command;
 
 pub mod common;
 
@@ -25,22 +26,249 @@ fn test_login() {
     assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
 }
 
+#[test]
+fn test_sessions() {
+    let username = common::unique_value("session_user");
+    let client = common::get_client_for_user(&username, common::TEST_VIEWER_ROLE);
+    let other_client = common::get_client_for_user(&username, common::TEST_VIEWER_ROLE);
+    let sessions_url = format!("{}/me/sessions", common::SERVER_URL);
+
+    let response = client.get(&sessions_url).send().unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    let sessions: Value = response.json().unwrap();
+    let sessions = sessions.as_array().unwrap();
+    assert_eq!(sessions.len(), 2);
+    assert_eq!(sessions[0]["current"], json!(true));
+    assert_eq!(sessions[0]["ip"], json!("127.0.0.1"));
+    assert!(sessions[0].get("token").is_none());
+    assert_eq!(sessions[1]["current"], json!(false));
+    let other_session_url = format!("{}/{}", sessions_url, sessions[1]["id"].as_str().unwrap());
+
+    // Revoking the other session logs that client out
+    let response = client.delete(&other_session_url).send().unwrap();
+    assert_eq!(response.status(), StatusCode::NO_CONTENT);
+    let response = other_client.get(&sessions_url).send().unwrap();
+    assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+    let response = client.delete(&other_session_url).send().unwrap();
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+
+    let response = client
+        .post(format!("{}/logout", common::SERVER_URL))
+        .send()
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::NO_CONTENT);
+    let response = client.get(&sessions_url).send().unwrap();
+    assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+}
+
+#[test]
+fn test_refresh_token() {
+    let username = common::unique_value("refresh_user");
+    common::create_test_user(&username, common::TEST_VIEWER_ROLE);
+    let client = Client::new();
+    let sessions_url = format!("{}/me/sessions", common::SERVER_URL);
+    let refresh_url = format!("{}/token/refresh", common::SERVER_URL);
+
+    let response = client
+        .post(format!("{}/login", common::SERVER_URL))
+        .json(&json!({ "username": username, "password": common::TEST_PASSWORD }))
+        .send()
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    let login: Value = response.json().unwrap();
+    assert_eq!(login["refresh_token"].as_str().unwrap().len(), 128);
+
+    // Refreshing swaps both tokens
+    let response = client
+        .post(&refresh_url)
+        .json(&json!({ "refresh_token": login["refresh_token"] }))
+        .send()
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    let refreshed: Value = response.json().unwrap();
+    assert_ne!(refreshed["token"], login["token"]);
+    assert_ne!(refreshed["refresh_token"], login["refresh_token"]);
+
+    let sessions_with = |token: &Value| {
+        client
+            .get(&sessions_url)
+            .bearer_auth(token.as_str().unwrap())
+            .send()
+            .unwrap()
+            .status()
+    };
+    assert_eq!(sessions_with(&login["token"]), StatusCode::UNAUTHORIZED);
+    assert_eq!(sessions_with(&refreshed["token"]), StatusCode::OK);
+
+    // Using a refresh token twice revokes the session
+    let response = client
+        .post(&refresh_url)
+        .json(&json!({ "refresh_token": login["refresh_token"] }))
+        .send()
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+    assert_eq!(sessions_with(&refreshed["token"]), StatusCode::UNAUTHORIZED);
+    let response = client
+        .post(&refresh_url)
+        .json(&json!({ "refresh_token": refreshed["refresh_token"] }))
+        .send()
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+}
+
+#[test]
+fn test_refresh_token_race() {
+    let username = common::unique_value("refresh_race_user");
+    common::create_test_user(&username, common::TEST_VIEWER_ROLE);
+    let client = Client::new();
+    let refresh_url = format!("{}/token/refresh", common::SERVER_URL);
+
+    let login: Value = client
+        .post(format!("{}/login", common::SERVER_URL))
+        .json(&json!({ "username": username, "password": common::TEST_PASSWORD }))
+        .send()
+        .unwrap()
+        .json()
+        .unwrap();
+
+    // Of several requests racing with one refresh token, at most one gets new tokens
+    let statuses: Vec<StatusCode> = std::thread::scope(|scope| {
+        let requests: Vec<_> = (0..4)
+            .map(|_| {
+                scope.spawn(|| {
+                    client
+                        .post(&refresh_url)
+                        .json(&json!({ "refresh_token": login["refresh_token"] }))
+                        .send()
+                        .unwrap()
+                        .status()
+                })
+            })
+            .collect();
+        requests.into_iter().map(|r| r.join().unwrap()).collect()
+    });
+    assert!(
+        statuses.iter().filter(|s| **s == StatusCode::OK).count() <= 1,
+        "{:?}",
+        statuses
+    );
+    assert!(
+        statuses
+            .iter()
+            .filter(|s| **s == StatusCode::UNAUTHORIZED)
+            .count()
+            >= 3,
+        "{:?}",
+        statuses
+    );
+
+    let response = client
+        .post(&refresh_url)
+        .json(&json!({ "refresh_token": login["refresh_token"] }))
+        .send()
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+}
+
+#[test]
+fn test_login_backoff() {
+    let username = common::unique_value("backoff_user");
+    common::create_test_user(&username, common::TEST_VIEWER_ROLE);
+    let client = Client::new();
+    let login = |password: &str| {
+        client
+            .post(format!("{}/login", common::SERVER_URL))
+            .json(&json!({ "username": username, "password": password }))
+            .send()
+            .unwrap()
+    };
+
+    // The failure reaching the backoff threshold of three makes further attempts wait
+    for _ in 0..3 {
+        assert_eq!(login("wrong").status(), StatusCode::UNAUTHORIZED);
+    }
+    let response = login(common::TEST_PASSWORD);
+    assert_eq!(response.status(), StatusCode::TOO_MANY_REQUESTS);
+    let retry_after: u64 = response.headers()["Retry-After"]
+        .to_str()
+        .unwrap()
+        .parse()
+        .unwrap();
+    assert!(retry_after >= 1);
+
+    // Admins can unlock the user from the CLI
+    let users: Value = common::get_client_with_logged_in_admin()
+        .get(common::USERS_URL)
+        .send()
+        .unwrap()
+        .json()
+        .unwrap();
+    let user = users
+        .as_array()
+        .unwrap()
+        .iter()
+        .find(|user| user["username"] == json!(username))
+        .unwrap();
+    let output = Command::new("cargo")
+        .args(["run", "--bin", "cli", "users", "unlock"])
+        .arg(user["id"].to_string())
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+    assert_eq!(login(common::TEST_PASSWORD).status(), StatusCode::OK);
+
+    // Unlocking a user that does not exist fails
+    let output = Command::new("cargo")
+        .args(["run", "--bin", "cli", "users", "unlock", "999999"])
+        .output()
+        .unwrap();
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("User 999999 not found"));
+}
+
 #[test]
 fn test_unauthorized_access_to_private_routes() {
     let client = Client::new();
 
-    // A list of private routes to test. We check different methods (GET, POST, PUT, DELETE).
+    // A list of private routes to test. We check different methods (GET, POST, PUT, PATCH, DELETE).
     let private_routes = vec![
         ("GET", common::RUSTACEANS_URL.to_string()),
         ("POST", common::RUSTACEANS_URL.to_string()),
         ("GET", format!("{}/1", common::RUSTACEANS_URL)),
         ("PUT", format!("{}/1", common::RUSTACEANS_URL)),
+        ("PATCH", format!("{}/1", common::RUSTACEANS_URL)),
         ("DELETE", format!("{}/1", common::RUSTACEANS_URL)),
+        ("POST", format!("{}/1/restore", common::RUSTACEANS_URL)),
+        ("PUT", format!("{}/1/user", common::RUSTACEANS_URL)),
+        ("POST", format!("{}/bulk", common::RUSTACEANS_URL)),
+        ("POST", format!("{}/bulk/delete", common::RUSTACEANS_URL)),
         ("GET", common::CRATES_URL.to_string()),
         ("POST", common::CRATES_URL.to_string()),
         ("GET", format!("{}/1", common::CRATES_URL)),
+        ("GET", format!("{}/search?q=serde", common::CRATES_URL)),
         ("PUT", format!("{}/1", common::CRATES_URL)),
+        ("PATCH", format!("{}/1", common::CRATES_URL)),
         ("DELETE", format!("{}/1", common::CRATES_URL)),
+        ("POST", format!("{}/1/restore", common::CRATES_URL)),
+        ("POST", format!("{}/bulk", common::CRATES_URL)),
+        ("POST", format!("{}/bulk/delete", common::CRATES_URL)),
+        (
+            "GET",
+            format!("{}/audit?resource=crates", common::SERVER_URL),
+        ),
+        ("GET", common::USERS_URL.to_string()),
+        ("POST", common::USERS_URL.to_string()),
+        ("PUT", format!("{}/1/roles", common::USERS_URL)),
+        ("DELETE", format!("{}/1", common::USERS_URL)),
+        ("POST", format!("{}/logout", common::SERVER_URL)),
+        ("GET", format!("{}/me/sessions", common::SERVER_URL)),
+        ("DELETE", format!("{}/me/sessions/abc", common::SERVER_URL)),
+        ("POST", format!("{}/me/password", common::SERVER_URL)),
+        ("GET", format!("{}/me/api-keys", common::SERVER_URL)),
+        ("POST", format!("{}/me/api-keys", common::SERVER_URL)),
+        ("GET", format!("{}/me/api-keys/1", common::SERVER_URL)),
+        ("PUT", format!("{}/me/api-keys/1", common::SERVER_URL)),
+        ("DELETE", format!("{}/me/api-keys/1", common::SERVER_URL)),
     ];
 
     for (method, url) in private_routes {
@@ -48,6 +276,7 @@ fn test_unauthorized_access_to_private_routes() {
             "GET" => client.get(&url),
             "POST" => client.post(&url),
             "PUT" => client.put(&url),
+            "PATCH" => common::merge_patch(&client, &url, &json!({})),
             "DELETE" => client.delete(&url),
             _ => panic!("Unsupported HTTP method in test"),
         };
@@ -67,3 +296,218 @@ fn test_unauthorized_access_to_private_routes() {
         );
     }
 }
+
+#[test]
+fn test_editor_permissions() {
+    let client = common::get_client_with_logged_in_editor();
+    let admin_client = common::get_client_with_logged_in_admin();
+
+    // Editors change the catalogue, though only crates of the rustacean linked to them...
+    let rustacean = common::create_test_rustacean(&client);
+    let rustacean_id = rustacean["id"].as_i64().unwrap() as i32;
+    let other_rustacean = common::create_test_rustacean(&admin_client);
+    let new_crate = |rustacean_id: i32| {
+        json!({
+            "rustacean_id": rustacean_id,
+            "name": "serde",
+            "code": common::unique_value("SERDE"),
+            "version": "1.0.0"
+        })
+    };
+    let response = client
+        .post(common::CRATES_URL)
+        .json(&new_crate(rustacean_id))
+        .send()
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::FORBIDDEN);
+
+    let a_crate = common::create_test_crate(&admin_client, rustacean_id);
+    let dependency = common::create_test_crate(
+        &admin_client,
+        other_rustacean["id"].as_i64().unwrap() as i32,
+    );
+    let crate_url = format!("{}/{}", common::CRATES_URL, a_crate["id"]);
+    let versions_url = format!("{}/versions", crate_url);
+    let dependencies_url = format!("{}/dependencies", crate_url);
+    let response = admin_client
+        .post(&versions_url)
+        .json(&json!({ "version": "1.1.0" }))
+        .send()
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::CREATED);
+    let response = admin_client
+        .post(&dependencies_url)
+        .json(&json!({ "dependency_id": dependency["id"], "requirement": "^1.0" }))
+        .send()
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::CREATED);
+
+    let response = common::merge_patch(&client, &crate_url, &json!({ "version": "1.0.0" }))
+        .send()
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::FORBIDDEN);
+    let response = client.delete(&crate_url).send().unwrap();
+    assert_eq!(response.status(), StatusCode::FORBIDDEN);
+    let requests = [
+        client
+            .post(&versions_url)
+            .json(&json!({ "version": "1.2.0" })),
+        client
+            .put(format!("{}/1.1.0", versions_url))
+            .json(&json!({ "yanked": true })),
+        client.delete(format!("{}/1.1.0", versions_url)),
+        client.post(&dependencies_url).json(
+            &json!({ "dependency_id": dependency["id"], "requirement": "^1.0", "kind": "dev" }),
+        ),
+        client.delete(format!("{}/{}", dependencies_url, dependency["id"])),
+    ];
+    for request in requests {
+        let response = request.send().unwrap();
+        assert_eq!(
+            response.status(),
+            StatusCode::FORBIDDEN,
+            "{}",
+            response.url()
+        );
+    }
+
+    let users: Value = admin_client
+        .get(common::USERS_URL)
+        .send()
+        .unwrap()
+        .json()
+        .unwrap();
+    let editor = users
+        .as_array()
+        .unwrap()
+        .iter()
+        .find(|user| user["username"] == common::TEST_EDITOR_USERNAME)
+        .unwrap();
+    let response = admin_client
+        .put(format!("{}/{}/user", common::RUSTACEANS_URL, rustacean_id))
+        .json(&json!({ "user_id": editor["id"] }))
+        .send()
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    let linked: Value = response.json().unwrap();
+    assert_eq!(linked["user_id"], editor["id"]);
+
+    let response = client
+        .post(&versions_url)
+        .json(&json!({ "version": "1.2.0" }))
+        .send()
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::CREATED);
+    let response = client
+        .put(format!("{}/1.1.0", versions_url))
+        .json(&json!({ "yanked": true }))
+        .send()
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    let response = client
+        .delete(format!("{}/1.1.0", versions_url))
+        .send()
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::NO_CONTENT);
+    let response = client
+        .delete(format!("{}/{}", dependencies_url, dependency["id"]))
+        .send()
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::NO_CONTENT);
+
+    // Their crates stay theirs: they cannot hand one over to another rustacean
+    let response = common::merge_patch(
+        &client,
+        &crate_url,
+        &json!({ "rustacean_id": other_rustacean["id"] }),
+    )
+    .send()
+    .unwrap();
+    assert_eq!(response.status(), StatusCode::FORBIDDEN);
+    let response = common::merge_patch(&client, &crate_url, &json!({ "version": "1.2.0" }))
+        .send()
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    let response = client.delete(&crate_url).send().unwrap();
+    assert_eq!(response.status(), StatusCode::NO_CONTENT);
+
+    let response = client
+        .post(common::CRATES_URL)
+        .json(&new_crate(rustacean_id))
+        .send()
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::CREATED);
+    let own_crate: Value = response.json().unwrap();
+    let response = client
+        .delete(format!("{}/{}", common::CRATES_URL, own_crate["id"]))
+        .send()
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::NO_CONTENT);
+
+    // ...but restoring, cascading deletes, taking over crates, changing where password resets
+    // of a user are sent, linking users, the audit log and users are for admins
+    let response = client
+        .post(format!("{}/{}/restore", common::CRATES_URL, a_crate["id"]))
+        .send()
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::FORBIDDEN);
+    let response = client
+        .delete(format!(
+            "{}/{}?cascade=true",
+            common::RUSTACEANS_URL,
+            rustacean_id
+        ))
+        .send()
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::FORBIDDEN);
+    let response = client
+        .delete(format!(
+            "{}/{}?reassign_to={}",
+            common::RUSTACEANS_URL,
+            other_rustacean["id"],
+            rustacean_id
+        ))
+        .send()
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::FORBIDDEN);
+    let rustacean_url = format!("{}/{}", common::RUSTACEANS_URL, rustacean_id);
+    let response = common::merge_patch(
+        &client,
+        &rustacean_url,
+        &json!({ "email": format!("{}@doe.com", common::unique_value("taken_over")) }),
+    )
+    .send()
+    .unwrap();
+    assert_eq!(response.status(), StatusCode::FORBIDDEN);
+    let response = common::merge_patch(&client, &rustacean_url, &json!({ "name": "Jane Doe" }))
+        .send()
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    let response = common::merge_patch(
+        &admin_client,
+        &rustacean_url,
+        &json!({ "email": format!("{}@doe.com", common::unique_value("moved")) }),
+    )
+    .send()
+    .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    let response = client
+        .put(format!("{}/{}/user", common::RUSTACEANS_URL, rustacean_id))
+        .json(&json!({ "user_id": null }))
+        .send()
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::FORBIDDEN);
+    for url in [
+        format!("{}/audit", common::SERVER_URL),
+        common::USERS_URL.to_string(),
+    ] {
+        let response = client.get(&url).send().unwrap();
+        assert_eq!(response.status(), StatusCode::FORBIDDEN, "GET {}", url);
+    }
+
+    let response = client
+        .delete(format!("{}/{}", common::RUSTACEANS_URL, rustacean_id))
+        .send()
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::NO_CONTENT);
+}
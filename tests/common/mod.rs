@@ -1,19 +1,25 @@
 #![allow(dead_code)]
 
-use reqwest::{blocking::Client, blocking::ClientBuilder, header, StatusCode};
+use reqwest::{
+    blocking::Client, blocking::ClientBuilder, blocking::RequestBuilder, header, StatusCode,
+};
 use rocket::serde::json::{serde_json::json, Value};
 use std::ops::Deref;
 use std::process::Command;
+use std::sync::atomic::{AtomicUsize, Ordering};
 
 // --- Constants ---
 pub const SERVER_URL: &str = "http://127.0.0.1:8000";
 pub const RUSTACEANS_URL: &str = "http://127.0.0.1:8000/rustaceans";
 pub const CRATES_URL: &str = "http://127.0.0.1:8000/crates";
+pub const USERS_URL: &str = "http://127.0.0.1:8000/users";
 
 // --- Test User Constants ---
-pub const TEST_PASSWORD: &str = "1234";
+pub const TEST_PASSWORD: &str = "Test-password-1";
 pub const TEST_ADMIN_USERNAME: &str = "test_admin";
 pub const TEST_ADMIN_ROLE: &str = "admin";
+pub const TEST_EDITOR_USERNAME: &str = "test_editor";
+pub const TEST_EDITOR_ROLE: &str = "editor";
 pub const TEST_VIEWER_USERNAME: &str = "test_viewer";
 pub const TEST_VIEWER_ROLE: &str = "viewer";
 
@@ -36,7 +42,10 @@ impl<'a> Drop for RustaceanGuard<'a> {
     fn drop(&mut self) {
         let _ = self
             .client
-            .delete(format!("{}/{}", RUSTACEANS_URL, self.value["id"]))
+            .delete(format!(
+                "{}/{}?cascade=true",
+                RUSTACEANS_URL, self.value["id"]
+            ))
             .send();
     }
 }
@@ -65,6 +74,25 @@ impl<'a> Drop for CrateGuard<'a> {
 
 // --- Helper Functions ---
 
+/// Builds a `PATCH` request sending `patch` as a JSON Merge Patch document.
+pub fn merge_patch(client: &Client, url: &str, patch: &Value) -> RequestBuilder {
+    client
+        .patch(url)
+        .header(header::CONTENT_TYPE, "application/merge-patch+json")
+        .json(patch)
+}
+
+/// Returns a value that no other test is using, for columns that must be unique.
+pub fn unique_value(prefix: &str) -> String {
+    static COUNTER: AtomicUsize = AtomicUsize::new(0);
+    format!(
+        "{}_{}_{}",
+        prefix,
+        std::process::id(),
+        COUNTER.fetch_add(1, Ordering::Relaxed)
+    )
+}
+
 /// Creates a rustacean with specific data.
 pub fn create_test_rustacean_with_data(
     client: &Client,
@@ -80,7 +108,11 @@ pub fn create_test_rustacean_with_data(
 
 /// Creates a rustacean with default data.
 pub fn create_test_rustacean(client: &Client) -> RustaceanGuard<'_> {
-    let response = create_test_rustacean_with_data(client, "John Doe", "john@doe.com");
+    let response = create_test_rustacean_with_data(
+        client,
+        "John Doe",
+        &format!("{}@doe.com", unique_value("john")),
+    );
     assert_eq!(response.status(), StatusCode::CREATED);
     let value = response.json().unwrap();
     RustaceanGuard { client, value }
@@ -111,7 +143,13 @@ pub fn create_test_crate_with_data<'a>(
 
 /// Creates a crate with default data.
 pub fn create_test_crate(client: &Client, rustacean_id: i32) -> CrateGuard<'_> {
-    create_test_crate_with_data(client, rustacean_id, "serde", "SERDE", "1.0")
+    create_test_crate_with_data(
+        client,
+        rustacean_id,
+        "serde",
+        &unique_value("SERDE"),
+        "1.0.0",
+    )
 }
 
 /// Creates a test user.
@@ -162,7 +200,7 @@ pub fn get_user_token(client: &Client, username: &str) -> String {
 }
 
 /// Creates a `reqwest::Client` authenticated for the given user.
-fn get_client_for_user(username: &str, role: &str) -> Client {
+pub fn get_client_for_user(username: &str, role: &str) -> Client {
     create_test_user(username, role);
     let client = Client::new();
     let token = get_user_token(&client, username);
@@ -184,6 +222,12 @@ pub fn get_client_with_logged_in_admin() -> Client {
     get_client_for_user(TEST_ADMIN_USERNAME, TEST_ADMIN_ROLE)
 }
 
+/// Creates and returns a new `reqwest::Client` instance with the default headers
+/// configured for an authenticated editor user.
+pub fn get_client_with_logged_in_editor() -> Client {
+    get_client_for_user(TEST_EDITOR_USERNAME, TEST_EDITOR_ROLE)
+}
+
 /// Creates and returns a new `reqwest::Client` instance with the default headers
 /// configured for an authenticated viewer user.
 pub fn get_client_with_logged_in_viewer() -> Client {
@@ -0,0 +1,120 @@
+use reqwest::StatusCode;
+use rocket::serde::json::{serde_json::json, Value};
+
+mod common;
+use common::{create_test_crate_with_data, create_test_rustacean, CRATES_URL};
+
+#[test]
+fn test_crate_dependencies() {
+    let client = common::get_client_with_logged_in_admin();
+    let rustacean = create_test_rustacean(&client);
+    let rustacean_id = rustacean["id"].as_i64().unwrap() as i32;
+    let app = create_test_crate_with_data(&client, rustacean_id, "app", "DEP_APP", "1.0.0");
+    let web = create_test_crate_with_data(&client, rustacean_id, "web", "DEP_WEB", "1.0.0");
+    let core = create_test_crate_with_data(&client, rustacean_id, "core", "DEP_CORE", "1.0.0");
+    let dependencies_url = |krate: &Value| format!("{}/{}/dependencies", CRATES_URL, krate["id"]);
+
+    // app -> web -> core
+    for (from, to) in [(&app, &web), (&web, &core)] {
+        let response = client
+            .post(dependencies_url(from))
+            .json(&json!({ "dependency_id": to["id"], "requirement": "^1.0" }))
+            .send()
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::CREATED);
+    }
+
+    let response = client
+        .post(dependencies_url(&app))
+        .json(&json!({ "dependency_id": web["id"], "requirement": "^1.0" }))
+        .send()
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::CONFLICT);
+
+    // core -> app would close the loop
+    let response = client
+        .post(dependencies_url(&core))
+        .json(&json!({ "dependency_id": app["id"], "requirement": "^1.0" }))
+        .send()
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::CONFLICT);
+
+    let response = client
+        .post(dependencies_url(&core))
+        .json(&json!({ "dependency_id": core["id"], "requirement": "not a requirement" }))
+        .send()
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::UNPROCESSABLE_ENTITY);
+    let json: Value = response.json().unwrap();
+    assert!(json["fields"]["dependency_id"].is_string());
+    assert!(json["fields"]["requirement"].is_string());
+
+    let response = client
+        .post(dependencies_url(&core))
+        .json(&json!({ "dependency_id": 999999, "requirement": "^1.0" }))
+        .send()
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+
+    let json: Value = client
+        .get(format!("{}/{}/dependents", CRATES_URL, core["id"]))
+        .send()
+        .unwrap()
+        .json()
+        .unwrap();
+    assert_eq!(json["items"].as_array().unwrap().len(), 1);
+    assert_eq!(json["items"][0]["crate"]["id"], web["id"]);
+
+    let tree_url = format!("{}/{}/dependency-tree", CRATES_URL, app["id"]);
+    let json: Value = client.get(&tree_url).send().unwrap().json().unwrap();
+    let items = json["items"].as_array().unwrap();
+    assert_eq!(items.len(), 2);
+    assert!(items
+        .iter()
+        .any(|edge| edge["dependency_id"] == core["id"] && edge["depth"] == json!(2)));
+
+    let json: Value = client
+        .get(format!("{}?depth=1", tree_url))
+        .send()
+        .unwrap()
+        .json()
+        .unwrap();
+    assert_eq!(json["items"].as_array().unwrap().len(), 1);
+
+    let response = client.get(format!("{}?depth=0", tree_url)).send().unwrap();
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+
+    let response = client
+        .delete(format!("{}/{}", dependencies_url(&web), core["id"]))
+        .send()
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::NO_CONTENT);
+    let response = client
+        .delete(format!("{}/{}", dependencies_url(&web), core["id"]))
+        .send()
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+}
+
+#[test]
+fn test_dependencies_of_deleted_crates() {
+    let client = common::get_client_with_logged_in_admin();
+    let rustacean = create_test_rustacean(&client);
+    let rustacean_id = rustacean["id"].as_i64().unwrap() as i32;
+    let live = create_test_crate_with_data(&client, rustacean_id, "live", "DEP_LIVE", "1.0.0");
+    let gone = create_test_crate_with_data(&client, rustacean_id, "gone", "DEP_GONE", "1.0.0");
+    let response = client
+        .delete(format!("{}/{}", CRATES_URL, gone["id"]))
+        .send()
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::NO_CONTENT);
+
+    for (from, to) in [(&live, &gone), (&gone, &live)] {
+        let response = client
+            .post(format!("{}/{}/dependencies", CRATES_URL, from["id"]))
+            .json(&json!({ "dependency_id": to["id"], "requirement": "^1.0" }))
+            .send()
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+    }
+}
@@ -0,0 +1,115 @@
+use reqwest::StatusCode;
+use rocket::serde::json::{serde_json::json, Value};
+
+mod common;
+use common::{create_test_crate_with_data, create_test_rustacean, CRATES_URL};
+
+#[test]
+fn test_crate_versions() {
+    let client = common::get_client_with_logged_in_admin();
+    let rustacean = create_test_rustacean(&client);
+    let rustacean_id = rustacean["id"].as_i64().unwrap() as i32;
+    let a_crate = create_test_crate_with_data(&client, rustacean_id, "tokio", "TOKIO", "1.0.0");
+    let versions_url = format!("{}/{}/versions", CRATES_URL, a_crate["id"]);
+
+    // The initial version is recorded on creation
+    let json: Value = client.get(&versions_url).send().unwrap().json().unwrap();
+    assert_eq!(json["items"].as_array().unwrap().len(), 1);
+    assert_eq!(json["latest"], json!("1.0.0"));
+
+    for version in ["1.2.0", "1.10.0-beta.1"] {
+        let response = client
+            .post(&versions_url)
+            .json(&json!({ "version": version, "changelog": "Changes" }))
+            .send()
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::CREATED);
+    }
+
+    // Stable releases win over newer pre-releases
+    let json: Value = client.get(&versions_url).send().unwrap().json().unwrap();
+    assert_eq!(json["latest"], json!("1.2.0"));
+    assert_eq!(json["items"][0]["version"], json!("1.10.0-beta.1"));
+    let fetched_crate: Value = client
+        .get(format!("{}/{}", CRATES_URL, a_crate["id"]))
+        .send()
+        .unwrap()
+        .json()
+        .unwrap();
+    assert_eq!(fetched_crate["version"], json!("1.2.0"));
+
+    let response = client
+        .post(&versions_url)
+        .json(&json!({ "version": "1.2.0" }))
+        .send()
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::CONFLICT);
+
+    let response = client
+        .post(&versions_url)
+        .json(&json!({ "version": "latest" }))
+        .send()
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::UNPROCESSABLE_ENTITY);
+    let json: Value = response.json().unwrap();
+    assert!(json["fields"]["version"].is_string());
+
+    // Yanked versions are skipped
+    let response = client
+        .put(format!("{}/1.2.0", versions_url))
+        .json(&json!({ "yanked": true }))
+        .send()
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    let json: Value = client.get(&versions_url).send().unwrap().json().unwrap();
+    assert_eq!(json["latest"], json!("1.0.0"));
+
+    let response = client
+        .delete(format!("{}/1.0.0", versions_url))
+        .send()
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::NO_CONTENT);
+    let response = client
+        .get(format!("{}/1.0.0", versions_url))
+        .send()
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+
+    // Updating the crate's version adds it to the history
+    let response = common::merge_patch(
+        &client,
+        &format!("{}/{}", CRATES_URL, a_crate["id"]),
+        &json!({ "version": "2.0.0" }),
+    )
+    .send()
+    .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    let json: Value = client.get(&versions_url).send().unwrap().json().unwrap();
+    assert_eq!(json["latest"], json!("2.0.0"));
+    assert_eq!(json["items"].as_array().unwrap().len(), 3);
+}
+
+#[test]
+fn test_versions_of_deleted_crate() {
+    let client = common::get_client_with_logged_in_admin();
+    let rustacean = create_test_rustacean(&client);
+    let rustacean_id = rustacean["id"].as_i64().unwrap() as i32;
+    let a_crate = create_test_crate_with_data(&client, rustacean_id, "gone", "GONE", "1.0.0");
+    let response = client
+        .delete(format!("{}/{}", CRATES_URL, a_crate["id"]))
+        .send()
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::NO_CONTENT);
+
+    let response = client
+        .post(format!("{}/{}/versions", CRATES_URL, a_crate["id"]))
+        .json(&json!({ "version": "1.1.0" }))
+        .send()
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+    let response = client
+        .get(format!("{}/{}/versions/1.0.0", CRATES_URL, a_crate["id"]))
+        .send()
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+}
@@ -15,8 +15,84 @@ fn test_get_crates() {
     assert_eq!(response.status(), reqwest::StatusCode::OK);
 
     let json: Value = response.json().unwrap();
-    assert!(json.as_array().unwrap().contains(&*crate1));
-    assert!(json.as_array().unwrap().contains(&*crate2));
+    assert!(json["items"].as_array().unwrap().contains(&*crate1));
+    assert!(json["items"].as_array().unwrap().contains(&*crate2));
+}
+
+#[test]
+fn test_get_crates_filtered_and_sorted() {
+    let client = common::get_client_with_logged_in_admin();
+    let rustacean = create_test_rustacean(&client);
+    let rustacean_id = rustacean["id"].as_i64().unwrap() as i32;
+    let crate_a = create_test_crate_with_data(&client, rustacean_id, "alpha", "ALPHA", "1.0.0");
+    let crate_b = create_test_crate_with_data(&client, rustacean_id, "beta", "BETA", "1.0.0");
+
+    let response = client
+        .get(format!(
+            "{}?rustacean_id={}&sort=-name",
+            CRATES_URL, rustacean_id
+        ))
+        .send()
+        .unwrap();
+    assert_eq!(response.status(), reqwest::StatusCode::OK);
+
+    let json: Value = response.json().unwrap();
+    assert_eq!(json["total"], json!(2));
+    assert_eq!(json["items"], json!([*crate_b, *crate_a]));
+
+    let response = client
+        .get(format!(
+            "{}?rustacean_id={}&code=ALPHA",
+            CRATES_URL, rustacean_id
+        ))
+        .send()
+        .unwrap();
+    let json: Value = response.json().unwrap();
+    assert_eq!(json["items"], json!([*crate_a]));
+
+    // Unknown filters, unparsable values and unknown sort fields are rejected
+    for query in ["unknown=1", "rustacean_id=abc", "sort=password"] {
+        let response = client
+            .get(format!("{}?{}", CRATES_URL, query))
+            .send()
+            .unwrap();
+        assert_eq!(response.status(), reqwest::StatusCode::BAD_REQUEST);
+        let json: Value = response.json().unwrap();
+        assert_eq!(json["error"], json!("Invalid query"));
+    }
+}
+
+#[test]
+fn test_search_crates() {
+    let client = common::get_client_with_logged_in_admin();
+    let rustacean = create_test_rustacean(&client);
+    let rustacean_id = rustacean["id"].as_i64().unwrap() as i32;
+    let a_crate = create_test_crate_with_data(
+        &client,
+        rustacean_id,
+        "quokkaparser",
+        "QUOKKAPARSER",
+        "1.0.0",
+    );
+
+    let response = client
+        .get(format!("{}/search?q=quokkaparser", CRATES_URL))
+        .send()
+        .unwrap();
+    assert_eq!(response.status(), reqwest::StatusCode::OK);
+
+    let json: Value = response.json().unwrap();
+    assert_eq!(json["total"], json!(1));
+    let hit = &json["items"][0];
+    assert_eq!(hit["id"], a_crate["id"]);
+    assert!(hit["rank"].as_f64().unwrap() > 0.0);
+    assert_eq!(hit["snippet"], json!("<mark>quokkaparser</mark>"));
+
+    let response = client
+        .get(format!("{}/search?q=", CRATES_URL))
+        .send()
+        .unwrap();
+    assert_eq!(response.status(), reqwest::StatusCode::BAD_REQUEST);
 }
 
 #[test]
@@ -24,7 +100,7 @@ fn test_create_crate() {
     let client = common::get_client_with_logged_in_admin();
     let rustacean = create_test_rustacean(&client);
     let rustacean_id = rustacean["id"].as_i64().unwrap() as i32;
-    let (name, code, version) = ("diesel", "DIESEL", "2.0");
+    let (name, code, version) = ("diesel", "DIESEL", "2.0.0");
 
     let a_crate = create_test_crate_with_data(&client, rustacean_id, name, code, version);
 
@@ -38,11 +114,12 @@ fn test_create_crate() {
             "version": version,
             "description": null,
             "created_at": a_crate["created_at"],
+            "updated_at": a_crate["updated_at"],
         })
     );
 
     // Test creating crate with non-existing rustacean
-    let (name, code, version) = ("another-crate", "ANO", "0.1");
+    let (name, code, version) = ("another-crate", "ANO", "0.1.0");
     let response = client
         .post(CRATES_URL)
         .json(&json!({
@@ -54,6 +131,39 @@ fn test_create_crate() {
         .send()
         .unwrap();
     assert_eq!(response.status(), reqwest::StatusCode::NOT_FOUND);
+
+    // Test creating crate with a code that is already taken, in any case
+    let response = client
+        .post(CRATES_URL)
+        .json(&json!({
+            "rustacean_id": rustacean_id,
+            "name": name,
+            "code": "diesel",
+            "version": version,
+        }))
+        .send()
+        .unwrap();
+    assert_eq!(response.status(), reqwest::StatusCode::CONFLICT);
+    let json: Value = response.json().unwrap();
+    assert_eq!(json["field"], json!("code"));
+
+    // Test creating crate with a version that is not semver
+    let response = client
+        .post(CRATES_URL)
+        .json(&json!({
+            "rustacean_id": rustacean_id,
+            "name": name,
+            "code": code,
+            "version": "v1",
+        }))
+        .send()
+        .unwrap();
+    assert_eq!(response.status(), reqwest::StatusCode::UNPROCESSABLE_ENTITY);
+    let json: Value = response.json().unwrap();
+    assert_eq!(
+        json["fields"]["version"],
+        json!("'v1' is not a valid semantic version, did you mean '1.0.0'?")
+    );
 }
 
 #[test]
@@ -86,42 +196,172 @@ fn test_update_crate() {
     let rustacean = create_test_rustacean(&client);
     let rustacean_id = rustacean["id"].as_i64().unwrap() as i32;
     let a_crate = create_test_crate(&client, rustacean_id);
-    let crate_id = a_crate["id"].clone();
+    let crate_url = format!("{}/{}", CRATES_URL, a_crate["id"]);
 
-    let response = client
-        .put(format!("{}/{}", CRATES_URL, crate_id))
-        .json(&json!({ "description": "An ORM for Rust" }))
+    let response = common::merge_patch(
+        &client,
+        &crate_url,
+        &json!({ "description": "An ORM for Rust" }),
+    )
+    .send()
+    .unwrap();
+    assert_eq!(response.status(), reqwest::StatusCode::OK);
+
+    let updated_crate: Value = response.json().unwrap();
+    assert_eq!(updated_crate["description"], json!("An ORM for Rust"));
+
+    // A null in the patch clears the field
+    let response = common::merge_patch(&client, &crate_url, &json!({ "description": null }))
         .send()
         .unwrap();
     assert_eq!(response.status(), reqwest::StatusCode::OK);
+    let updated_crate: Value = response.json().unwrap();
+    assert_eq!(updated_crate["description"], Value::Null);
+    assert_eq!(updated_crate["code"], a_crate["code"]);
+
+    // PUT replaces the whole crate and rejects partial bodies
+    let response = client
+        .put(&crate_url)
+        .json(&json!({ "description": "Partial" }))
+        .send()
+        .unwrap();
+    assert_eq!(response.status(), reqwest::StatusCode::UNPROCESSABLE_ENTITY);
 
+    let response = client
+        .put(&crate_url)
+        .json(&json!({
+            "rustacean_id": rustacean_id,
+            "code": a_crate["code"],
+            "name": "Diesel",
+            "version": "2.1.0",
+            "description": "A safe, extensible ORM",
+        }))
+        .send()
+        .unwrap();
+    assert_eq!(response.status(), reqwest::StatusCode::OK);
     let updated_crate: Value = response.json().unwrap();
-    assert_eq!(updated_crate["description"], json!("An ORM for Rust"));
+    assert_eq!(updated_crate["name"], json!("Diesel"));
+    assert_eq!(updated_crate["version"], json!("2.1.0"));
+    assert_eq!(
+        updated_crate["description"],
+        json!("A safe, extensible ORM")
+    );
 
-    // Test changing crate owner
-    let another_rustacean = create_test_rustacean(&client);
-    let another_rustacean_id = another_rustacean["id"].as_i64().unwrap() as i32;
+    // The crate points at its latest version, so PUT cannot go back to an older one
     let response = client
-        .put(format!("{}/{}", CRATES_URL, crate_id))
+        .put(&crate_url)
         .json(&json!({
-            "rustacean_id": another_rustacean_id,
+            "rustacean_id": rustacean_id,
+            "code": a_crate["code"],
+            "name": "Diesel",
+            "version": "1.0.0",
+            "description": "A safe, extensible ORM",
         }))
         .send()
         .unwrap();
+    assert_eq!(response.status(), reqwest::StatusCode::UNPROCESSABLE_ENTITY);
+    let json: Value = response.json().unwrap();
+    assert!(json["fields"]["version"].is_string());
+    let response = client.get(&crate_url).send().unwrap();
+    let current: Value = response.json().unwrap();
+    assert_eq!(current["version"], json!("2.1.0"));
+
+    // Test changing crate owner
+    let another_rustacean = create_test_rustacean(&client);
+    let another_rustacean_id = another_rustacean["id"].as_i64().unwrap() as i32;
+    let response = common::merge_patch(
+        &client,
+        &crate_url,
+        &json!({
+            "rustacean_id": another_rustacean_id,
+        }),
+    )
+    .send()
+    .unwrap();
     assert_eq!(response.status(), reqwest::StatusCode::OK);
 
     let updated_crate: Value = response.json().unwrap();
     assert_eq!(updated_crate["rustacean_id"], json!(another_rustacean_id));
 
+    // Test changing crate code to one owned by another crate
+    let another_crate = create_test_crate(&client, rustacean_id);
+    let response = common::merge_patch(
+        &client,
+        &crate_url,
+        &json!({
+            "code": another_crate["code"].as_str().unwrap().to_lowercase(),
+        }),
+    )
+    .send()
+    .unwrap();
+    assert_eq!(response.status(), reqwest::StatusCode::CONFLICT);
+
     // Test changing crate owner to non-existing rustacean
-    let response = client
-        .put(format!("{}/{}", CRATES_URL, crate_id))
-        .json(&json!({
+    let response = common::merge_patch(
+        &client,
+        &crate_url,
+        &json!({
             "rustacean_id": 9999,
-        }))
+        }),
+    )
+    .send()
+    .unwrap();
+    assert_eq!(response.status(), reqwest::StatusCode::NOT_FOUND);
+
+    // Test changing crate version to something that is not semver
+    let response = common::merge_patch(
+        &client,
+        &crate_url,
+        &json!({
+            "version": "latest",
+        }),
+    )
+    .send()
+    .unwrap();
+    assert_eq!(response.status(), reqwest::StatusCode::UNPROCESSABLE_ENTITY);
+}
+
+#[test]
+fn test_update_crate_if_match() {
+    let client = common::get_client_with_logged_in_admin();
+    let rustacean = create_test_rustacean(&client);
+    let rustacean_id = rustacean["id"].as_i64().unwrap() as i32;
+    let a_crate = create_test_crate(&client, rustacean_id);
+    let crate_url = format!("{}/{}", CRATES_URL, a_crate["id"]);
+
+    let response = client.get(&crate_url).send().unwrap();
+    let etag = response.headers()["ETag"].to_str().unwrap().to_string();
+
+    let response = common::merge_patch(&client, &crate_url, &json!({ "description": "First" }))
+        .header("If-Match", &etag)
         .send()
         .unwrap();
-    assert_eq!(response.status(), reqwest::StatusCode::NOT_FOUND);
+    assert_eq!(response.status(), reqwest::StatusCode::OK);
+    let new_etag = response.headers()["ETag"].to_str().unwrap().to_string();
+    assert_ne!(new_etag, etag);
+
+    // A concurrent editor still holding the old ETag loses
+    let response = common::merge_patch(&client, &crate_url, &json!({ "description": "Second" }))
+        .header("If-Match", &etag)
+        .send()
+        .unwrap();
+    assert_eq!(response.status(), reqwest::StatusCode::PRECONDITION_FAILED);
+    let response = client
+        .delete(&crate_url)
+        .header("If-Match", &etag)
+        .send()
+        .unwrap();
+    assert_eq!(response.status(), reqwest::StatusCode::PRECONDITION_FAILED);
+
+    let fetched_crate: Value = client.get(&crate_url).send().unwrap().json().unwrap();
+    assert_eq!(fetched_crate["description"], json!("First"));
+
+    let response = client
+        .delete(&crate_url)
+        .header("If-Match", &new_etag)
+        .send()
+        .unwrap();
+    assert_eq!(response.status(), reqwest::StatusCode::NO_CONTENT);
 }
 
 #[test]
@@ -137,4 +377,134 @@ fn test_delete_crate() {
         .send()
         .unwrap();
     assert_eq!(response.status(), reqwest::StatusCode::NO_CONTENT);
+
+    // Deleted crates are hidden until restored
+    let response = client
+        .get(format!("{}/{}", CRATES_URL, crate_id))
+        .send()
+        .unwrap();
+    assert_eq!(response.status(), reqwest::StatusCode::NOT_FOUND);
+    let response = client
+        .get(format!("{}?rustacean_id={}", CRATES_URL, rustacean_id))
+        .send()
+        .unwrap();
+    let json: Value = response.json().unwrap();
+    assert_eq!(json["total"], json!(0));
+
+    let response = client
+        .post(format!("{}/{}/restore", CRATES_URL, crate_id))
+        .send()
+        .unwrap();
+    assert_eq!(response.status(), reqwest::StatusCode::OK);
+    let restored_crate: Value = response.json().unwrap();
+    assert_eq!(restored_crate["id"], a_crate["id"]);
+    assert_eq!(restored_crate["code"], a_crate["code"]);
+
+    let response = client
+        .post(format!("{}/{}/restore", CRATES_URL, crate_id))
+        .send()
+        .unwrap();
+    assert_eq!(response.status(), reqwest::StatusCode::NOT_FOUND);
+}
+
+#[test]
+fn test_bulk_crates() {
+    let client = common::get_client_with_logged_in_admin();
+    let rustacean = create_test_rustacean(&client);
+    let rustacean_id = rustacean["id"].as_i64().unwrap() as i32;
+    let taken = create_test_crate(&client, rustacean_id);
+    let new_crate = |code: &str| {
+        json!({
+            "rustacean_id": rustacean_id,
+            "name": "bulk",
+            "code": code,
+            "version": "1.0.0",
+        })
+    };
+    let codes = [common::unique_value("BULK"), common::unique_value("BULK")];
+    let items = json!([
+        new_crate(&codes[0]),
+        new_crate(taken["code"].as_str().unwrap()),
+        new_crate(&codes[1]),
+    ]);
+    let count_created = || {
+        let json: Value = client
+            .get(format!("{}?rustacean_id={}", CRATES_URL, rustacean_id))
+            .send()
+            .unwrap()
+            .json()
+            .unwrap();
+        json["total"].as_i64().unwrap()
+    };
+
+    // All-or-nothing is the default and keeps none of the items
+    let response = client
+        .post(format!("{}/bulk", CRATES_URL))
+        .json(&items)
+        .send()
+        .unwrap();
+    assert_eq!(response.status(), reqwest::StatusCode::UNPROCESSABLE_ENTITY);
+    let json: Value = response.json().unwrap();
+    assert_eq!(json["committed"], json!(false));
+    let statuses: Vec<_> = json["results"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|result| result["status"].clone())
+        .collect();
+    assert_eq!(statuses, [json!(424), json!(409), json!(424)]);
+    assert_eq!(json["results"][1]["error"]["field"], json!("code"));
+    assert_eq!(count_created(), 1);
+
+    let response = client
+        .post(format!("{}/bulk?mode=best-effort", CRATES_URL))
+        .json(&items)
+        .send()
+        .unwrap();
+    assert_eq!(response.status(), reqwest::StatusCode::MULTI_STATUS);
+    let json: Value = response.json().unwrap();
+    assert_eq!(json["committed"], json!(true));
+    assert_eq!(json["results"][0]["status"], json!(201));
+    assert_eq!(json["results"][1]["status"], json!(409));
+    assert_eq!(json["results"][2]["item"]["code"], json!(codes[1]));
+    assert_eq!(count_created(), 3);
+    let ids = [
+        json["results"][0]["item"]["id"].clone(),
+        json["results"][2]["item"]["id"].clone(),
+    ];
+
+    let response = client
+        .patch(format!("{}/bulk?mode=best-effort", CRATES_URL))
+        .json(&json!([
+            { "id": ids[0], "patch": { "description": "Bulk patched" } },
+            { "id": ids[1], "patch": { "version": "latest" } },
+            { "id": 999999, "patch": {} },
+        ]))
+        .send()
+        .unwrap();
+    assert_eq!(response.status(), reqwest::StatusCode::MULTI_STATUS);
+    let json: Value = response.json().unwrap();
+    assert_eq!(
+        json["results"][0]["item"]["description"],
+        json!("Bulk patched")
+    );
+    assert_eq!(json["results"][1]["status"], json!(422));
+    assert_eq!(json["results"][2]["status"], json!(404));
+
+    let response = client
+        .post(format!("{}/bulk/delete?mode=everything", CRATES_URL))
+        .json(&ids)
+        .send()
+        .unwrap();
+    assert_eq!(response.status(), reqwest::StatusCode::BAD_REQUEST);
+
+    let response = client
+        .post(format!("{}/bulk/delete", CRATES_URL))
+        .json(&ids)
+        .send()
+        .unwrap();
+    assert_eq!(response.status(), reqwest::StatusCode::OK);
+    let json: Value = response.json().unwrap();
+    assert_eq!(json["results"][1]["status"], json!(204));
+    assert_eq!(count_created(), 1);
 }
@@ -0,0 +1,156 @@
+use reqwest::{blocking::Client, StatusCode};
+use rocket::serde::json::{serde_json, serde_json::json, Value};
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+pub mod common;
+
+/// Runs a `crates` subcommand of the CLI and returns what it printed.
+fn run_crates_command(args: &[&str]) -> String {
+    let output = Command::new("cargo")
+        .args(["run", "--bin", "cli", "crates"])
+        .args(args)
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+    String::from_utf8(output.stdout).unwrap()
+}
+
+/// Writes an import file into the temporary directory and returns its path.
+fn write_import_file(extension: &str, contents: &str) -> PathBuf {
+    let path = std::env::temp_dir().join(format!(
+        "{}.{}",
+        common::unique_value("crates_import"),
+        extension
+    ));
+    std::fs::write(&path, contents).unwrap();
+    path
+}
+
+fn import(path: &Path) -> String {
+    run_crates_command(&["import", path.to_str().unwrap()])
+}
+
+fn find_crate_by_code(client: &Client, code: &str) -> Value {
+    let response = client
+        .get(common::CRATES_URL)
+        .query(&[("code", code)])
+        .send()
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    let page: Value = response.json().unwrap();
+    page["items"][0].clone()
+}
+
+#[test]
+fn test_crates_csv_round_trip() {
+    let client = common::get_client_with_logged_in_admin();
+    let rustacean = common::create_test_rustacean(&client);
+    let rustacean_id = rustacean["id"].as_i64().unwrap() as i32;
+    let a_crate = common::create_test_crate(&client, rustacean_id);
+    let code = a_crate["code"].as_str().unwrap();
+
+    // Crates are exported with their owner's email rather than the owner's id
+    let export = run_crates_command(&["export", "--format", "csv"]);
+    let mut lines = export.lines();
+    let header = lines.next().unwrap();
+    assert_eq!(header, "owner_email,code,name,version,description");
+    let row = lines.find(|line| line.contains(code)).unwrap();
+    assert!(row.starts_with(rustacean["email"].as_str().unwrap()));
+
+    // Importing the export again changes nothing
+    let path = write_import_file("csv", &format!("{}\n{}\n", header, row));
+    assert!(import(&path).contains("Inserted 0, updated 0 and skipped 1 row(s)"));
+
+    // A changed field updates the crate
+    let renamed = row.replace(",serde,", ",serde_renamed,");
+    std::fs::write(&path, format!("{}\n{}\n", header, renamed)).unwrap();
+    assert!(import(&path).contains("Inserted 0, updated 1 and skipped 0 row(s)"));
+    assert_eq!(
+        find_crate_by_code(&client, code)["name"],
+        json!("serde_renamed")
+    );
+
+    // An older version is added to the history once, and the crate stays at its latest
+    let older = renamed.replace(",1.0.0,", ",0.9.0,");
+    std::fs::write(&path, format!("{}\n{}\n", header, older)).unwrap();
+    assert!(import(&path).contains("Inserted 0, updated 1 and skipped 0 row(s)"));
+    assert!(import(&path).contains("Inserted 0, updated 0 and skipped 1 row(s)"));
+    assert_eq!(find_crate_by_code(&client, code)["version"], json!("1.0.0"));
+    let response = client
+        .get(format!("{}/{}/versions", common::CRATES_URL, a_crate["id"]))
+        .send()
+        .unwrap();
+    let versions: Value = response.json().unwrap();
+    assert_eq!(versions["items"].as_array().unwrap().len(), 2);
+    assert_eq!(versions["latest"], json!("1.0.0"));
+
+    std::fs::remove_file(path).unwrap();
+}
+
+#[test]
+fn test_crates_jsonl_import() {
+    let client = common::get_client_with_logged_in_admin();
+    let rustacean = common::create_test_rustacean(&client);
+    let rustacean_id = rustacean["id"].as_i64().unwrap() as i32;
+    let code = common::unique_value("IMPORTED");
+
+    let rows = [
+        json!({
+            "owner_email": rustacean["email"],
+            "code": code,
+            "name": "imported",
+            "version": "0.1.0",
+            "description": null,
+        })
+        .to_string(),
+        // A row owned by nobody known is skipped
+        json!({
+            "owner_email": "nobody@example.com",
+            "code": common::unique_value("ORPHAN"),
+            "name": "orphan",
+            "version": "0.1.0",
+            "description": null,
+        })
+        .to_string(),
+        // So is a row that is not valid
+        json!({
+            "owner_email": rustacean["email"],
+            "code": common::unique_value("INVALID"),
+            "name": "invalid",
+            "version": "latest",
+            "description": null,
+        })
+        .to_string(),
+        "not json".to_string(),
+    ];
+    let path = write_import_file("jsonl", &format!("{}\n", rows.join("\n")));
+
+    // A dry run reports the counts without saving anything
+    let output = run_crates_command(&["import", path.to_str().unwrap(), "--dry-run"]);
+    assert!(output.contains("Dry run, nothing saved. Inserted 1, updated 0 and skipped 3 row(s)"));
+    assert_eq!(find_crate_by_code(&client, &code), Value::Null);
+
+    let output = import(&path);
+    assert!(output.contains("Row 2: skipped, no rustacean with email 'nobody@example.com'"));
+    assert!(output.contains("Inserted 1, updated 0 and skipped 3 row(s)"));
+    let imported = find_crate_by_code(&client, &code);
+    assert_eq!(imported["rustacean_id"], json!(rustacean_id));
+    assert_eq!(imported["version"], json!("0.1.0"));
+
+    // The export lists the imported crate as it was imported
+    let export = run_crates_command(&["export", "--format", "jsonl"]);
+    let exported: Value = export
+        .lines()
+        .map(|line| serde_json::from_str::<Value>(line).unwrap())
+        .find(|row| row["code"] == json!(code))
+        .unwrap();
+    assert_eq!(exported, serde_json::from_str::<Value>(&rows[0]).unwrap());
+
+    let response = client
+        .delete(format!("{}/{}", common::CRATES_URL, imported["id"]))
+        .send()
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::NO_CONTENT);
+    std::fs::remove_file(path).unwrap();
+}
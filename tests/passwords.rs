@@ -0,0 +1,138 @@
+use reqwest::{blocking::Client, StatusCode};
+use rocket::serde::json::{serde_json::json, Value};
+
+mod common;
+use common::{SERVER_URL, TEST_PASSWORD, USERS_URL};
+
+#[test]
+fn test_password_policy() {
+    let client = common::get_client_with_logged_in_admin();
+    let username = common::unique_value("policy_user");
+
+    for password in ["Ab1", "lowercaseonly", "Password1", &username] {
+        let response = client
+            .post(USERS_URL)
+            .json(&json!({ "username": username, "password": password, "roles": ["viewer"] }))
+            .send()
+            .unwrap();
+        assert_eq!(
+            response.status(),
+            StatusCode::UNPROCESSABLE_ENTITY,
+            "Expected {} to be refused",
+            password
+        );
+        let json: Value = response.json().unwrap();
+        assert!(json["fields"]["password"].is_string());
+    }
+}
+
+#[test]
+fn test_change_password() {
+    let username = common::unique_value("password_user");
+    let client = common::get_client_for_user(&username, common::TEST_VIEWER_ROLE);
+    let password_url = format!("{}/me/password", SERVER_URL);
+    let login = |password: &str| {
+        Client::new()
+            .post(format!("{}/login", SERVER_URL))
+            .json(&json!({ "username": username, "password": password }))
+            .send()
+            .unwrap()
+            .status()
+    };
+
+    let response = client
+        .post(&password_url)
+        .json(&json!({ "current_password": "wrong", "new_password": "New password 1" }))
+        .send()
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::UNPROCESSABLE_ENTITY);
+    let json: Value = response.json().unwrap();
+    assert!(json["fields"]["current_password"].is_string());
+
+    let response = client
+        .post(&password_url)
+        .json(&json!({ "current_password": TEST_PASSWORD, "new_password": "short" }))
+        .send()
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::UNPROCESSABLE_ENTITY);
+    let json: Value = response.json().unwrap();
+    assert!(json["fields"]["new_password"].is_string());
+
+    let other_session = common::get_user_token(&Client::new(), &username);
+    let sessions_with = |client: &Client, token: Option<&str>| {
+        let request = client.get(format!("{}/me/sessions", SERVER_URL));
+        match token {
+            Some(token) => request.bearer_auth(token),
+            None => request,
+        }
+        .send()
+        .unwrap()
+        .status()
+    };
+    assert_eq!(
+        sessions_with(&Client::new(), Some(&other_session)),
+        StatusCode::OK
+    );
+
+    let response = client
+        .post(&password_url)
+        .json(&json!({ "current_password": TEST_PASSWORD, "new_password": "New password 1" }))
+        .send()
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::NO_CONTENT);
+
+    // Other sessions end, the one the password was changed in goes on
+    assert_eq!(
+        sessions_with(&Client::new(), Some(&other_session)),
+        StatusCode::UNAUTHORIZED
+    );
+    assert_eq!(sessions_with(&client, None), StatusCode::OK);
+    assert_eq!(login(TEST_PASSWORD), StatusCode::UNAUTHORIZED);
+    assert_eq!(login("New password 1"), StatusCode::OK);
+}
+
+#[test]
+fn test_password_reset() {
+    let client = Client::new();
+
+    // Unknown addresses get the same answer as known ones
+    let response = client
+        .post(format!("{}/password/reset", SERVER_URL))
+        .json(&json!({ "email": format!("{}@nowhere.com", common::unique_value("nobody")) }))
+        .send()
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::ACCEPTED);
+
+    let response = client
+        .post(format!("{}/password/reset/confirm", SERVER_URL))
+        .json(&json!({ "token": "unknown", "password": "New password 1" }))
+        .send()
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+}
+
+#[test]
+fn test_password_reset_throttling() {
+    let client = Client::new();
+    let email = format!("{}@nowhere.com", common::unique_value("flooded"));
+    let request_reset = |email: &str| {
+        client
+            .post(format!("{}/password/reset", SERVER_URL))
+            .json(&json!({ "email": email }))
+            .send()
+            .unwrap()
+    };
+
+    // The request reaching the backoff threshold of three makes further ones wait
+    for _ in 0..3 {
+        assert_eq!(request_reset(&email).status(), StatusCode::ACCEPTED);
+    }
+    let response = request_reset(&email.to_uppercase());
+    assert_eq!(response.status(), StatusCode::TOO_MANY_REQUESTS);
+    let retry_after: u64 = response.headers()["Retry-After"]
+        .to_str()
+        .unwrap()
+        .parse()
+        .unwrap();
+    assert!(retry_after >= 1);
+}
@@ -1,7 +1,10 @@
 use rocket::serde::json::serde_json::json;
 
 mod common;
-use common::{create_test_rustacean, create_test_rustacean_with_data, RUSTACEANS_URL};
+use common::{
+    create_test_crate, create_test_rustacean, create_test_rustacean_with_data, CRATES_URL,
+    RUSTACEANS_URL,
+};
 
 #[test]
 fn test_get_rustaceans() {
@@ -13,8 +16,59 @@ fn test_get_rustaceans() {
     assert_eq!(response.status(), reqwest::StatusCode::OK);
 
     let json: rocket::serde::json::Value = response.json().unwrap();
-    assert!(json.as_array().unwrap().contains(&*rustacean1));
-    assert!(json.as_array().unwrap().contains(&*rustacean2));
+    assert!(json["items"].as_array().unwrap().contains(&*rustacean1));
+    assert!(json["items"].as_array().unwrap().contains(&*rustacean2));
+}
+
+#[test]
+fn test_get_rustaceans_paginated() {
+    let client = common::get_client_with_logged_in_admin();
+    let _rustacean1 = create_test_rustacean(&client);
+    let _rustacean2 = create_test_rustacean(&client);
+    let _rustacean3 = create_test_rustacean(&client);
+
+    let response = client
+        .get(format!("{}?limit=2", RUSTACEANS_URL))
+        .send()
+        .unwrap();
+    assert_eq!(response.status(), reqwest::StatusCode::OK);
+
+    let first_page: rocket::serde::json::Value = response.json().unwrap();
+    assert_eq!(first_page["items"].as_array().unwrap().len(), 2);
+    assert!(first_page["total"].as_i64().unwrap() >= 3);
+    let cursor = first_page["next_cursor"].as_str().unwrap();
+
+    let response = client
+        .get(format!("{}?limit=2&cursor={}", RUSTACEANS_URL, cursor))
+        .send()
+        .unwrap();
+    assert_eq!(response.status(), reqwest::StatusCode::OK);
+
+    let second_page: rocket::serde::json::Value = response.json().unwrap();
+    let last_on_first = first_page["items"][1]["id"].as_i64().unwrap();
+    let first_on_second = second_page["items"][0]["id"].as_i64().unwrap();
+    assert!(first_on_second < last_on_first);
+
+    let response = client
+        .get(format!("{}?offset=1&cursor={}", RUSTACEANS_URL, cursor))
+        .send()
+        .unwrap();
+    assert_eq!(response.status(), reqwest::StatusCode::BAD_REQUEST);
+
+    let response = client
+        .get(format!("{}?cursor=garbage", RUSTACEANS_URL))
+        .send()
+        .unwrap();
+    assert_eq!(response.status(), reqwest::StatusCode::BAD_REQUEST);
+    for (field, query) in [("limit", "limit=abc"), ("offset", "offset=1.5")] {
+        let response = client
+            .get(format!("{}?{}", RUSTACEANS_URL, query))
+            .send()
+            .unwrap();
+        assert_eq!(response.status(), reqwest::StatusCode::BAD_REQUEST);
+        let json: rocket::serde::json::Value = response.json().unwrap();
+        assert_eq!(json["field"], field);
+    }
 }
 
 #[test]
@@ -39,8 +93,27 @@ fn test_create_rustacean() {
             "name": name,
             "email": email,
             "created_at": rustacean_value["created_at"],
+            "updated_at": rustacean_value["updated_at"],
+            "user_id": null,
         })
     );
+
+    // Emails are unique regardless of case
+    let response = create_test_rustacean_with_data(&client, name, &email.to_uppercase());
+    assert_eq!(response.status(), reqwest::StatusCode::CONFLICT);
+    let json: rocket::serde::json::Value = response.json().unwrap();
+    assert_eq!(json["field"], json!("email"));
+
+    for invalid_email in [
+        "john",
+        "john@",
+        "@smith.com",
+        "john@smith",
+        "john smith@smith.com",
+    ] {
+        let response = create_test_rustacean_with_data(&client, name, invalid_email);
+        assert_eq!(response.status(), reqwest::StatusCode::UNPROCESSABLE_ENTITY);
+    }
 }
 
 #[test]
@@ -99,8 +172,21 @@ fn test_update_rustacean() {
             "name": "Jane Doe",
             "email": "jane@doe.com",
             "created_at": rustacean["created_at"],
+            "updated_at": updated_rustacean["updated_at"],
+            "user_id": null,
         })
     );
+
+    let response = common::merge_patch(
+        &client,
+        &format!("{}/{}", RUSTACEANS_URL, rustacean_id),
+        &json!({ "email": "jane.doe.com" }),
+    )
+    .send()
+    .unwrap();
+    assert_eq!(response.status(), reqwest::StatusCode::UNPROCESSABLE_ENTITY);
+    let json: rocket::serde::json::Value = response.json().unwrap();
+    assert!(json["fields"]["email"].is_string());
 }
 
 #[test]
@@ -114,4 +200,126 @@ fn test_delete_rustacean() {
         .send()
         .unwrap();
     assert_eq!(response.status(), reqwest::StatusCode::NO_CONTENT);
+
+    let response = client
+        .get(format!("{}/{}", RUSTACEANS_URL, rustacean_id))
+        .send()
+        .unwrap();
+    assert_eq!(response.status(), reqwest::StatusCode::NOT_FOUND);
+
+    // Viewers may not restore
+    let viewer_client = common::get_client_with_logged_in_viewer();
+    let response = viewer_client
+        .post(format!("{}/{}/restore", RUSTACEANS_URL, rustacean_id))
+        .send()
+        .unwrap();
+    assert_eq!(response.status(), reqwest::StatusCode::FORBIDDEN);
+
+    let response = client
+        .post(format!("{}/{}/restore", RUSTACEANS_URL, rustacean_id))
+        .send()
+        .unwrap();
+    assert_eq!(response.status(), reqwest::StatusCode::OK);
+    let restored_rustacean: rocket::serde::json::Value = response.json().unwrap();
+    assert_eq!(restored_rustacean["id"], rustacean["id"]);
+    assert_eq!(restored_rustacean["email"], rustacean["email"]);
+}
+
+#[test]
+fn test_delete_rustacean_owning_crates() {
+    let client = common::get_client_with_logged_in_admin();
+    let rustacean = create_test_rustacean(&client);
+    let rustacean_id = rustacean["id"].as_i64().unwrap() as i32;
+    let heir = create_test_rustacean(&client);
+    let a_crate = create_test_crate(&client, rustacean_id);
+
+    let response = client
+        .delete(format!("{}/{}", RUSTACEANS_URL, rustacean_id))
+        .send()
+        .unwrap();
+    assert_eq!(response.status(), reqwest::StatusCode::CONFLICT);
+    let json: rocket::serde::json::Value = response.json().unwrap();
+    assert_eq!(json["crates"], json!([*a_crate]));
+
+    let response = client
+        .delete(format!(
+            "{}/{}?cascade=true&reassign_to={}",
+            RUSTACEANS_URL, rustacean_id, heir["id"]
+        ))
+        .send()
+        .unwrap();
+    assert_eq!(response.status(), reqwest::StatusCode::BAD_REQUEST);
+
+    // Reassigning hands the crates over before deleting
+    let response = client
+        .delete(format!(
+            "{}/{}?reassign_to={}",
+            RUSTACEANS_URL, rustacean_id, heir["id"]
+        ))
+        .send()
+        .unwrap();
+    assert_eq!(response.status(), reqwest::StatusCode::NO_CONTENT);
+    let fetched_crate: rocket::serde::json::Value = client
+        .get(format!("{}/{}", CRATES_URL, a_crate["id"]))
+        .send()
+        .unwrap()
+        .json()
+        .unwrap();
+    assert_eq!(fetched_crate["rustacean_id"], heir["id"]);
+
+    // Cascading deletes the crates along with their owner
+    let response = client
+        .delete(format!("{}/{}?cascade=true", RUSTACEANS_URL, heir["id"]))
+        .send()
+        .unwrap();
+    assert_eq!(response.status(), reqwest::StatusCode::NO_CONTENT);
+    let response = client
+        .get(format!("{}/{}", CRATES_URL, a_crate["id"]))
+        .send()
+        .unwrap();
+    assert_eq!(response.status(), reqwest::StatusCode::NOT_FOUND);
+}
+
+#[test]
+fn test_bulk_rustaceans() {
+    let client = common::get_client_with_logged_in_admin();
+    let owner = create_test_rustacean(&client);
+    let _owned_crate = create_test_crate(&client, owner["id"].as_i64().unwrap() as i32);
+
+    let email = format!("{}@doe.com", common::unique_value("bulk"));
+    let response = client
+        .post(format!("{}/bulk?mode=best-effort", RUSTACEANS_URL))
+        .json(&json!([
+            { "name": "Bulk Doe", "email": email },
+            { "name": "Bulk Doe", "email": "bulk.doe.com" },
+        ]))
+        .send()
+        .unwrap();
+    assert_eq!(response.status(), reqwest::StatusCode::MULTI_STATUS);
+    let json: rocket::serde::json::Value = response.json().unwrap();
+    assert_eq!(json["results"][0]["item"]["email"], json!(email));
+    assert!(json["results"][1]["error"]["fields"]["email"].is_string());
+    let created_id = json["results"][0]["item"]["id"].clone();
+
+    // Rustaceans owning crates are not deleted in bulk
+    let response = client
+        .post(format!("{}/bulk/delete?mode=best-effort", RUSTACEANS_URL))
+        .json(&json!([created_id, owner["id"]]))
+        .send()
+        .unwrap();
+    assert_eq!(response.status(), reqwest::StatusCode::MULTI_STATUS);
+    let json: rocket::serde::json::Value = response.json().unwrap();
+    assert_eq!(json["results"][0]["status"], json!(204));
+    assert_eq!(json["results"][1]["status"], json!(409));
+
+    let response = client
+        .get(format!("{}/{}", RUSTACEANS_URL, created_id))
+        .send()
+        .unwrap();
+    assert_eq!(response.status(), reqwest::StatusCode::NOT_FOUND);
+    let response = client
+        .get(format!("{}/{}", RUSTACEANS_URL, owner["id"]))
+        .send()
+        .unwrap();
+    assert_eq!(response.status(), reqwest::StatusCode::OK);
 }
@@ -0,0 +1,155 @@
+use reqwest::{blocking::Client, StatusCode};
+use rocket::serde::json::{serde_json::json, Value};
+
+mod common;
+use common::{TEST_ADMIN_USERNAME, USERS_URL};
+
+#[test]
+fn test_manage_users() {
+    let client = common::get_client_with_logged_in_admin();
+    let username = common::unique_value("user");
+
+    let response = client
+        .post(USERS_URL)
+        .json(&json!({ "username": username, "password": "Secret123", "roles": ["viewer"] }))
+        .send()
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::CREATED);
+    let user: Value = response.json().unwrap();
+    assert_eq!(user["username"], json!(username));
+    assert_eq!(user["roles"], json!(["viewer"]));
+    assert!(user.get("password").is_none());
+    let user_url = format!("{}/{}", USERS_URL, user["id"]);
+
+    let response = client
+        .post(USERS_URL)
+        .json(&json!({ "username": username, "password": "Secret123", "roles": ["viewer"] }))
+        .send()
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::CONFLICT);
+    let json: Value = response.json().unwrap();
+    assert_eq!(json["field"], json!("username"));
+
+    let response = client
+        .post(USERS_URL)
+        .json(&json!({ "username": "", "password": "", "roles": [] }))
+        .send()
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::UNPROCESSABLE_ENTITY);
+    let json: Value = response.json().unwrap();
+    assert!(json["fields"]["username"].is_string());
+    assert!(json["fields"]["password"].is_string());
+    assert!(json["fields"]["roles"].is_string());
+
+    let response = client
+        .put(format!("{}/roles", user_url))
+        .json(&json!({ "roles": ["editor", "viewer"] }))
+        .send()
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+
+    // Taking roles away or deleting the user signs them out
+    let login = || {
+        let json: Value = Client::new()
+            .post(format!("{}/login", common::SERVER_URL))
+            .json(&json!({ "username": username, "password": "Secret123" }))
+            .send()
+            .unwrap()
+            .json()
+            .unwrap();
+        json["token"].as_str().unwrap().to_string()
+    };
+    let status_for = |token: &str| {
+        Client::new()
+            .get(common::RUSTACEANS_URL)
+            .bearer_auth(token)
+            .send()
+            .unwrap()
+            .status()
+    };
+    let token = login();
+    assert_eq!(status_for(&token), StatusCode::OK);
+
+    let users: Value = client.get(USERS_URL).send().unwrap().json().unwrap();
+    let listed = users
+        .as_array()
+        .unwrap()
+        .iter()
+        .find(|listed| listed["id"] == user["id"])
+        .unwrap();
+    let mut roles: Vec<_> = listed["roles"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|role| role.as_str().unwrap())
+        .collect();
+    roles.sort();
+    assert_eq!(roles, ["editor", "viewer"]);
+    assert!(users
+        .as_array()
+        .unwrap()
+        .iter()
+        .all(|listed| listed.get("password").is_none()));
+
+    let response = client
+        .put(format!("{}/roles/editor", user_url))
+        .send()
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    let response = client
+        .delete(format!("{}/roles/viewer", user_url))
+        .send()
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    let json: Value = response.json().unwrap();
+    assert_eq!(json["roles"], json!(["editor"]));
+    assert_eq!(status_for(&token), StatusCode::UNAUTHORIZED);
+    let token = login();
+    let response = client
+        .put(format!("{}/roles/superuser", user_url))
+        .send()
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+    let response = client
+        .put(format!("{}/999999/roles/editor", USERS_URL))
+        .send()
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+
+    // Admins cannot lock themselves out
+    let admin = users
+        .as_array()
+        .unwrap()
+        .iter()
+        .find(|listed| listed["username"] == json!(TEST_ADMIN_USERNAME))
+        .unwrap();
+    let admin_url = format!("{}/{}", USERS_URL, admin["id"]);
+    let response = client
+        .put(format!("{}/roles", admin_url))
+        .json(&json!({ "roles": ["viewer"] }))
+        .send()
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::CONFLICT);
+    let response = client
+        .delete(format!("{}/roles/admin", admin_url))
+        .send()
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::CONFLICT);
+    let response = client.delete(&admin_url).send().unwrap();
+    assert_eq!(response.status(), StatusCode::CONFLICT);
+
+    let response = client.delete(&user_url).send().unwrap();
+    assert_eq!(response.status(), StatusCode::NO_CONTENT);
+    assert_eq!(status_for(&token), StatusCode::UNAUTHORIZED);
+    let response = client.get(&user_url).send().unwrap();
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+    let response = client.delete(&user_url).send().unwrap();
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+}
+
+#[test]
+fn test_users_require_admin() {
+    let client = common::get_client_with_logged_in_viewer();
+    let response = client.get(USERS_URL).send().unwrap();
+    assert_eq!(response.status(), StatusCode::FORBIDDEN);
+}